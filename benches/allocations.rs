@@ -0,0 +1,84 @@
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    collections::BTreeMap,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use serde::{Deserialize, Serialize};
+use tempfile::TempDir;
+
+use bjw_db::SyncPolicy;
+use bjw_db_derive::derive_bjw_db;
+
+/// Counts all allocations, to see how many an update needs.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+struct Counters {
+    counters: BTreeMap<u8, u64>,
+}
+
+#[derive_bjw_db]
+impl Counters {
+    pub fn add(&mut self, counter: u8, amount: u64) {
+        *self.counters.entry(counter).or_default() += amount;
+    }
+
+    pub fn get(&self, counter: &u8) -> u64 {
+        self.counters.get(counter).copied().unwrap_or_default()
+    }
+}
+
+const N: u64 = 100_000;
+
+fn bench_update_allocations(c: &mut Criterion) {
+    let tempdir = TempDir::with_prefix("bjw-bench-").unwrap();
+    let mut db = Database::builder(JsonFormat::<Counters>::new())
+        .sync_policy(SyncPolicy::Never)
+        .open(tempdir.path().join("counters"))
+        .unwrap();
+    let updates = |db: &mut Database<Counters, JsonFormat<Counters>>| {
+        for i in 0..N {
+            db.update(&CountersUpdateParams::Add((i % 8) as u8, i))
+                .unwrap();
+        }
+    };
+
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    updates(&mut db);
+    println!(
+        "allocations for {N} updates: {}",
+        ALLOCATIONS.load(Ordering::Relaxed) - before
+    );
+    c.bench_function("update-allocations", |b| b.iter(|| updates(&mut db)));
+}
+
+criterion_group! {
+    name = allocations;
+    config = Criterion::default().sample_size(10).warm_up_time(Duration::from_secs(1));
+    targets = bench_update_allocations
+}
+criterion_main!(allocations);
@@ -0,0 +1,60 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use serde::{Deserialize, Serialize};
+use tempfile::TempDir;
+
+use bjw_db::SyncPolicy;
+use bjw_db_derive::derive_bjw_db;
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+struct Events {
+    events: BTreeMap<u64, String>,
+}
+
+#[derive_bjw_db]
+impl Events {
+    pub fn record(&mut self, id: u64, event: String) {
+        self.events.insert(id, event);
+    }
+
+    pub fn get(&self, id: &u64) -> Option<String> {
+        self.events.get(id).cloned()
+    }
+}
+
+const N: u64 = 100_000;
+
+fn bench_ingest(c: &mut Criterion) {
+    let mut group = c.benchmark_group("ingest");
+    for append_only in [false, true] {
+        let tempdir = TempDir::with_prefix("bjw-bench-").unwrap();
+        let builder = Database::builder(JsonFormat::<Events>::new()).sync_policy(SyncPolicy::Never);
+        let path = tempdir.path().join("events");
+        let mut append: Box<dyn FnMut(&EventsUpdateParams)> = if append_only {
+            let mut db = builder.open_append_only(path).unwrap();
+            Box::new(move |params| db.append(params).unwrap())
+        } else {
+            let mut db = builder.open(path).unwrap();
+            Box::new(move |params| db.append(params).unwrap())
+        };
+        let mut id = 0;
+        let name = if append_only { "append-only" } else { "update" };
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                for _ in 0..N {
+                    append(&EventsUpdateParams::Record(id, "static event".to_string()));
+                    id += 1;
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group! {
+    name = append_only;
+    config = Criterion::default().sample_size(10).warm_up_time(Duration::from_secs(1));
+    targets = bench_ingest
+}
+criterion_main!(append_only);
@@ -1,9 +1,18 @@
-use std::{collections::BTreeMap, time::Duration};
+use std::{
+    collections::BTreeMap,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock,
+    },
+    time::Duration,
+};
 
 use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
 use serde::{Deserialize, Serialize};
 use tempfile::TempDir;
 
+use bjw_db::{Backend, FsBackend, SharedDatabase, SyncPolicy};
 use bjw_db_derive::derive_bjw_db;
 
 #[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
@@ -57,9 +66,185 @@ fn bench_checkpoint(c: &mut Criterion) {
     }
 }
 
+fn bench_get(c: &mut Criterion) {
+    let n = 10_000;
+    let (db, _tempdir) = create_and_insert(n);
+    c.bench_function("get", |b| {
+        b.iter(|| {
+            for i in 0..n {
+                criterion::black_box(db.get(&i));
+            }
+        })
+    });
+}
+
+fn bench_sync_policy(c: &mut Criterion) {
+    let mut group = c.benchmark_group("sync-policy");
+    let n = 10_000;
+    let policies = [
+        ("every-write", SyncPolicy::EveryWrite),
+        ("every-100", SyncPolicy::EveryN(100)),
+        ("never", SyncPolicy::Never),
+    ];
+    for (name, policy) in policies {
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let tempdir = TempDir::with_prefix("bjw-bench-").unwrap();
+                let mut db = Database::builder(JsonFormat::<KeyValueStore>::new())
+                    .sync_policy(policy)
+                    .open(tempdir.path().join("kv-store"))
+                    .unwrap();
+                let value = "static value".to_string();
+                for i in 0..n {
+                    db.update(&KeyValueStoreUpdateParams::Insert(i, value.clone()))
+                        .unwrap();
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+/// Leaves out `log_writer`, so the log is reopened for every update. Syncing the log takes at
+/// least `sync_delay`, to simulate a slow disk.
+struct ReopeningBackend {
+    sync_delay: Duration,
+}
+
+impl Backend for ReopeningBackend {
+    fn exists(&self, path: &Path) -> std::io::Result<bool> {
+        FsBackend.exists(path)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> std::io::Result<()> {
+        FsBackend.create_dir_all(path)
+    }
+
+    fn read(&self, path: &Path) -> std::io::Result<Vec<u8>> {
+        FsBackend.read(path)
+    }
+
+    fn write(&self, path: &Path, data: &[u8]) -> std::io::Result<()> {
+        FsBackend.write(path, data)
+    }
+
+    fn append(&self, path: &Path, data: &[u8], sync: bool) -> std::io::Result<()> {
+        if sync {
+            std::thread::sleep(self.sync_delay);
+        }
+        FsBackend.append(path, data, sync)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> std::io::Result<()> {
+        FsBackend.rename(from, to)
+    }
+
+    fn remove_file(&self, path: &Path) -> std::io::Result<()> {
+        FsBackend.remove_file(path)
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> std::io::Result<()> {
+        FsBackend.remove_dir_all(path)
+    }
+
+    fn list_files(&self, path: &Path) -> std::io::Result<Vec<String>> {
+        FsBackend.list_files(path)
+    }
+
+    fn sync_dir(&self, path: &Path) -> std::io::Result<()> {
+        FsBackend.sync_dir(path)
+    }
+}
+
+fn bench_log_writer(c: &mut Criterion) {
+    let mut group = c.benchmark_group("log-writer");
+    let n = 10_000;
+    for (name, reopen) in [("kept-open", false), ("reopened", true)] {
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let tempdir = TempDir::with_prefix("bjw-bench-").unwrap();
+                let mut builder = Database::builder(JsonFormat::<KeyValueStore>::new())
+                    .sync_policy(SyncPolicy::Never);
+                if reopen {
+                    builder = builder.backend(ReopeningBackend {
+                        sync_delay: Duration::ZERO,
+                    });
+                }
+                let mut db = builder.open(tempdir.path().join("kv-store")).unwrap();
+                let value = "static value".to_string();
+                for i in 0..n {
+                    db.update(&KeyValueStoreUpdateParams::Insert(i, value.clone()))
+                        .unwrap();
+                }
+                db.flush().unwrap();
+            })
+        });
+    }
+    group.finish();
+}
+
+/// Reads while another thread keeps updating on a slow disk, either with the write lock held for
+/// the whole update, or with `SharedDatabase` that only takes it to apply the update.
+fn bench_reader_stall(c: &mut Criterion) {
+    let mut group = c.benchmark_group("reader-stall");
+    let open = |tempdir: &TempDir| {
+        Database::builder(JsonFormat::<KeyValueStore>::new())
+            .backend(ReopeningBackend {
+                sync_delay: Duration::from_millis(1),
+            })
+            .open(tempdir.path().join("kv-store"))
+            .unwrap()
+    };
+    let value = "static value".to_string();
+    let stop = Arc::new(AtomicBool::new(false));
+
+    let tempdir = TempDir::with_prefix("bjw-bench-").unwrap();
+    let db = Arc::new(RwLock::new(open(&tempdir)));
+    let writer = {
+        let (db, stop, value) = (db.clone(), stop.clone(), value.clone());
+        std::thread::spawn(move || {
+            for i in 0.. {
+                if stop.load(Ordering::Relaxed) {
+                    break;
+                }
+                let params = KeyValueStoreUpdateParams::Insert(i, value.clone());
+                db.write().unwrap().update(&params).unwrap();
+            }
+        })
+    };
+    group.bench_function("rwlock", |b| {
+        b.iter(|| criterion::black_box(db.read().unwrap().read(&KeyValueStoreReadParams::Get(&0))))
+    });
+    stop.store(true, Ordering::Relaxed);
+    writer.join().unwrap();
+
+    stop.store(false, Ordering::Relaxed);
+    let tempdir = TempDir::with_prefix("bjw-bench-").unwrap();
+    let db = Arc::new(SharedDatabase::new(open(&tempdir)));
+    let writer = {
+        let (db, stop) = (db.clone(), stop.clone());
+        std::thread::spawn(move || {
+            for i in 0.. {
+                if stop.load(Ordering::Relaxed) {
+                    break;
+                }
+                let params = KeyValueStoreUpdateParams::Insert(i, value.clone());
+                db.update(&params).unwrap();
+            }
+        })
+    };
+    group.bench_function("shared", |b| {
+        b.iter(|| criterion::black_box(db.read().read(&KeyValueStoreReadParams::Get(&0))))
+    });
+    stop.store(true, Ordering::Relaxed);
+    writer.join().unwrap();
+    group.finish();
+}
+
 criterion_group! {
     name = key_value_store;
     config = Criterion::default().sample_size(32).warm_up_time(Duration::from_secs(1));
-    targets = bench_create_and_insert, bench_checkpoint
+    targets = bench_create_and_insert, bench_checkpoint, bench_get, bench_sync_policy, bench_log_writer,
+        bench_reader_stall
 }
 criterion_main!(key_value_store);
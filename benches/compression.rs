@@ -0,0 +1,85 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use serde::{Deserialize, Serialize};
+use tempfile::TempDir;
+
+use bjw_db::Compressed;
+use bjw_db_derive::derive_bjw_db;
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+struct KeyValueStore {
+    store: BTreeMap<u64, String>,
+}
+
+#[derive_bjw_db]
+impl KeyValueStore {
+    pub fn insert(&mut self, key: u64, value: String) {
+        self.store.insert(key, value);
+    }
+
+    pub fn get(&self, key: &u64) -> Option<String> {
+        self.store.get(key).cloned()
+    }
+}
+
+const N: u64 = 1_000_000;
+
+fn open_and_fill<F: DataFormat<Data = KeyValueStore>>(
+    fmt: F,
+) -> (Database<KeyValueStore, F>, TempDir) {
+    let tempdir = TempDir::with_prefix("bjw-bench-").unwrap();
+    let mut db = Database::open(tempdir.path().join("kv-store"), fmt).unwrap();
+    let value = "static value".to_string();
+    for i in 0..N {
+        db.update(&KeyValueStoreUpdateParams::Insert(i, value.clone()))
+            .unwrap();
+    }
+    (db, tempdir)
+}
+
+fn checkpoint_size<F: DataFormat<Data = KeyValueStore>>(db: &Database<KeyValueStore, F>) -> u64 {
+    let mut size = 0;
+    for entry in std::fs::read_dir(db.path()).unwrap() {
+        let entry = entry.unwrap();
+        if entry
+            .file_name()
+            .to_string_lossy()
+            .starts_with("checkpoint")
+        {
+            size += entry.metadata().unwrap().len();
+        }
+    }
+    size
+}
+
+fn bench_compressed_checkpoint(c: &mut Criterion) {
+    let mut group = c.benchmark_group("compressed-checkpoint");
+
+    let (mut db, _tempdir) = open_and_fill(JsonFormat::<KeyValueStore>::new());
+    db.create_checkpoint().unwrap();
+    println!(
+        "json checkpoint for {N} entries: {} bytes",
+        checkpoint_size(&db)
+    );
+    group.bench_function("json", |b| b.iter(|| db.create_checkpoint().unwrap()));
+
+    let (mut db, _tempdir) = open_and_fill(Compressed::<JsonFormat<KeyValueStore>>::new());
+    db.create_checkpoint().unwrap();
+    println!(
+        "compressed json checkpoint for {N} entries: {} bytes",
+        checkpoint_size(&db)
+    );
+    group.bench_function("compressed-json", |b| {
+        b.iter(|| db.create_checkpoint().unwrap())
+    });
+
+    group.finish();
+}
+
+criterion_group! {
+    name = compression;
+    config = Criterion::default().sample_size(10).warm_up_time(Duration::from_secs(1));
+    targets = bench_compressed_checkpoint
+}
+criterion_main!(compression);
@@ -0,0 +1,79 @@
+use std::time::Duration;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use serde::{Deserialize, Serialize};
+use tempfile::TempDir;
+
+use bjw_db::Result;
+use bjw_db_derive::derive_bjw_db;
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+struct Numbers {
+    values: Vec<u64>,
+}
+
+#[derive_bjw_db(fmt = "BincodeFormat")]
+impl Numbers {
+    pub fn fill(&mut self, n: u64) {
+        self.values = (0..n).collect();
+    }
+
+    pub fn get(&self, index: &usize) -> Option<u64> {
+        self.values.get(*index).copied()
+    }
+}
+
+/// About 500MB of checkpoint.
+const N: u64 = 62_500_000;
+
+/// Bincode, but without mapping the checkpoint.
+struct CopiedBincode(BincodeFormat<Numbers>);
+
+impl DataFormat for CopiedBincode {
+    type Data = Numbers;
+
+    fn new() -> Self {
+        CopiedBincode(BincodeFormat::new())
+    }
+
+    fn serialize_data(&self, data: &Numbers) -> Result<Vec<u8>> {
+        self.0.serialize_data(data)
+    }
+
+    fn deserialize_data(&self, input: &[u8]) -> Result<Numbers> {
+        self.0.deserialize_data(input)
+    }
+
+    fn serialize_params(&self, params: &NumbersUpdateParams) -> Result<Vec<u8>> {
+        self.0.serialize_params(params)
+    }
+
+    fn deserialize_params(&self, input: &[u8]) -> Result<Vec<NumbersUpdateParams>> {
+        self.0.deserialize_params(input)
+    }
+}
+
+fn bench_open(c: &mut Criterion) {
+    let mut group = c.benchmark_group("open-large-checkpoint");
+    let tempdir = TempDir::with_prefix("bjw-bench-").unwrap();
+    let path = tempdir.path().join("numbers");
+    let mut db = NumbersDb::open(&path).unwrap();
+    db.fill(N).unwrap();
+    db.create_checkpoint().unwrap();
+    drop(db);
+
+    group.bench_function("mapped", |b| {
+        b.iter(|| Database::open(&path, BincodeFormat::<Numbers>::new()).unwrap())
+    });
+    group.bench_function("copied", |b| {
+        b.iter(|| Database::open(&path, CopiedBincode::new()).unwrap())
+    });
+    group.finish();
+}
+
+criterion_group! {
+    name = mmap;
+    config = Criterion::default().sample_size(10).warm_up_time(Duration::from_secs(1));
+    targets = bench_open
+}
+criterion_main!(mmap);
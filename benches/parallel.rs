@@ -0,0 +1,64 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use serde::{Deserialize, Serialize};
+use tempfile::TempDir;
+
+use bjw_db::MapData;
+use bjw_db_derive::derive_bjw_db;
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+struct KeyValueStore {
+    store: BTreeMap<u64, String>,
+}
+
+#[derive_bjw_db]
+impl KeyValueStore {
+    pub fn insert(&mut self, key: u64, value: String) {
+        self.store.insert(key, value);
+    }
+
+    pub fn get(&self, key: &u64) -> Option<String> {
+        self.store.get(key).cloned()
+    }
+}
+
+impl MapData for KeyValueStore {
+    type Key = u64;
+    type Value = String;
+
+    fn entries(&self) -> &BTreeMap<u64, String> {
+        &self.store
+    }
+
+    fn from_entries(store: BTreeMap<u64, String>) -> Self {
+        KeyValueStore { store }
+    }
+}
+
+const N: u64 = 1_000_000;
+
+fn bench_parallel_checkpoint(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parallel-checkpoint");
+    let store = KeyValueStore {
+        store: (0..N).map(|i| (i, "static value".to_string())).collect(),
+    };
+    for threads in [1, 8] {
+        let tempdir = TempDir::with_prefix("bjw-bench-").unwrap();
+        let mut db = Database::builder(JsonFormat::<KeyValueStore>::new())
+            .parallel_checkpoints(threads)
+            .open_or_init(tempdir.path().join("kv-store"), || store.clone())
+            .unwrap();
+        group.bench_with_input(BenchmarkId::from_parameter(threads), &threads, |b, _| {
+            b.iter(|| db.create_checkpoint().unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group! {
+    name = parallel;
+    config = Criterion::default().sample_size(10).warm_up_time(Duration::from_secs(1));
+    targets = bench_parallel_checkpoint
+}
+criterion_main!(parallel);
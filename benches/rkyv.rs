@@ -0,0 +1,134 @@
+use std::{collections::BTreeMap, path::Path, time::Duration};
+
+use criterion::{criterion_group, criterion_main, BenchmarkGroup, Criterion};
+use serde::{de::DeserializeOwned, Serialize};
+use tempfile::TempDir;
+
+use bjw_db::{DataFormat, Database, JsonFormat, RkyvFormat};
+
+/// A checkpoint of numbers, which JSON has to parse digit by digit.
+mod numbers {
+    use serde::{Deserialize, Serialize};
+
+    use bjw_db_derive::derive_bjw_db;
+
+    #[derive(
+        Debug,
+        Default,
+        Serialize,
+        Deserialize,
+        Clone,
+        PartialEq,
+        rkyv::Archive,
+        rkyv::Serialize,
+        rkyv::Deserialize,
+    )]
+    pub struct Numbers {
+        pub store: super::BTreeMap<u64, u64>,
+    }
+
+    #[derive_bjw_db(fmt = "RkyvFormat")]
+    impl Numbers {
+        pub fn insert(&mut self, key: u64, value: u64) {
+            self.store.insert(key, value);
+        }
+
+        pub fn get(&self, key: &u64) -> Option<u64> {
+            self.store.get(key).copied()
+        }
+    }
+}
+
+/// A checkpoint of strings, which both formats have to allocate anew.
+mod strings {
+    use serde::{Deserialize, Serialize};
+
+    use bjw_db_derive::derive_bjw_db;
+
+    #[derive(
+        Debug,
+        Default,
+        Serialize,
+        Deserialize,
+        Clone,
+        PartialEq,
+        rkyv::Archive,
+        rkyv::Serialize,
+        rkyv::Deserialize,
+    )]
+    pub struct Strings {
+        pub store: super::BTreeMap<u64, String>,
+    }
+
+    #[derive_bjw_db(fmt = "RkyvFormat")]
+    impl Strings {
+        pub fn insert(&mut self, key: u64, value: String) {
+            self.store.insert(key, value);
+        }
+
+        pub fn get(&self, key: &u64) -> Option<String> {
+            self.store.get(key).cloned()
+        }
+    }
+}
+
+const N: u64 = 1_000_000;
+
+/// Benchmarks opening a database whose checkpoint holds `data`, written with `F`.
+fn bench_format<F>(
+    group: &mut BenchmarkGroup<'_, criterion::measurement::WallTime>,
+    name: &str,
+    path: &Path,
+    data: &F::Data,
+) where
+    F: DataFormat,
+    F::Data: Clone + Default + Serialize + DeserializeOwned,
+{
+    Database::open_or_init(path, F::new(), || data.clone()).unwrap();
+    group.bench_function(name, |b| b.iter(|| Database::open(path, F::new()).unwrap()));
+}
+
+fn bench_open(c: &mut Criterion) {
+    let tempdir = TempDir::with_prefix("bjw-bench-").unwrap();
+    let mut group = c.benchmark_group("open-large-checkpoint");
+
+    let data = numbers::Numbers {
+        store: (0..N).map(|i| (i, i * i)).collect(),
+    };
+    bench_format::<JsonFormat<_>>(
+        &mut group,
+        "numbers-json",
+        &tempdir.path().join("nj"),
+        &data,
+    );
+    bench_format::<RkyvFormat<_>>(
+        &mut group,
+        "numbers-rkyv",
+        &tempdir.path().join("nr"),
+        &data,
+    );
+
+    let data = strings::Strings {
+        store: (0..N).map(|i| (i, format!("value {i}"))).collect(),
+    };
+    bench_format::<JsonFormat<_>>(
+        &mut group,
+        "strings-json",
+        &tempdir.path().join("sj"),
+        &data,
+    );
+    bench_format::<RkyvFormat<_>>(
+        &mut group,
+        "strings-rkyv",
+        &tempdir.path().join("sr"),
+        &data,
+    );
+    group.finish();
+}
+
+criterion_group! {
+    name = rkyv;
+    config = Criterion::default().sample_size(10).warm_up_time(Duration::from_secs(1));
+    targets = bench_open
+}
+criterion_main!(rkyv);
@@ -0,0 +1,82 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use serde::{Deserialize, Serialize};
+use tempfile::TempDir;
+
+use bjw_db_derive::derive_bjw_db;
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+struct KeyValueStore {
+    store: BTreeMap<u64, String>,
+}
+
+#[derive_bjw_db(thread_safe)]
+impl KeyValueStore {
+    pub fn insert(&mut self, key: u64, value: String) {
+        self.store.insert(key, value);
+    }
+
+    pub fn get(&self, key: &u64) -> Option<String> {
+        self.store.get(key).cloned()
+    }
+}
+
+const KEYS: u64 = 10_000;
+const READERS: usize = 4;
+const READS: u64 = 20_000;
+/// How many reads use the same snapshot before a new one is taken.
+const READS_PER_SNAPSHOT: u64 = 1_000;
+const WRITES: u64 = 20;
+
+/// Runs `READERS` threads doing `READS` reads each with `reads`, while a few updates happen.
+fn concurrent_reads(db: &KeyValueStoreDb, reads: impl Fn(&KeyValueStoreDb) + Sync) {
+    std::thread::scope(|scope| {
+        for _ in 0..READERS {
+            scope.spawn(|| reads(db));
+        }
+        for i in 0..WRITES {
+            db.insert(i, "updated".to_string()).unwrap();
+        }
+    });
+}
+
+fn bench_snapshot_reads(c: &mut Criterion) {
+    let mut group = c.benchmark_group("concurrent-reads");
+    let tempdir = TempDir::with_prefix("bjw-bench-").unwrap();
+    let db = KeyValueStoreDb::open(tempdir.path().join("kv-store")).unwrap();
+    for key in 0..KEYS {
+        db.insert(key, "value".to_string()).unwrap();
+    }
+    db.create_checkpoint().unwrap();
+
+    group.bench_function("read-lock", |b| {
+        b.iter(|| {
+            concurrent_reads(&db, |db| {
+                for i in 0..READS {
+                    std::hint::black_box(db.get(&(i % KEYS)));
+                }
+            })
+        })
+    });
+    group.bench_function("snapshot-arc", |b| {
+        b.iter(|| {
+            concurrent_reads(&db, |db| {
+                for chunk in 0..READS / READS_PER_SNAPSHOT {
+                    let snapshot = db.snapshot_arc();
+                    for i in chunk * READS_PER_SNAPSHOT..(chunk + 1) * READS_PER_SNAPSHOT {
+                        std::hint::black_box(snapshot.get(&(i % KEYS)));
+                    }
+                }
+            })
+        })
+    });
+    group.finish();
+}
+
+criterion_group! {
+    name = snapshot;
+    config = Criterion::default().sample_size(10).warm_up_time(Duration::from_secs(1));
+    targets = bench_snapshot_reads
+}
+criterion_main!(snapshot);
@@ -1,7 +1,11 @@
-use darling::{ast::NestedMeta, Error, FromMeta};
-use proc_macro::TokenStream;
+use darling::{ast::NestedMeta, Error, FromAttributes, FromMeta};
+use proc_macro::{Ident, TokenStream, TokenTree};
 use quote::{format_ident, quote};
-use syn::{parse_macro_input, FnArg, ItemImpl, PatType, ReturnType, Type, TypeReference};
+use syn::{
+    parse::{Parse, ParseStream},
+    parse_macro_input, parse_quote, FnArg, GenericArgument, GenericParam, ItemImpl, Meta, Pat,
+    PatIdent, PatType, PathArguments, ReturnType, Type, TypeReference,
+};
 
 fn uppercase_first(input: &str) -> String {
     if input.is_empty() {
@@ -22,56 +26,372 @@ fn uppercase_first(input: &str) -> String {
 struct DeriveArgs {
     thread_safe: bool,
     fmt: Option<String>,
+    auto_checkpoint_bytes: Option<u64>,
+    auto_checkpoint_ratio: Option<f64>,
+    async_api: bool,
+    db_name: Option<String>,
+    debug: bool,
+    backup: bool,
+    subscribe: bool,
+    dry_run: bool,
+    visibility: Option<String>,
 }
 
+/// Options for a single method, given as `#[bjw(...)]`.
+#[derive(Default, FromAttributes)]
+#[darling(attributes(bjw), default)]
+struct MethodArgs {
+    skip: bool,
+    flatten_result: bool,
+    rename: Option<String>,
+    serde_rename: Option<String>,
+}
+
+/// Splits `Result<T, E>` into `T` and `E`.
+fn result_types(ty: &Type) -> Option<(&Type, &Type)> {
+    let Type::Path(tp) = ty else {
+        return None;
+    };
+    let segment = tp.path.segments.last()?;
+    if segment.ident != "Result" {
+        return None;
+    }
+    let PathArguments::AngleBracketed(args) = &segment.arguments else {
+        return None;
+    };
+    let mut types = args.args.iter().filter_map(|arg| match arg {
+        GenericArgument::Type(ty) => Some(ty),
+        _ => None,
+    });
+    match (types.next(), types.next(), types.next()) {
+        (Some(ok), Some(err), None) => Some((ok, err)),
+        _ => None,
+    }
+}
+
+/// Generates the parameter and return enums for all methods of the impl block, and a `<Name>Db`
+/// wrapper with one method per original method, which carries its doc comments. Update methods
+/// return the method's return value wrapped in `bjw_db::Result`, so a method returning
+/// `Result<T, E>` ends up as `bjw_db::Result<Result<T, E>>`. Mark it with
+/// `#[bjw(flatten_result)]` to get `Result<T, bjw_db::UpdateError<E>>` instead. Methods marked
+/// with `#[bjw(skip)]` are left out completely, which also allows associated functions without
+/// `self`. The enum variant of a method is its name starting with an uppercase letter (a unit
+/// variant if it takes no arguments), `#[bjw(rename = "...")]` overrides it. As the update
+/// variants are what the log stores, `#[bjw(serde_rename = "...")]` pins the name an update is
+/// serialized with, e.g. to keep reading old logs after renaming its method.
+///
+/// The wrappers, their methods and the enums are `pub`, `visibility = "pub(crate)"` (or any
+/// other visibility) keeps them out of a library's public API. The enums can be constructed
+/// directly, e.g. to pass a `Vec<<Name>UpdateParams>` to `update_batch`.
+///
+/// An impl block may have only read methods, only update methods, or none at all. The enums of
+/// the missing kind are then empty (apart from a hidden variant for the read lifetime).
+///
+/// Methods taking `self` by value must return the new `Self`, they are treated as updates that
+/// replace the whole state.
+///
+/// With `dry_run`, every update method `<name>` also gets a `<name>_dry_run` wrapper taking
+/// `&self`, which returns what the update would return without logging or applying it, see
+/// `bjw_db::Database::peek_update`.
+///
+/// Read methods returning a reference (to the data) get no variant. Their wrapper takes a
+/// closure as an additional last argument `f`, which gets the reference while the data is
+/// borrowed, e.g. `db.get_ref(key, |value| value.len())`. Their arguments are not cloned.
+///
+/// Generic stores are supported, the enums and wrappers take over the generics of the impl
+/// block, as well as its where clause (which also works without generics). Their type
+/// parameters have to be `Serialize + DeserializeOwned + 'static`, and the store itself
+/// `Clone + Default`. The enums then get a hidden variant that is never used.
+///
+/// With `thread_safe`, `<Name>Db` also gets `spawn_autocheckpoint`, which takes
+/// `self: &Arc<Self>` and creates checkpoints in a background thread, see
+/// `bjw_db::SharedDatabase::spawn_autocheckpoint`.
+///
+/// With `async`, which requires the `tokio` feature of bjw_db, a `<Name>AsyncDb` wrapper around
+/// `bjw_db::AsyncDatabase` is generated as well. Its methods are `async` and take `&self`, as the
+/// database is guarded by a `tokio::sync::RwLock`, so it can be shared regardless of
+/// `thread_safe`. The synchronous `<Name>Db` is unaffected.
+///
+/// With `backup`, which requires the `backup` feature of bjw_db, `<Name>Db` gets `backup_to` and
+/// `restore_from`, see `bjw_db::Database::backup_to`.
+///
+/// With `fmt = "RkyvFormat"`, which requires the `rkyv` feature of bjw_db, the update enum also
+/// derives rkyv's `Archive`, `Serialize` and `Deserialize`, so all argument types of update
+/// methods must implement them, as must the store, see `bjw_db::RkyvFormat`. Generic stores are
+/// not supported with it.
+///
+/// With `fmt = "..."`, the wrappers use that format instead of `JsonFormat`. It must be generic
+/// over the store (`fmt = "Name"` is used as `Name<Store>`), as the wrappers create it themselves
+/// with `DataFormat::new`. The wrapper formats `Compressed`, `Encrypted` and `Checksummed` are
+/// rejected, as is anything else that needs a key or other configuration: to encrypt, define a
+/// format like `struct MyFormat<T>(Encrypted<JsonFormat<T>>)` that delegates to the wrapped
+/// format, and whose `DataFormat::new` loads the key from wherever the application keeps it.
+///
+/// With `subscribe`, the update enum derives `Clone` and `<Name>Db` gets `subscribe`, see
+/// `bjw_db::Database::subscribe`. All argument types of update methods must implement `Clone`.
+///
+/// The attribute can only be used on a single impl block per struct, as the generated items
+/// would clash otherwise. Use [`bjw_db_impls!`] to spread the methods over several blocks.
 #[proc_macro_attribute]
 pub fn derive_bjw_db(args: TokenStream, item: TokenStream) -> TokenStream {
-    let attr_args = match NestedMeta::parse_meta_list(args.into()) {
+    let args = match parse_args(args) {
         Ok(v) => v,
-        Err(e) => {
-            return TokenStream::from(Error::from(e).write_errors());
+        Err(e) => return e,
+    };
+    let input = parse_macro_input!(item as ItemImpl);
+    expand(args, vec![input])
+}
+
+/// Several impl blocks of the same struct, see [`bjw_db_impls!`].
+struct ImplBlocks(Vec<ItemImpl>);
+
+impl Parse for ImplBlocks {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut impls = Vec::new();
+        while !input.is_empty() {
+            impls.push(input.parse()?);
         }
+        Ok(ImplBlocks(impls))
+    }
+}
+
+fn is_derive_attr(attr: &syn::Attribute) -> bool {
+    attr.path()
+        .segments
+        .last()
+        .is_some_and(|segment| segment.ident == "derive_bjw_db")
+}
+
+/// Works like `#[derive_bjw_db]`, but collects the methods of several impl blocks of the same
+/// struct, which end up in the same generated enums and wrapper. Options are given by putting
+/// `#[derive_bjw_db(...)]` on the first block.
+///
+/// ```ignore
+/// bjw_db_impls! {
+///     #[derive_bjw_db(thread_safe)]
+///     impl KeyValueStore {
+///         pub fn insert(&mut self, key: String, value: String) { ... }
+///     }
+///
+///     impl KeyValueStore {
+///         pub fn get(&self, key: &str) -> Option<String> { ... }
+///     }
+/// }
+/// ```
+#[proc_macro]
+pub fn bjw_db_impls(input: TokenStream) -> TokenStream {
+    let ImplBlocks(mut impls) = parse_macro_input!(input as ImplBlocks);
+    let Some(first) = impls.first_mut() else {
+        return TokenStream::from(Error::custom("Expected at least one impl block").write_errors());
     };
-    let args = match DeriveArgs::from_list(&attr_args) {
-        Ok(v) => v,
-        Err(e) => {
-            return TokenStream::from(e.write_errors());
+    let mut args = DeriveArgs::default();
+    if let Some(pos) = first.attrs.iter().position(is_derive_attr) {
+        let attr = first.attrs.remove(pos);
+        let tokens = match attr.meta {
+            Meta::Path(_) => TokenStream::new(),
+            Meta::List(list) => list.tokens.into(),
+            Meta::NameValue(nv) => {
+                return TokenStream::from(
+                    Error::unsupported_format("name value")
+                        .with_span(&nv)
+                        .write_errors(),
+                );
+            }
+        };
+        args = match parse_args(tokens) {
+            Ok(v) => v,
+            Err(e) => return e,
+        };
+    }
+    for block in impls.iter().skip(1) {
+        if let Some(attr) = block.attrs.iter().find(|attr| is_derive_attr(attr)) {
+            return TokenStream::from(
+                Error::custom("Only the first impl block may carry #[derive_bjw_db(...)]")
+                    .with_span(attr)
+                    .write_errors(),
+            );
         }
+    }
+    expand(args, impls)
+}
+
+fn parse_args(args: TokenStream) -> Result<DeriveArgs, TokenStream> {
+    // `async` is a keyword and thus no valid meta item, so it is renamed before parsing
+    let args: TokenStream = args
+        .into_iter()
+        .map(|tt| match tt {
+            TokenTree::Ident(ident) if ident.to_string() == "async" => {
+                TokenTree::Ident(Ident::new("async_api", ident.span()))
+            }
+            tt => tt,
+        })
+        .collect();
+    let attr_args = NestedMeta::parse_meta_list(args.into())
+        .map_err(|e| TokenStream::from(Error::from(e).write_errors()))?;
+    DeriveArgs::from_list(&attr_args).map_err(|e| TokenStream::from(e.write_errors()))
+}
+
+/// Generates everything for the methods of all `impls`, which must belong to the same struct.
+fn expand(args: DeriveArgs, impls: Vec<ItemImpl>) -> TokenStream {
+    // the formats shipped with bjw_db are imported automatically, custom ones must be in scope
+    let fmt_name = args.fmt.unwrap_or_else(|| "JsonFormat".to_string());
+    // the wrappers take a format instead of the store, and Encrypted has no key to be created with
+    if matches!(
+        fmt_name.as_str(),
+        "Compressed" | "Encrypted" | "Checksummed"
+    ) {
+        return TokenStream::from(
+            Error::custom(format!(
+                "{fmt_name} wraps another format and cannot be used as fmt directly, name a \
+                 format generic over the store that creates it instead"
+            ))
+            .write_errors(),
+        );
+    }
+    let fmt = format_ident!("{}", fmt_name);
+    let import_fmt = match fmt_name.as_str() {
+        "JsonFormat" | "BincodeFormat" | "MsgPackFormat" | "CborFormat" | "TomlFormat"
+        | "RkyvFormat" => {
+            quote! { use bjw_db::#fmt; }
+        }
+        _ => quote! {},
     };
-    let (fmt, import_json_fmt) = match args.fmt {
-        Some(f) => (format_ident!("{}", f), quote! {}),
-        None => (
-            format_ident!("JsonFormat"),
-            quote! { use bjw_db::JsonFormat; },
-        ),
+
+    let vis = match args
+        .visibility
+        .as_deref()
+        .map(syn::parse_str::<syn::Visibility>)
+    {
+        None => quote! { pub },
+        Some(Ok(vis)) => quote! { #vis },
+        Some(Err(_)) => {
+            return TokenStream::from(
+                Error::custom(format!(
+                    "{:?} is no valid visibility",
+                    args.visibility.unwrap()
+                ))
+                .write_errors(),
+            );
+        }
     };
-    let fmt = format_ident!("{}", fmt);
 
-    let input = parse_macro_input!(item as ItemImpl);
-    let cloned = input.clone();
+    // open-time options are passed on to the database builder
+    let mut builder_options = Vec::new();
+    if let Some(bytes) = args.auto_checkpoint_bytes {
+        builder_options.push(quote! { .auto_checkpoint_bytes(#bytes) });
+    }
+    if let Some(ratio) = args.auto_checkpoint_ratio {
+        builder_options.push(quote! { .auto_checkpoint_ratio(#ratio) });
+    }
+
+    // the original impls are emitted without our method attributes, which are no real attributes
+    let mut cloned = impls.clone();
+    for item in cloned.iter_mut().flat_map(|block| block.items.iter_mut()) {
+        if let syn::ImplItem::Fn(method) = item {
+            method.attrs.retain(|attr| !attr.path().is_ident("bjw"));
+        }
+    }
+
+    let mut struct_names = Vec::new();
+    for block in impls.iter() {
+        match &*block.self_ty {
+            Type::Path(tp) if tp.qself.is_none() && !tp.path.segments.is_empty() => {
+                struct_names.push(tp.path.segments.last().unwrap().ident.clone());
+            }
+            ty => {
+                return TokenStream::from(
+                    Error::custom("Expected the impl block of a struct")
+                        .with_span(ty)
+                        .write_errors(),
+                );
+            }
+        }
+    }
+    let struct_name = struct_names[0].clone();
+    if let Some(other) = struct_names.iter().find(|name| **name != struct_name) {
+        return TokenStream::from(
+            Error::custom(format!(
+                "All impl blocks must belong to {struct_name}, found one for {other}"
+            ))
+            .with_span(other)
+            .write_errors(),
+        );
+    }
 
-    let struct_name = if let Type::Path(tp) = *input.self_ty {
-        tp.path.segments.last().unwrap().ident.clone()
+    // the generated items take over the generics of the impl, including their bounds
+    let generics = impls[0].generics.clone();
+    let self_ty = impls[0].self_ty.clone();
+    let is_generic = !generics.params.is_empty();
+    let (impl_generics, ty_generics, _) = generics.split_for_impl();
+    let def_params: Vec<_> = generics
+        .params
+        .iter()
+        .map(|param| quote! { #param })
+        .collect();
+    let def_where = &generics.where_clause;
+    let ty_args: Vec<_> = generics
+        .params
+        .iter()
+        .map(|param| match param {
+            GenericParam::Type(tp) => {
+                let ident = &tp.ident;
+                quote! { #ident }
+            }
+            GenericParam::Lifetime(lp) => {
+                let lifetime = &lp.lifetime;
+                quote! { #lifetime }
+            }
+            GenericParam::Const(cp) => {
+                let ident = &cp.ident;
+                quote! { #ident }
+            }
+        })
+        .collect();
+    // every enum has to use all parameters, which a hidden variant that cannot exist takes care of
+    let phantom_types: Vec<_> = generics
+        .params
+        .iter()
+        .filter_map(|param| match param {
+            GenericParam::Type(tp) => {
+                let ident = &tp.ident;
+                Some(quote! { #ident })
+            }
+            GenericParam::Lifetime(lp) => {
+                let lifetime = &lp.lifetime;
+                Some(quote! { &#lifetime () })
+            }
+            GenericParam::Const(_) => None,
+        })
+        .collect();
+    let (phantom_variant, serde_skip) = if is_generic {
+        (
+            quote! {
+                #[doc(hidden)]
+                __Phantom(std::marker::PhantomData<(#(#phantom_types),*)>, std::convert::Infallible),
+            },
+            quote! { #[serde(skip)] },
+        )
     } else {
-        panic!("Expected a struct implementation");
+        (quote! {}, quote! {})
     };
 
-    // some things differ between the thread_safe and the not thread_safe version
+    // some things differ between the thread_safe and the not thread_safe version, updates call
+    // `self.db.update` in both, as `SharedDatabase` does its own locking for them
     let (wrapped_type, constructor, read_acces, write_access, mut_self, into_inner) = if args
         .thread_safe
     {
         (
-            quote! { std::sync::RwLock<Database<#struct_name, #fmt<#struct_name>>> },
-            quote! { Ok(Self { db: std::sync::RwLock::new(db), path: path.as_ref().to_path_buf() }) },
-            quote! { self.db.read().unwrap() },
-            quote! { self.db.write().unwrap() },
+            quote! { bjw_db::SharedDatabase<#self_ty, #fmt<#self_ty>> },
+            quote! { Ok(Self { path: db.path().clone(), db: bjw_db::SharedDatabase::new(db) }) },
+            quote! { self.db.read() },
+            quote! { self.db.write() },
             quote! { &self },
-            quote! { self.db.into_inner().unwrap() },
+            quote! { self.db.into_inner() },
         )
     } else {
         (
-            quote! { Database<#struct_name, #fmt<#struct_name>> },
-            quote! { Ok(Self { db, path: path.as_ref().to_path_buf() }) },
+            quote! { Database<#self_ty, #fmt<#self_ty>> },
+            quote! { Ok(Self { path: db.path().clone(), db }) },
             quote! { self.db },
             quote! { self.db },
             quote! { &mut self },
@@ -79,14 +399,54 @@ pub fn derive_bjw_db(args: TokenStream, item: TokenStream) -> TokenStream {
         )
     };
 
+    // a shared database keeps the snapshot until the data changes
+    let snapshot_arc = if args.thread_safe {
+        quote! { self.db.snapshot_arc() }
+    } else {
+        quote! { std::sync::Arc::new(self.db.clone_data()) }
+    };
+
+    // only a shared database can be checkpointed from another thread
+    let spawn_autocheckpoint = if args.thread_safe {
+        quote! {
+            /// Creates checkpoints every `interval` in a background thread until the handle is
+            /// dropped, see `bjw_db::SharedDatabase::spawn_autocheckpoint`.
+            #vis fn spawn_autocheckpoint(
+                self: &std::sync::Arc<Self>,
+                interval: std::time::Duration,
+            ) -> bjw_db::Result<bjw_db::AutoCheckpoint>
+            where
+                Self: Send + Sync + 'static,
+            {
+                bjw_db::AutoCheckpoint::spawn(self, interval, |wrapper: &Self| {
+                    wrapper.db.checkpoint_if_pending()
+                })
+            }
+        }
+    } else {
+        quote! {}
+    };
+
     // build the names for the three enums we need
     let read_params_ident = format_ident!("{}ReadParams", struct_name);
     let read_return_ident = format_ident!("{}ReadReturn", struct_name);
     let update_params_ident = format_ident!("{}UpdateParams", struct_name);
     let update_return_ident = format_ident!("{}UpdateReturn", struct_name);
+    let read_params_ty = quote! { #read_params_ident<'a #(, #ty_args)*> };
+    let read_return_ty = quote! { #read_return_ident #ty_generics };
+    let update_params_ty = quote! { #update_params_ident #ty_generics };
+    let update_return_ty = quote! { #update_return_ident #ty_generics };
+    let update_phantom_arm = if is_generic {
+        quote! { #update_params_ident::__Phantom(_, never) => match *never {}, }
+    } else {
+        quote! {}
+    };
 
     // build the name for the DB wrapper
-    let db_struct_ident = format_ident!("{}Db", struct_name);
+    let db_struct_ident = match &args.db_name {
+        Some(name) => format_ident!("{}", name),
+        None => format_ident!("{}Db", struct_name),
+    };
 
     // vectors for collecting relevant infos while parsing all functions
     let mut read_params_variants = Vec::new();
@@ -97,159 +457,691 @@ pub fn derive_bjw_db(args: TokenStream, item: TokenStream) -> TokenStream {
     let mut update_match_arms = Vec::new();
     let mut read_methods = Vec::new();
     let mut update_methods = Vec::new();
+    let mut async_read_methods = Vec::new();
+    let mut async_update_methods = Vec::new();
+    // the variants used so far, along with the method they belong to and whether it reads
+    let mut variants: Vec<(String, bool, syn::Ident)> = Vec::new();
+    let mut has_consuming = false;
+    // whether any read params variant uses the `'a` lifetime
+    let mut reads_borrow = false;
 
     // parse all function signatures
-    for item in input.items.iter() {
+    for item in impls.iter().flat_map(|block| block.items.iter()) {
         if let syn::ImplItem::Fn(method) = item {
             let method_name = &method.sig.ident;
-            let variant_name =
-                format_ident!("{}", uppercase_first(method_name.to_string().as_ref()));
+            let method_args = match MethodArgs::from_attributes(&method.attrs) {
+                Ok(v) => v,
+                Err(e) => {
+                    return TokenStream::from(e.write_errors());
+                }
+            };
+            // helpers that should not be accessible through the database
+            if method_args.skip {
+                continue;
+            }
+            // the generated methods carry the documentation of the original ones
+            let docs: Vec<_> = method
+                .attrs
+                .iter()
+                .filter(|attr| attr.path().is_ident("doc"))
+                .collect();
+            let variant = method_args
+                .rename
+                .unwrap_or_else(|| uppercase_first(method_name.to_string().as_ref()));
+            let variant_name = format_ident!("{}", variant);
 
-            let is_read = method.sig.inputs.iter().next().is_some_and(|arg| matches!(arg, FnArg::Receiver(r) if r.reference.is_some() && r.mutability.is_none()));
-            let is_update = method.sig.inputs.iter().next().is_some_and(|arg| matches!(arg, FnArg::Receiver(r) if r.reference.is_some() && r.mutability.is_some()));
-            if !is_read && !is_update {
-                panic!("Found strange function without a &[mut] self");
+            let receiver = match method.sig.inputs.first() {
+                Some(FnArg::Receiver(r)) if r.colon_token.is_none() => r,
+                _ => {
+                    return TokenStream::from(
+                        Error::custom(format!(
+                            "{method_name} needs a self, &self or &mut self receiver, \
+                             mark helpers with #[bjw(skip)]"
+                        ))
+                        .with_span(method_name)
+                        .write_errors(),
+                    );
+                }
+            };
+            let is_read = receiver.reference.is_some() && receiver.mutability.is_none();
+            // methods taking self by value replace the whole state, so they are updates too
+            let is_consuming = receiver.reference.is_none();
+            has_consuming |= is_consuming;
+            let is_update = is_consuming || receiver.mutability.is_some();
+            if is_consuming {
+                let returns_self = match &method.sig.output {
+                    ReturnType::Type(_, ty) => match &**ty {
+                        Type::Path(tp) => tp.path.segments.last().is_some_and(|segment| {
+                            segment.ident == "Self" || segment.ident == struct_name
+                        }),
+                        _ => false,
+                    },
+                    _ => false,
+                };
+                if !returns_self {
+                    return TokenStream::from(
+                        Error::custom(format!(
+                            "{method_name} takes self by value, so it must return the new Self"
+                        ))
+                        .with_span(method_name)
+                        .write_errors(),
+                    );
+                }
+            }
+
+            // a borrow of the live data cannot leave the lock guarding it, so reads returning one
+            // get no variant, and hand it to a closure instead
+            let borrowed = match &method.sig.output {
+                ReturnType::Type(_, ty) if is_read => match &**ty {
+                    Type::Reference(TypeReference { elem, .. }) => Some(elem),
+                    _ => None,
+                },
+                _ => None,
+            };
+
+            // e.g. `get` and `Get` would both become `Get`
+            if let Some((_, _, other)) = variants
+                .iter()
+                .find(|(name, read, _)| *name == variant && *read == is_read)
+                .filter(|_| borrowed.is_none())
+            {
+                return TokenStream::from(
+                    Error::custom(format!(
+                        "The variant {variant} for {method_name} is already used for {other}, \
+                         use #[bjw(rename = \"...\")] to choose another one"
+                    ))
+                    .with_span(method_name)
+                    .write_errors(),
+                );
+            }
+            if borrowed.is_none() {
+                variants.push((variant, is_read, method_name.clone()));
             }
 
             // parse arguments
             let mut arg_types = Vec::new();
             let mut arg_types_with_lifetime = Vec::new();
             let mut arg_names = Vec::new();
+            let mut arg_is_ref = Vec::new();
             for arg in method.sig.inputs.iter().skip(1) {
-                if let FnArg::Typed(PatType { ty, .. }) = arg {
-                    arg_types.push(quote! { #ty });
-                    if let Type::Reference(TypeReference { elem, .. }) = &**ty {
-                        arg_types_with_lifetime.push(quote! { &'a #elem });
-                    } else {
-                        arg_types_with_lifetime.push(quote! { #ty });
-                    }
-                    if let FnArg::Typed(PatType { pat, .. }) = arg {
-                        arg_names.push(quote! { #pat });
-                    } else {
-                        panic!("Found strange function argument without a name: {:?}", arg);
+                // the receiver can only come first, which the parser already made sure of
+                let FnArg::Typed(PatType { pat, ty, .. }) = arg else {
+                    continue;
+                };
+                // the names end up as fields of the enum variants, so patterns do not work
+                let name = match &**pat {
+                    Pat::Ident(PatIdent {
+                        ident,
+                        subpat: None,
+                        by_ref: None,
+                        ..
+                    }) => ident,
+                    _ => {
+                        return TokenStream::from(
+                            Error::custom(format!(
+                                "The arguments of {method_name} must be plain names, \
+                                 destructure them in the method body instead"
+                            ))
+                            .with_span(pat)
+                            .write_errors(),
+                        );
                     }
+                };
+                arg_names.push(quote! { #name });
+                arg_types.push(quote! { #ty });
+                if let Type::Reference(TypeReference { elem, .. }) = &**ty {
+                    arg_types_with_lifetime.push(quote! { &'a #elem });
+                    arg_is_ref.push(true);
                 } else {
-                    panic!("Found strange function argument without a type: {:?}", arg);
+                    arg_types_with_lifetime.push(quote! { #ty });
+                    arg_is_ref.push(false);
                 }
             }
+            if let Some(elem) = borrowed {
+                read_methods.push(quote! {
+                    #(#docs)*
+                    #[allow(dead_code)]
+                    #vis fn #method_name<Output>(&self, #(#arg_names: #arg_types,)* f: impl FnOnce(&#elem) -> Output) -> Output {
+                        f(#read_acces.read_all().#method_name(#(#arg_names),*))
+                    }
+                });
+                async_read_methods.push(quote! {
+                    #(#docs)*
+                    #[allow(dead_code)]
+                    #vis async fn #method_name<Output>(&self, #(#arg_names: #arg_types,)* f: impl FnOnce(&#elem) -> Output) -> Output {
+                        self.db.read_with(|data| f(data.#method_name(#(#arg_names),*))).await
+                    }
+                });
+                continue;
+            }
+
             let cloned_args: Vec<_> = arg_names.iter().map(|n| quote! { #n.clone()}).collect();
+            // methods without arguments get unit variants, which serialize as just their name
+            let (arg_pattern, read_fields, update_fields) = if arg_names.is_empty() {
+                (quote! {}, quote! {}, quote! {})
+            } else {
+                (
+                    quote! { (#(#arg_names),*) },
+                    quote! { (#(#arg_types_with_lifetime),*) },
+                    quote! { (#(#arg_types),*) },
+                )
+            };
+            // the read params already hold the borrowed arguments, which can just be passed on
+            let read_args: Vec<_> = arg_names
+                .iter()
+                .zip(&arg_is_ref)
+                .map(|(n, is_ref)| {
+                    if *is_ref {
+                        quote! { *#n }
+                    } else {
+                        quote! { #n.clone() }
+                    }
+                })
+                .collect();
 
             let return_type = match &method.sig.output {
+                _ if is_consuming => quote! { () },
                 ReturnType::Type(_, ty) => quote! { #ty },
                 _ => quote! { () },
             };
 
+            // with flatten_result, a method returning `Result<T, E>` gets a generated method
+            // returning `Result<T, UpdateError<E>>` instead of `Result<Result<T, E>, BjwError>`
+            let flattened = if method_args.flatten_result {
+                let ReturnType::Type(_, ty) = &method.sig.output else {
+                    return TokenStream::from(
+                        Error::custom("flatten_result requires a method returning a Result")
+                            .with_span(&method.sig)
+                            .write_errors(),
+                    );
+                };
+                let Some((ok, err)) = result_types(ty) else {
+                    return TokenStream::from(
+                        Error::custom("flatten_result requires a method returning a Result")
+                            .with_span(ty)
+                            .write_errors(),
+                    );
+                };
+                if is_read {
+                    return TokenStream::from(
+                        Error::custom("flatten_result is only useful for update methods")
+                            .with_span(&method.sig)
+                            .write_errors(),
+                    );
+                }
+                Some((ok, err))
+            } else {
+                None
+            };
+
+            let serde_rename = match method_args.serde_rename {
+                Some(_) if is_read => {
+                    return TokenStream::from(
+                        Error::custom("serde_rename is only useful for update methods")
+                            .with_span(&method.sig)
+                            .write_errors(),
+                    );
+                }
+                Some(name) => quote! { #[serde(rename = #name)] },
+                None => quote! {},
+            };
+
             if is_read {
-                read_params_variants.push(quote! { #variant_name(#(#arg_types_with_lifetime),*) });
+                reads_borrow |= arg_is_ref.iter().any(|is_ref| *is_ref);
+                read_params_variants.push(quote! { #variant_name #read_fields });
                 read_return_variants.push(quote! { #variant_name(#return_type) });
                 read_match_arms.push(quote! {
-                    #read_params_ident::#variant_name(#(#arg_names),*) => #read_return_ident::#variant_name(self.#method_name(#(#cloned_args),*))
+                    #read_params_ident::#variant_name #arg_pattern => #read_return_ident::#variant_name(self.#method_name(#(#read_args),*))
                 });
 
                 read_methods.push(quote! {
+                    #(#docs)*
                     #[allow(dead_code)]
-                    pub fn #method_name(&self, #(#arg_names: #arg_types),*) -> #return_type {
-                        match #read_acces.read(&#read_params_ident::#variant_name(#(#arg_names),*)) {
+                    #vis fn #method_name(&self, #(#arg_names: #arg_types),*) -> #return_type {
+                        match #read_acces.read(&#read_params_ident::#variant_name #arg_pattern) {
                             #read_return_ident::#variant_name(value) => value,
                             _ => unreachable!()
                         }
                     }
                 });
-            } else if is_update {
-                update_params_variants.push(quote! { #variant_name(#(#arg_types),*) });
-                update_return_variants.push(quote! { #variant_name(#return_type) });
-                update_match_arms.push(quote! {
-                    #update_params_ident::#variant_name(#(#arg_names),*) => #update_return_ident::#variant_name(self.#method_name(#(#cloned_args),*))
-                });
-
-                update_methods.push(quote! {
+                async_read_methods.push(quote! {
+                    #(#docs)*
                     #[allow(dead_code)]
-                    pub fn #method_name(#mut_self, #(#arg_names: #arg_types),*) -> std::io::Result<#return_type> {
-                        match #write_access.update(&#update_params_ident::#variant_name(#(#arg_names),*))? {
-                            #update_return_ident::#variant_name(value) => Ok(value),
+                    #vis async fn #method_name(&self, #(#arg_names: #arg_types),*) -> #return_type {
+                        match self.db.read(&#read_params_ident::#variant_name #arg_pattern).await {
+                            #read_return_ident::#variant_name(value) => value,
                             _ => unreachable!()
                         }
                     }
                 });
+            } else if is_update {
+                update_params_variants.push(quote! { #serde_rename #variant_name #update_fields });
+                update_return_variants.push(quote! { #variant_name(#return_type) });
+                if is_consuming {
+                    // working on a clone keeps the state intact if the method panics
+                    update_match_arms.push(quote! {
+                        #update_params_ident::#variant_name #arg_pattern => {
+                            *self = self.clone().#method_name(#(#cloned_args),*);
+                            #update_return_ident::#variant_name(())
+                        }
+                    });
+                } else {
+                    update_match_arms.push(quote! {
+                        #update_params_ident::#variant_name #arg_pattern => #update_return_ident::#variant_name(self.#method_name(#(#cloned_args),*))
+                    });
+                }
+
+                if args.dry_run {
+                    let dry_run_name = format_ident!("{}_dry_run", method_name);
+                    let dry_run_doc = format!(
+                        " Returns what `{method_name}` would, without applying or logging it."
+                    );
+                    update_methods.push(quote! {
+                        #[doc = #dry_run_doc]
+                        #vis fn #dry_run_name(&self, #(#arg_names: #arg_types),*) -> #return_type {
+                            match #read_acces.peek_update(&#update_params_ident::#variant_name #arg_pattern) {
+                                #update_return_ident::#variant_name(value) => value,
+                                _ => unreachable!()
+                            }
+                        }
+                    });
+                    async_update_methods.push(quote! {
+                        #[doc = #dry_run_doc]
+                        #vis async fn #dry_run_name(&self, #(#arg_names: #arg_types),*) -> #return_type {
+                            match self.db.peek_update(&#update_params_ident::#variant_name #arg_pattern).await {
+                                #update_return_ident::#variant_name(value) => value,
+                                _ => unreachable!()
+                            }
+                        }
+                    });
+                }
+                if let Some((ok, err)) = flattened {
+                    update_methods.push(quote! {
+                        #(#docs)*
+                        #[allow(dead_code)]
+                        #vis fn #method_name(#mut_self, #(#arg_names: #arg_types),*) -> std::result::Result<#ok, bjw_db::UpdateError<#err>> {
+                            match self.db.update(&#update_params_ident::#variant_name #arg_pattern).map_err(bjw_db::UpdateError::Db)? {
+                                #update_return_ident::#variant_name(value) => value.map_err(bjw_db::UpdateError::Update),
+                                _ => unreachable!()
+                            }
+                        }
+                    });
+                    async_update_methods.push(quote! {
+                        #(#docs)*
+                        #[allow(dead_code)]
+                        #vis async fn #method_name(&self, #(#arg_names: #arg_types),*) -> std::result::Result<#ok, bjw_db::UpdateError<#err>> {
+                            match self.db.update(#update_params_ident::#variant_name #arg_pattern).await.map_err(bjw_db::UpdateError::Db)? {
+                                #update_return_ident::#variant_name(value) => value.map_err(bjw_db::UpdateError::Update),
+                                _ => unreachable!()
+                            }
+                        }
+                    });
+                } else {
+                    update_methods.push(quote! {
+                        #(#docs)*
+                        #[allow(dead_code)]
+                        #vis fn #method_name(#mut_self, #(#arg_names: #arg_types),*) -> bjw_db::Result<#return_type> {
+                            match self.db.update(&#update_params_ident::#variant_name #arg_pattern)? {
+                                #update_return_ident::#variant_name(value) => Ok(value),
+                                _ => unreachable!()
+                            }
+                        }
+                    });
+                    async_update_methods.push(quote! {
+                        #(#docs)*
+                        #[allow(dead_code)]
+                        #vis async fn #method_name(&self, #(#arg_names: #arg_types),*) -> bjw_db::Result<#return_type> {
+                            match self.db.update(#update_params_ident::#variant_name #arg_pattern).await? {
+                                #update_return_ident::#variant_name(value) => Ok(value),
+                                _ => unreachable!()
+                            }
+                        }
+                    });
+                }
             }
         }
     }
 
-    let original = quote! { #cloned };
+    // the data and its updates are serialized, so the type parameters have to be serializable
+    let where_clause = |extra: Vec<syn::WherePredicate>| {
+        let mut bounded = generics.clone();
+        if is_generic {
+            let predicates = &mut bounded.make_where_clause().predicates;
+            for param in generics.type_params() {
+                let ident = &param.ident;
+                predicates.push(parse_quote! {
+                    #ident: serde::Serialize + serde::de::DeserializeOwned + 'static
+                });
+            }
+            predicates.extend(extra);
+        }
+        let where_clause = bounded.where_clause;
+        quote! { #where_clause }
+    };
+    let trait_where = where_clause(if has_consuming {
+        vec![parse_quote! { #self_ty: Clone }]
+    } else {
+        Vec::new()
+    });
+    let wrapper_where = where_clause(vec![parse_quote! { #self_ty: Clone + Default }]);
+
+    // the async wrapper requires the tokio feature of bjw_db, so it is only emitted on request
+    let async_db = if args.async_api {
+        let async_where = where_clause(vec![
+            parse_quote! { #self_ty: Clone + Default + Send + Sync },
+            parse_quote! { #update_params_ty: Send },
+            parse_quote! { #update_return_ty: Send },
+        ]);
+        let async_db_struct_ident = format_ident!("{}AsyncDb", struct_name);
+        quote! {
+            #vis struct #async_db_struct_ident<#(#def_params),*> #def_where {
+                db: bjw_db::AsyncDatabase<#self_ty, #fmt<#self_ty>>,
+                path: std::path::PathBuf,
+            }
+
+            impl #impl_generics #async_db_struct_ident #ty_generics #async_where {
+                #vis async fn open<P: AsRef<std::path::Path>>(path: P) -> bjw_db::Result<Self> {
+                    let fmt = #fmt::<#self_ty>::new();
+                    let builder = Database::builder(fmt)#(#builder_options)*;
+                    let db = bjw_db::AsyncDatabase::from_builder(builder, &path).await?;
+                    Ok(Self { db, path: path.as_ref().to_path_buf() })
+                }
+
+                #vis async fn open_read_only<P: AsRef<std::path::Path>>(path: P) -> bjw_db::Result<Self> {
+                    let fmt = #fmt::<#self_ty>::new();
+                    let builder = Database::builder(fmt)#(#builder_options)*.read_only();
+                    let db = bjw_db::AsyncDatabase::from_builder(builder, &path).await?;
+                    Ok(Self { db, path: path.as_ref().to_path_buf() })
+                }
+
+                #vis fn path(&self) -> &std::path::PathBuf {
+                    &self.path
+                }
+
+                #(#async_read_methods)*
+                #(#async_update_methods)*
+
+                #vis async fn update_batch(&self, params: Vec<#update_params_ty>) -> bjw_db::Result<Vec<#update_return_ty>> {
+                    self.db.update_batch(params).await
+                }
+
+                #vis async fn create_checkpoint(&self) -> bjw_db::Result<()> {
+                    self.db.create_checkpoint().await
+                }
+
+                #vis async fn stats(&self) -> bjw_db::Result<bjw_db::DbStats> {
+                    self.db.stats().await
+                }
+
+                #vis async fn clone_data(&self) -> #self_ty {
+                    self.db.clone_data().await
+                }
+
+                #vis async fn snapshot(&self) -> (u64, #self_ty) {
+                    self.db.snapshot().await
+                }
+
+                #vis async fn with_data<Output>(&self, f: impl FnOnce(&#self_ty) -> Output) -> Output {
+                    self.db.read_with(f).await
+                }
+
+                #vis async fn delete(self) -> bjw_db::Result<()> {
+                    self.db.delete().await
+                }
+
+                #vis async fn close(self) -> bjw_db::Result<()> {
+                    self.db.close().await
+                }
+            }
+        }
+    } else {
+        quote! {}
+    };
+
+    // like the async wrapper, this requires a feature of bjw_db
+    let backup = if args.backup {
+        quote! {
+            impl #impl_generics #db_struct_ident #ty_generics #wrapper_where {
+                #vis fn backup_to<W: std::io::Write>(#mut_self, writer: W) -> bjw_db::Result<()> {
+                    #write_access.backup_to(writer)
+                }
+
+                #vis fn restore_from<R: std::io::Read, P: AsRef<std::path::Path>>(
+                    reader: R,
+                    path: P,
+                    force: bool,
+                ) -> bjw_db::Result<Self> {
+                    let fmt = #fmt::<#self_ty>::new();
+                    let db = Database::builder(fmt)#(#builder_options)*.restore_from(reader, &path, force)?;
+                    #constructor
+                }
+            }
+        }
+    } else {
+        quote! {}
+    };
+
+    // the argument types might not implement Clone either
+    let (derive_clone, subscribe) = if args.subscribe {
+        let subscribe_where = where_clause(vec![
+            parse_quote! { #self_ty: Clone + Default },
+            parse_quote! { #update_params_ty: Clone + Send + Sync },
+        ]);
+        (
+            quote! { #[derive(Clone)] },
+            quote! {
+                impl #impl_generics #db_struct_ident #ty_generics #subscribe_where {
+                    /// Receives every update once it is durable.
+                    #vis fn subscribe(#mut_self) -> std::sync::mpsc::Receiver<#update_params_ty> {
+                        #write_access.subscribe()
+                    }
+                }
+            },
+        )
+    } else {
+        (quote! {}, quote! {})
+    };
+
+    // opt-in, as the argument and return types might not all implement Debug
+    // the read params need a hidden variant for `'a` as well, if no read borrows an argument
+    let (read_phantom_variant, read_phantom_arm) = if is_generic || !reads_borrow {
+        (
+            quote! {
+                #[doc(hidden)]
+                __Phantom(
+                    std::marker::PhantomData<(&'a (), #(#phantom_types),*)>,
+                    std::convert::Infallible,
+                ),
+            },
+            quote! { #read_params_ident::__Phantom(_, never) => match *never {}, },
+        )
+    } else {
+        (quote! {}, quote! {})
+    };
+    // matching on a reference to an empty enum needs a dereference to be exhaustive
+    let update_match_params = if update_match_arms.is_empty() && !is_generic {
+        quote! { *params }
+    } else {
+        quote! { params }
+    };
+
+    let derive_debug = if args.debug {
+        quote! { #[derive(Debug)] }
+    } else {
+        quote! {}
+    };
+    // rkyv stores the updates with its own derives instead of serde's
+    let derive_rkyv = if fmt_name == "RkyvFormat" {
+        quote! {
+            #[derive(bjw_db::rkyv::Archive, bjw_db::rkyv::Serialize, bjw_db::rkyv::Deserialize)]
+            #[rkyv(crate = bjw_db::rkyv)]
+        }
+    } else {
+        quote! {}
+    };
+
+    let original = quote! { #(#cloned)* };
     let derived = quote! {
         use bjw_db::{Database, Readable, Updateable, DataFormat};
-        #import_json_fmt
+        #import_fmt
 
-        pub enum #read_params_ident<'a> {
-            #(#read_params_variants),*
+        #derive_debug
+        #vis enum #read_params_ident<'a #(, #def_params)*> #def_where {
+            #(#read_params_variants,)*
+            #read_phantom_variant
         }
 
-        pub enum #read_return_ident {
-            #(#read_return_variants),*
+        #derive_debug
+        #vis enum #read_return_ident<#(#def_params),*> #def_where {
+            #(#read_return_variants,)*
+            #phantom_variant
         }
 
-        impl Readable for #struct_name {
-            type Args<'a> = #read_params_ident<'a>;
-            type ReturnType = #read_return_ident;
+        impl #impl_generics Readable for #self_ty #trait_where {
+            type Args<'a> = #read_params_ty;
+            type ReturnType = #read_return_ty;
 
-            fn read(&self, params: &#read_params_ident<'_>) -> Self::ReturnType {
+            fn read(&self, params: &Self::Args<'_>) -> Self::ReturnType {
                 match params {
-                    #(#read_match_arms),*
+                    #(#read_match_arms,)*
+                    #read_phantom_arm
                 }
             }
         }
 
+        #derive_debug
+        #derive_clone
+        #derive_rkyv
         #[derive(serde::Serialize, serde::Deserialize)]
-        pub enum #update_params_ident {
-            #(#update_params_variants),*
+        #vis enum #update_params_ident<#(#def_params),*> #def_where {
+            #(#update_params_variants,)*
+            #serde_skip
+            #phantom_variant
         }
 
-        pub enum #update_return_ident {
-            #(#update_return_variants),*
+        #derive_debug
+        #vis enum #update_return_ident<#(#def_params),*> #def_where {
+            #(#update_return_variants,)*
+            #phantom_variant
         }
 
-        impl Updateable for #struct_name {
-            type Args = #update_params_ident;
-            type ReturnType = #update_return_ident;
+        impl #impl_generics Updateable for #self_ty #trait_where {
+            type Args = #update_params_ty;
+            type ReturnType = #update_return_ty;
 
             fn update(&mut self, params: &Self::Args) -> Self::ReturnType {
-                match params {
-                    #(#update_match_arms),*
+                match #update_match_params {
+                    #(#update_match_arms,)*
+                    #update_phantom_arm
                 }
             }
         }
 
-        pub struct #db_struct_ident {
+        #vis struct #db_struct_ident<#(#def_params),*> #def_where {
             db: #wrapped_type,
             path: std::path::PathBuf,
         }
 
-        impl #db_struct_ident {
-            pub fn open<P: AsRef<std::path::Path>>(path: P) -> std::io::Result<Self> {
-                let fmt = #fmt::<#struct_name>::new();
-                let db = Database::open(&path, fmt)?;
+        impl #impl_generics #db_struct_ident #ty_generics #wrapper_where {
+            #vis fn open<P: AsRef<std::path::Path>>(path: P) -> bjw_db::Result<Self> {
+                let fmt = #fmt::<#self_ty>::new();
+                let db = Database::builder(fmt)#(#builder_options)*.open(&path)?;
+                #constructor
+            }
+
+            #vis fn open_read_only<P: AsRef<std::path::Path>>(path: P) -> bjw_db::Result<Self> {
+                let fmt = #fmt::<#self_ty>::new();
+                let db = Database::builder(fmt)#(#builder_options)*.read_only().open(&path)?;
+                #constructor
+            }
+
+            #vis fn open_in_memory() -> bjw_db::Result<Self> {
+                let fmt = #fmt::<#self_ty>::new();
+                let db = Database::open_in_memory(fmt)?;
                 #constructor
             }
 
-            pub fn path(&self) -> &std::path::PathBuf {
+            #vis fn path(&self) -> &std::path::PathBuf {
                 &self.path
             }
 
             #(#read_methods)*
             #(#update_methods)*
 
-            pub fn create_checkpoint(#mut_self) -> std::io::Result<()> {
+            /// Applies all updates at once, either all of them or none survive a crash.
+            #vis fn update_batch(#mut_self, params: &[#update_params_ty]) -> bjw_db::Result<Vec<#update_return_ty>> {
+                self.db.update_batch(params)
+            }
+
+            #vis fn create_checkpoint(#mut_self) -> bjw_db::Result<()> {
                 #write_access.create_checkpoint()
             }
 
-            pub fn clone_data(&self) -> #struct_name {
+            /// Pulls in updates another process appended, see `bjw_db::Database::refresh`.
+            #vis fn refresh(#mut_self) -> bjw_db::Result<usize> {
+                #write_access.refresh()
+            }
+
+            #vis fn create_checkpoint_with_version(#mut_self, version: u64) -> bjw_db::Result<()> {
+                #write_access.create_checkpoint_with_version(version)
+            }
+
+            #vis fn stats(&self) -> bjw_db::Result<bjw_db::DbStats> {
+                #read_acces.stats()
+            }
+
+            #vis fn version(&self) -> u64 {
+                #read_acces.version()
+            }
+
+            #vis fn has_pending_updates(&self) -> bjw_db::Result<bool> {
+                #read_acces.has_pending_updates()
+            }
+
+            #vis fn current_log_path(&self) -> std::path::PathBuf {
+                #read_acces.current_log_path()
+            }
+
+            #vis fn current_checkpoint_path(&self) -> std::path::PathBuf {
+                #read_acces.current_checkpoint_path()
+            }
+
+            #vis fn clone_data(&self) -> #self_ty {
                 #read_acces.clone_data()
             }
 
-            pub fn delete(self) -> std::io::Result<()> {
+            /// The data together with its version, taken consistently, see
+            /// `bjw_db::Database::snapshot`.
+            #vis fn snapshot(&self) -> (u64, #self_ty) {
+                #read_acces.snapshot()
+            }
+
+            /// A clone of the data that does not hold any lock, see
+            /// `bjw_db::SharedDatabase::snapshot_arc`.
+            #vis fn snapshot_arc(&self) -> std::sync::Arc<#self_ty> {
+                #snapshot_arc
+            }
+
+            #spawn_autocheckpoint
+
+            #vis fn with_data<Output>(&self, f: impl FnOnce(&#self_ty) -> Output) -> Output {
+                #read_acces.with_data(f)
+            }
+
+            #vis fn delete(self) -> bjw_db::Result<()> {
                 #into_inner.delete()
             }
+
+            /// Syncs the log and closes the database, which dropping it does as well, but
+            /// without reporting errors.
+            #vis fn close(self) -> bjw_db::Result<()> {
+                #into_inner.close()
+            }
         }
+
+        #async_db
+        #backup
+        #subscribe
     };
 
     quote! {
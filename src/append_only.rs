@@ -0,0 +1,64 @@
+use serde::{de::DeserializeOwned, Serialize};
+use std::path::PathBuf;
+
+use crate::{DataFormat, Database, DbStats, Readable, Result, Updateable};
+
+/// A database opened for ingesting updates only, see [`Database::open_append_only`]. Its state is
+/// neither loaded nor kept up to date, so opening and appending stay cheap however large it
+/// grows, and there is no way to read it: reopen the database normally for that.
+pub struct AppendOnlyDatabase<T, F> {
+    db: Database<T, F>,
+}
+
+impl<T, F> AppendOnlyDatabase<T, F> {
+    pub(crate) fn new(db: Database<T, F>) -> Self {
+        AppendOnlyDatabase { db }
+    }
+}
+
+impl<T, F> AppendOnlyDatabase<T, F>
+where
+    T: Serialize + DeserializeOwned + Readable + Updateable,
+    F: DataFormat<Data = T>,
+{
+    /// See [`Database::path`].
+    pub fn path(&self) -> &PathBuf {
+        self.db.path()
+    }
+
+    /// See [`Database::version`].
+    pub fn version(&self) -> u64 {
+        self.db.version()
+    }
+
+    /// Appends `parameters` to the log without applying them, see [`Database::append`].
+    pub fn append(&mut self, parameters: &<T as Updateable>::Args) -> Result<()> {
+        self.db.append(parameters)
+    }
+
+    /// Folds the log into a new checkpoint, see [`Database::create_checkpoint`]. This loads the
+    /// state just for writing the checkpoint, and drops it again afterwards.
+    pub fn create_checkpoint(&mut self) -> Result<()> {
+        self.db.create_checkpoint()
+    }
+
+    /// See [`Database::flush`].
+    pub fn flush(&mut self) -> Result<()> {
+        self.db.flush()
+    }
+
+    /// See [`Database::close`].
+    pub fn close(self) -> Result<()> {
+        self.db.close()
+    }
+
+    /// See [`Database::stats`].
+    pub fn stats(&self) -> Result<DbStats> {
+        self.db.stats()
+    }
+
+    /// See [`Database::has_pending_updates`].
+    pub fn has_pending_updates(&self) -> Result<bool> {
+        self.db.has_pending_updates()
+    }
+}
@@ -0,0 +1,164 @@
+use serde::{de::DeserializeOwned, Serialize};
+use std::{path::Path, sync::Arc};
+use tokio::sync::RwLock;
+
+use crate::{
+    BjwError, DataFormat, Database, DatabaseBuilder, DbStats, Readable, Result, Updateable,
+};
+
+/// Wraps a [`Database`] for use from async code. Everything that blocks, i.e. serializing,
+/// writing and syncing files, runs on tokio's blocking thread pool, while reads are served
+/// from memory right away. Updates still go through [`Database::update`], so the log is
+/// appended to (and synced) before the update is applied, and the files are exactly the same
+/// as those of a synchronously opened database.
+///
+/// `#[derive_bjw_db(async)]` additionally generates a `<Name>AsyncDb` wrapper with async
+/// methods:
+///
+#[cfg_attr(all(feature = "derive", feature = "json"), doc = "```")]
+#[cfg_attr(not(all(feature = "derive", feature = "json")), doc = "```ignore")]
+/// use bjw_db::derive_bjw_db;
+/// use serde::{Deserialize, Serialize};
+/// use std::collections::BTreeMap;
+///
+/// #[derive(Debug, Default, Serialize, Deserialize, Clone)]
+/// struct KeyValueStore {
+///     store: BTreeMap<String, String>,
+/// }
+///
+/// #[derive_bjw_db(async)]
+/// impl KeyValueStore {
+///     pub fn insert(&mut self, key: String, value: String) {
+///         self.store.insert(key, value);
+///     }
+///
+///     pub fn get(&self, key: &str) -> Option<String> {
+///         self.store.get(key).cloned()
+///     }
+/// }
+///
+/// #[tokio::main(flavor = "current_thread")]
+/// async fn main() -> bjw_db::Result<()> {
+///     let tempdir = tempfile::TempDir::new()?;
+///     let db = KeyValueStoreAsyncDb::open(tempdir.path().join("kv-store")).await?;
+///     db.insert("key".to_string(), "value".to_string()).await?;
+///     assert_eq!(db.get("key").await, Some("value".to_string()));
+///     Ok(())
+/// }
+/// ```
+pub struct AsyncDatabase<T, F> {
+    db: Arc<RwLock<Database<T, F>>>,
+}
+
+impl<T, F> AsyncDatabase<T, F>
+where
+    T: Default + Serialize + DeserializeOwned + Readable + Updateable + Send + Sync + 'static,
+    <T as Updateable>::Args: Send + 'static,
+    <T as Updateable>::ReturnType: Send + 'static,
+    F: DataFormat<Data = T> + Send + Sync + 'static,
+{
+    /// See [`Database::open`].
+    pub async fn open<P: AsRef<Path>>(path: P, fmt: F) -> Result<Self> {
+        Self::from_builder(Database::builder(fmt), path).await
+    }
+
+    /// Opens the database with the options collected by `builder`.
+    pub async fn from_builder<P: AsRef<Path>>(
+        builder: DatabaseBuilder<T, F>,
+        path: P,
+    ) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let db = blocking(move || builder.open(path)).await??;
+        Ok(AsyncDatabase {
+            db: Arc::new(RwLock::new(db)),
+        })
+    }
+
+    pub async fn read(
+        &self,
+        parameters: &<T as Readable>::Args<'_>,
+    ) -> <T as Readable>::ReturnType {
+        self.db.read().await.read(parameters)
+    }
+
+    /// Runs `f` on the current state while holding the read lock, e.g. to borrow from it
+    /// instead of cloning.
+    pub async fn read_with<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        f(self.db.read().await.read_all())
+    }
+
+    /// See [`Database::update`].
+    pub async fn update(
+        &self,
+        parameters: <T as Updateable>::Args,
+    ) -> Result<<T as Updateable>::ReturnType> {
+        let db = self.db.clone();
+        blocking(move || db.blocking_write().update(&parameters)).await?
+    }
+
+    /// See [`Database::update_batch`].
+    pub async fn update_batch(
+        &self,
+        parameters: Vec<<T as Updateable>::Args>,
+    ) -> Result<Vec<<T as Updateable>::ReturnType>> {
+        let db = self.db.clone();
+        blocking(move || db.blocking_write().update_batch(&parameters)).await?
+    }
+
+    pub async fn create_checkpoint(&self) -> Result<()> {
+        let db = self.db.clone();
+        blocking(move || db.blocking_write().create_checkpoint()).await?
+    }
+
+    pub async fn stats(&self) -> Result<DbStats> {
+        let db = self.db.clone();
+        blocking(move || db.blocking_read().stats()).await?
+    }
+
+    /// Fails if another operation on this database is still in progress, e.g. because its
+    /// future was dropped but the blocking part keeps running.
+    pub async fn delete(self) -> Result<()> {
+        let db = Arc::try_unwrap(self.db)
+            .map_err(|_| BjwError::Io(std::io::Error::other("Database is still in use")))?;
+        blocking(move || db.into_inner().delete()).await?
+    }
+
+    /// See [`Database::close`], fails like [`AsyncDatabase::delete`] if the database is in use.
+    pub async fn close(self) -> Result<()> {
+        let db = Arc::try_unwrap(self.db)
+            .map_err(|_| BjwError::Io(std::io::Error::other("Database is still in use")))?;
+        blocking(move || db.into_inner().close()).await?
+    }
+}
+
+impl<T: Clone, F> AsyncDatabase<T, F> {
+    pub async fn clone_data(&self) -> T {
+        self.db.read().await.clone_data()
+    }
+
+    /// See [`Database::snapshot`].
+    pub async fn snapshot(&self) -> (u64, T) {
+        self.db.read().await.snapshot()
+    }
+
+    /// See [`Database::peek_update`].
+    pub async fn peek_update(
+        &self,
+        parameters: &<T as Updateable>::Args,
+    ) -> <T as Updateable>::ReturnType
+    where
+        T: Updateable,
+    {
+        self.db.read().await.peek_update(parameters)
+    }
+}
+
+async fn blocking<R, C>(f: C) -> Result<R>
+where
+    R: Send + 'static,
+    C: FnOnce() -> R + Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| BjwError::Io(std::io::Error::other(e)))
+}
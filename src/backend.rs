@@ -0,0 +1,721 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::{File, OpenOptions, TryLockError},
+    io::{BufRead, BufReader, BufWriter, Cursor, ErrorKind, Write},
+    ops::Deref,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+#[cfg(any(test, feature = "testing"))]
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+type Result<T> = std::io::Result<T>;
+
+/// Abstracts all storage operations of a [`Database`](crate::Database), which only ever works on
+/// the files directly inside its directory.
+pub trait Backend: Send + Sync {
+    /// Whether a file or directory exists at `path`.
+    fn exists(&self, path: &Path) -> Result<bool>;
+    /// Whether a directory exists at `path`. The default is `exists`, for backends whose paths
+    /// are never files and directories at once.
+    fn is_dir(&self, path: &Path) -> Result<bool> {
+        self.exists(path)
+    }
+    fn create_dir_all(&self, path: &Path) -> Result<()>;
+    fn read(&self, path: &Path) -> Result<Vec<u8>>;
+
+    /// Reads the file at `path` by mapping it into memory, which the database only does for
+    /// checkpoints (that are replaced by renaming, never modified) and only if their format
+    /// benefits from it, see [`DataFormat::supports_mmap`](crate::DataFormat::supports_mmap).
+    /// The default reads the whole file, [`FsBackend`] maps it with the `mmap` feature.
+    fn read_mapped(&self, path: &Path) -> Result<Box<dyn Deref<Target = [u8]> + '_>> {
+        Ok(Box::new(self.read(path)?))
+    }
+
+    /// Opens the file at `path` for reading it piece by piece. The default reads the whole file,
+    /// so backends should override it if they can do better.
+    fn open_reader(&self, path: &Path) -> Result<Box<dyn BufRead + '_>> {
+        Ok(Box::new(Cursor::new(self.read(path)?)))
+    }
+    /// Creates or truncates the file at `path`, writes `data` and syncs it.
+    fn write(&self, path: &Path, data: &[u8]) -> Result<()>;
+    /// Creates the file at `path` empty unless it exists already, returning whether it did.
+    /// Unlike checking `exists` before `write`, this never truncates a file someone else just
+    /// created. The default does exactly that though, so backends should override it with an
+    /// atomic operation if they have one.
+    fn create_new(&self, path: &Path) -> Result<bool> {
+        if self.exists(path)? {
+            return Ok(false);
+        }
+        self.write(path, &[])?;
+        Ok(true)
+    }
+    /// Appends `data` to the existing file at `path`, syncing it only if `sync` is set.
+    fn append(&self, path: &Path, data: &[u8], sync: bool) -> Result<()>;
+
+    /// Opens the existing file at `path` for appending to it repeatedly, which saves reopening
+    /// it for every update. Without one (the default), every update goes through `append`.
+    fn log_writer(&self, _path: &Path) -> Result<Option<Box<dyn LogWriter>>> {
+        Ok(None)
+    }
+    /// Creates or truncates the file at `path` for writing it piece by piece, it is complete once
+    /// synced. Without one (the default), large files are written through `write` at once.
+    fn file_writer(&self, _path: &Path) -> Result<Option<Box<dyn LogWriter>>> {
+        Ok(None)
+    }
+    fn rename(&self, from: &Path, to: &Path) -> Result<()>;
+
+    /// The size of the file at `path` in bytes. The default reads the whole file, so backends
+    /// should override it if they can do better.
+    fn file_size(&self, path: &Path) -> Result<u64> {
+        Ok(self.read(path)?.len() as u64)
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<()>;
+    fn remove_dir_all(&self, path: &Path) -> Result<()>;
+    /// Lists the names of all files directly inside the directory at `path`.
+    fn list_files(&self, path: &Path) -> Result<Vec<String>>;
+    /// Makes creating and renaming files inside the directory at `path` durable.
+    fn sync_dir(&self, path: &Path) -> Result<()>;
+
+    /// Acquires an exclusive lock on the lock file at `path` (creating it if required), which is
+    /// held until the returned guard is dropped. Fails with `WouldBlock` if it is already locked,
+    /// unless `wait` is set. Backends that cannot be shared between processes do not need to lock
+    /// anything.
+    fn lock(&self, _path: &Path, _wait: bool) -> Result<Box<dyn Send + Sync>> {
+        Ok(Box::new(()))
+    }
+}
+
+/// A file kept open for writing, see [`Backend::log_writer`] and [`Backend::file_writer`].
+/// Written data may be buffered until it is synced.
+pub trait LogWriter: Write + Send + Sync {
+    /// Writes out everything buffered and makes it durable.
+    fn sync(&mut self) -> Result<()>;
+}
+
+impl LogWriter for BufWriter<File> {
+    fn sync(&mut self) -> Result<()> {
+        self.flush()?;
+        self.get_ref().sync_all()
+    }
+}
+
+/// Stores the database in a directory on the local file system.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FsBackend;
+
+impl Backend for FsBackend {
+    fn exists(&self, path: &Path) -> Result<bool> {
+        Ok(path.exists())
+    }
+
+    fn is_dir(&self, path: &Path) -> Result<bool> {
+        Ok(path.is_dir())
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<()> {
+        std::fs::create_dir_all(path)
+    }
+
+    fn read(&self, path: &Path) -> Result<Vec<u8>> {
+        std::fs::read(path)
+    }
+
+    #[cfg(feature = "mmap")]
+    fn read_mapped(&self, path: &Path) -> Result<Box<dyn Deref<Target = [u8]> + '_>> {
+        let file = File::open(path)?;
+        // empty files cannot be mapped on all platforms
+        if file.metadata()?.len() == 0 {
+            return Ok(Box::new(Vec::new()));
+        }
+        // SAFETY: the file must not be modified while it is mapped, which holds for checkpoints
+        // as long as nobody but the database itself writes to its directory
+        let map = unsafe { memmap2::Mmap::map(&file)? };
+        Ok(Box::new(map))
+    }
+
+    fn open_reader(&self, path: &Path) -> Result<Box<dyn BufRead + '_>> {
+        Ok(Box::new(BufReader::new(File::open(path)?)))
+    }
+
+    fn write(&self, path: &Path, data: &[u8]) -> Result<()> {
+        let mut file = File::create(path)?;
+        file.write_all(data)?;
+        file.sync_all()
+    }
+
+    fn create_new(&self, path: &Path) -> Result<bool> {
+        match OpenOptions::new().write(true).create_new(true).open(path) {
+            Ok(file) => file.sync_all().map(|_| true),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn append(&self, path: &Path, data: &[u8], sync: bool) -> Result<()> {
+        let mut file = OpenOptions::new().append(true).open(path)?;
+        file.write_all(data)?;
+        if sync {
+            file.sync_all()?;
+        }
+        Ok(())
+    }
+
+    fn log_writer(&self, path: &Path) -> Result<Option<Box<dyn LogWriter>>> {
+        let file = OpenOptions::new().append(true).open(path)?;
+        Ok(Some(Box::new(BufWriter::new(file))))
+    }
+
+    fn file_writer(&self, path: &Path) -> Result<Option<Box<dyn LogWriter>>> {
+        Ok(Some(Box::new(BufWriter::new(File::create(path)?))))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        std::fs::rename(from, to)
+    }
+
+    fn file_size(&self, path: &Path) -> Result<u64> {
+        Ok(std::fs::metadata(path)?.len())
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<()> {
+        std::fs::remove_file(path)
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> Result<()> {
+        std::fs::remove_dir_all(path)
+    }
+
+    fn list_files(&self, path: &Path) -> Result<Vec<String>> {
+        let mut files = Vec::new();
+        for entry in std::fs::read_dir(path)? {
+            let entry = entry?;
+            if entry.metadata()?.is_file() {
+                if let Ok(filename) = entry.file_name().into_string() {
+                    files.push(filename);
+                }
+            }
+        }
+        Ok(files)
+    }
+
+    fn sync_dir(&self, path: &Path) -> Result<()> {
+        File::open(path)?.sync_all()
+    }
+
+    fn lock(&self, path: &Path, wait: bool) -> Result<Box<dyn Send + Sync>> {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)?;
+        if wait {
+            file.lock()?;
+        } else {
+            file.try_lock().map_err(|e| match e {
+                TryLockError::WouldBlock => std::io::Error::new(
+                    ErrorKind::WouldBlock,
+                    format!("{} is locked by another process", path.display()),
+                ),
+                TryLockError::Error(e) => e,
+            })?;
+        }
+        // the lock is released once the file is closed
+        Ok(Box::new(file))
+    }
+}
+
+/// Keeps the database in memory only, which is handy for tests. Clones share the same storage,
+/// so a database can be re-opened from a clone as long as one of them is alive.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryBackend {
+    storage: Arc<Mutex<MemoryStorage>>,
+}
+
+#[derive(Debug, Default)]
+struct MemoryStorage {
+    dirs: BTreeSet<PathBuf>,
+    files: BTreeMap<PathBuf, Vec<u8>>,
+}
+
+impl MemoryBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn not_found(path: &Path) -> std::io::Error {
+        std::io::Error::new(ErrorKind::NotFound, format!("{} not found", path.display()))
+    }
+}
+
+impl Backend for MemoryBackend {
+    fn exists(&self, path: &Path) -> Result<bool> {
+        let storage = self.storage.lock().unwrap();
+        Ok(storage.dirs.contains(path) || storage.files.contains_key(path))
+    }
+
+    fn is_dir(&self, path: &Path) -> Result<bool> {
+        Ok(self.storage.lock().unwrap().dirs.contains(path))
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<()> {
+        let mut storage = self.storage.lock().unwrap();
+        storage.dirs.extend(path.ancestors().map(Path::to_path_buf));
+        Ok(())
+    }
+
+    fn read(&self, path: &Path) -> Result<Vec<u8>> {
+        let storage = self.storage.lock().unwrap();
+        storage
+            .files
+            .get(path)
+            .cloned()
+            .ok_or_else(|| Self::not_found(path))
+    }
+
+    fn write(&self, path: &Path, data: &[u8]) -> Result<()> {
+        let mut storage = self.storage.lock().unwrap();
+        storage.files.insert(path.to_path_buf(), data.to_vec());
+        Ok(())
+    }
+
+    fn create_new(&self, path: &Path) -> Result<bool> {
+        let mut storage = self.storage.lock().unwrap();
+        if storage.files.contains_key(path) || storage.dirs.contains(path) {
+            return Ok(false);
+        }
+        storage.files.insert(path.to_path_buf(), Vec::new());
+        Ok(true)
+    }
+
+    fn append(&self, path: &Path, data: &[u8], _sync: bool) -> Result<()> {
+        let mut storage = self.storage.lock().unwrap();
+        let file = storage
+            .files
+            .get_mut(path)
+            .ok_or_else(|| Self::not_found(path))?;
+        file.extend_from_slice(data);
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        let mut storage = self.storage.lock().unwrap();
+        let data = storage
+            .files
+            .remove(from)
+            .ok_or_else(|| Self::not_found(from))?;
+        storage.files.insert(to.to_path_buf(), data);
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<()> {
+        let mut storage = self.storage.lock().unwrap();
+        storage
+            .files
+            .remove(path)
+            .map(|_| ())
+            .ok_or_else(|| Self::not_found(path))
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> Result<()> {
+        let mut storage = self.storage.lock().unwrap();
+        if !storage.dirs.contains(path) {
+            return Err(Self::not_found(path));
+        }
+        storage.dirs.retain(|dir| !dir.starts_with(path));
+        storage.files.retain(|file, _| !file.starts_with(path));
+        Ok(())
+    }
+
+    fn list_files(&self, path: &Path) -> Result<Vec<String>> {
+        let storage = self.storage.lock().unwrap();
+        if !storage.dirs.contains(path) {
+            return Err(Self::not_found(path));
+        }
+        Ok(storage
+            .files
+            .keys()
+            .filter(|file| file.parent() == Some(path))
+            .filter_map(|file| file.file_name()?.to_str().map(str::to_string))
+            .collect())
+    }
+
+    fn sync_dir(&self, _path: &Path) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Wraps another backend and fails one of its writes on purpose, e.g. to test what a crash at
+/// that point would leave behind. Writing, appending, renaming and removing files count as
+/// writes. Clones share the same counter, so the fault can be set up after opening a database.
+/// Syncing appends are counted separately as well, see [`FaultInjectingBackend::fail_nth_sync`].
+/// Only available with the `testing` feature.
+#[cfg(any(test, feature = "testing"))]
+#[derive(Clone, Debug, Default)]
+pub struct FaultInjectingBackend<B> {
+    inner: B,
+    faults: Arc<Faults>,
+}
+
+#[cfg(any(test, feature = "testing"))]
+#[derive(Debug, Default)]
+struct Faults {
+    writes: AtomicUsize,
+    /// The number of the write that fails, 0 for none.
+    fail_at: AtomicUsize,
+    syncs: AtomicUsize,
+    /// The number of the syncing append whose sync fails, 0 for none.
+    fail_sync_at: AtomicUsize,
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl<B: Backend> FaultInjectingBackend<B> {
+    pub fn new(inner: B) -> Self {
+        FaultInjectingBackend {
+            inner,
+            faults: Arc::default(),
+        }
+    }
+
+    /// Fails the `n`th write from now on, counting from 1, while all others go through.
+    pub fn fail_nth_write(&self, n: usize) {
+        let writes = self.faults.writes.load(Ordering::SeqCst);
+        self.faults.fail_at.store(writes + n, Ordering::SeqCst);
+    }
+
+    /// Fails the sync of the `n`th syncing append from now on, counting from 1. Its data is still
+    /// appended, like an fsync that fails after the data reached the OS.
+    pub fn fail_nth_sync(&self, n: usize) {
+        let syncs = self.faults.syncs.load(Ordering::SeqCst);
+        self.faults.fail_sync_at.store(syncs + n, Ordering::SeqCst);
+    }
+
+    /// The number of writes so far, including the failed one.
+    pub fn writes(&self) -> usize {
+        self.faults.writes.load(Ordering::SeqCst)
+    }
+
+    fn write_op<R>(&self, path: &Path, op: impl FnOnce(&B) -> Result<R>) -> Result<R> {
+        let write = self.faults.writes.fetch_add(1, Ordering::SeqCst) + 1;
+        if write == self.faults.fail_at.load(Ordering::SeqCst) {
+            return Err(std::io::Error::other(format!(
+                "injected fault writing {}",
+                path.display()
+            )));
+        }
+        op(&self.inner)
+    }
+}
+
+// leaves out `log_writer` and `file_writer`, so that every write goes through the counter
+#[cfg(any(test, feature = "testing"))]
+impl<B: Backend> Backend for FaultInjectingBackend<B> {
+    fn exists(&self, path: &Path) -> Result<bool> {
+        self.inner.exists(path)
+    }
+
+    fn is_dir(&self, path: &Path) -> Result<bool> {
+        self.inner.is_dir(path)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<()> {
+        self.inner.create_dir_all(path)
+    }
+
+    fn read(&self, path: &Path) -> Result<Vec<u8>> {
+        self.inner.read(path)
+    }
+
+    fn read_mapped(&self, path: &Path) -> Result<Box<dyn Deref<Target = [u8]> + '_>> {
+        self.inner.read_mapped(path)
+    }
+
+    fn open_reader(&self, path: &Path) -> Result<Box<dyn BufRead + '_>> {
+        self.inner.open_reader(path)
+    }
+
+    fn write(&self, path: &Path, data: &[u8]) -> Result<()> {
+        self.write_op(path, |inner| inner.write(path, data))
+    }
+
+    fn create_new(&self, path: &Path) -> Result<bool> {
+        self.write_op(path, |inner| inner.create_new(path))
+    }
+
+    fn append(&self, path: &Path, data: &[u8], sync: bool) -> Result<()> {
+        self.write_op(path, |inner| {
+            if !sync {
+                return inner.append(path, data, sync);
+            }
+            let sync = self.faults.syncs.fetch_add(1, Ordering::SeqCst) + 1;
+            if sync != self.faults.fail_sync_at.load(Ordering::SeqCst) {
+                return inner.append(path, data, true);
+            }
+            inner.append(path, data, false)?;
+            Err(std::io::Error::other(format!(
+                "injected fault syncing {}",
+                path.display()
+            )))
+        })
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        self.write_op(from, |inner| inner.rename(from, to))
+    }
+
+    fn file_size(&self, path: &Path) -> Result<u64> {
+        self.inner.file_size(path)
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<()> {
+        self.write_op(path, |inner| inner.remove_file(path))
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> Result<()> {
+        self.write_op(path, |inner| inner.remove_dir_all(path))
+    }
+
+    fn list_files(&self, path: &Path) -> Result<Vec<String>> {
+        self.inner.list_files(path)
+    }
+
+    fn sync_dir(&self, path: &Path) -> Result<()> {
+        self.inner.sync_dir(path)
+    }
+
+    fn lock(&self, path: &Path, wait: bool) -> Result<Box<dyn Send + Sync>> {
+        self.inner.lock(path, wait)
+    }
+}
+
+/// Stores the database in an object store such as S3, see the `object_store` crate. The path of
+/// the database is used as the prefix of its objects, and there is no need to create it first.
+///
+/// Object stores cannot append, so every append to a file is stored as an object of its own,
+/// numbered in order below the file's object (`logfile.0/00000000000000000001` and so on), and
+/// reading the file concatenates them. Appending costs a single upload that way, but reading a
+/// log takes a request per update appended to it: keep logs short with
+/// [`DatabaseBuilder::auto_checkpoint_bytes`](crate::DatabaseBuilder::auto_checkpoint_bytes).
+/// Renaming copies the object before deleting the original, which still leaves one of its
+/// complete versions (or both) after a crash. The appended objects are moved one by one after
+/// it, which the database only does for logs it marks as compacted and never reads again.
+/// Nothing is locked, so a database must not be opened by more than one process at a time.
+#[cfg(feature = "object_store")]
+pub struct ObjectStoreBackend {
+    store: Arc<dyn object_store::ObjectStore>,
+    runtime: tokio::runtime::Runtime,
+    /// The number of the last object appended to each file this backend appended to.
+    appended: Mutex<std::collections::HashMap<PathBuf, u64>>,
+}
+
+#[cfg(feature = "object_store")]
+impl ObjectStoreBackend {
+    /// Uses `store`, whose requests are driven by a runtime of its own, so the backend must not
+    /// be used from within an async task.
+    pub fn new(store: Arc<dyn object_store::ObjectStore>) -> Result<Self> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        Ok(ObjectStoreBackend {
+            store,
+            runtime,
+            appended: Mutex::default(),
+        })
+    }
+
+    fn location(path: &Path) -> object_store::path::Path {
+        path.components()
+            .filter_map(|component| match component {
+                std::path::Component::Normal(part) => part.to_str(),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn list(&self, path: &Path) -> Result<object_store::ListResult> {
+        let prefix = Self::location(path);
+        let prefix = (!prefix.as_ref().is_empty()).then_some(&prefix);
+        Ok(self
+            .runtime
+            .block_on(self.store.list_with_delimiter(prefix))?)
+    }
+
+    /// The objects appended to the file at `path` in the order they were appended, see
+    /// [`Backend::append`]. Their numbers are zero-padded, so they sort by name.
+    fn list_appended(&self, path: &Path) -> Result<Vec<object_store::ObjectMeta>> {
+        let mut appended = self.list(path)?.objects;
+        appended.sort_by(|a, b| a.location.cmp(&b.location));
+        Ok(appended)
+    }
+
+    fn remove_appended(&self, path: &Path) -> Result<()> {
+        for meta in self.list_appended(path)? {
+            self.runtime.block_on(self.store.delete(&meta.location))?;
+        }
+        self.appended.lock().unwrap().remove(path);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "object_store")]
+impl Backend for ObjectStoreBackend {
+    fn exists(&self, path: &Path) -> Result<bool> {
+        match self.file_size(path) {
+            Ok(_) => Ok(true),
+            // directories only exist by the objects inside them
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                let list = self.list(path)?;
+                Ok(!list.objects.is_empty() || !list.common_prefixes.is_empty())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn is_dir(&self, path: &Path) -> Result<bool> {
+        let list = self.list(path)?;
+        Ok(!list.objects.is_empty() || !list.common_prefixes.is_empty())
+    }
+
+    fn create_dir_all(&self, _path: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    fn read(&self, path: &Path) -> Result<Vec<u8>> {
+        let appended = self.list_appended(path)?;
+        self.runtime.block_on(async {
+            let result = self.store.get(&Self::location(path)).await?;
+            let mut data = result.bytes().await?.to_vec();
+            for meta in appended {
+                let result = self.store.get(&meta.location).await?;
+                data.extend_from_slice(&result.bytes().await?);
+            }
+            Ok(data)
+        })
+    }
+
+    fn write(&self, path: &Path, data: &[u8]) -> Result<()> {
+        self.remove_appended(path)?;
+        let payload = data.to_vec().into();
+        self.runtime
+            .block_on(self.store.put(&Self::location(path), payload))?;
+        Ok(())
+    }
+
+    fn create_new(&self, path: &Path) -> Result<bool> {
+        let location = Self::location(path);
+        let put = self.store.put_opts(
+            &location,
+            Vec::new().into(),
+            object_store::PutMode::Create.into(),
+        );
+        match self.runtime.block_on(put) {
+            Ok(_) => Ok(true),
+            Err(object_store::Error::AlreadyExists { .. }) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn append(&self, path: &Path, data: &[u8], _sync: bool) -> Result<()> {
+        let mut appended = self.appended.lock().unwrap();
+        let mut last = match appended.get(path) {
+            Some(last) => *last,
+            None => {
+                // only existing files can be appended to
+                self.file_size(path)?;
+                let last = self
+                    .list_appended(path)?
+                    .last()
+                    .and_then(|meta| meta.location.filename().and_then(|name| name.parse().ok()));
+                last.unwrap_or_default()
+            }
+        };
+        loop {
+            let number = last + 1;
+            let location = Self::location(path).child(format!("{number:020}"));
+            let put = self.store.put_opts(
+                &location,
+                data.to_vec().into(),
+                object_store::PutMode::Create.into(),
+            );
+            match self.runtime.block_on(put) {
+                Ok(_) => {
+                    appended.insert(path.to_path_buf(), number);
+                    return Ok(());
+                }
+                // never overwrites what someone else appended in the meantime
+                Err(object_store::Error::AlreadyExists { .. }) => last = number,
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        self.remove_appended(to)?;
+        let appended = self.list_appended(from)?;
+        let (from_location, to_location) = (Self::location(from), Self::location(to));
+        self.runtime
+            .block_on(self.store.rename(&from_location, &to_location))?;
+        for meta in appended {
+            let Some(name) = meta.location.filename() else {
+                continue;
+            };
+            let to_appended = to_location.child(name);
+            self.runtime
+                .block_on(self.store.rename(&meta.location, &to_appended))?;
+        }
+        self.appended.lock().unwrap().remove(from);
+        Ok(())
+    }
+
+    fn file_size(&self, path: &Path) -> Result<u64> {
+        let meta = self
+            .runtime
+            .block_on(self.store.head(&Self::location(path)))?;
+        let appended = self.list_appended(path)?;
+        Ok(meta.size + appended.iter().map(|meta| meta.size).sum::<u64>())
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<()> {
+        // deleting a missing object succeeds on some stores
+        self.file_size(path)?;
+        self.remove_appended(path)?;
+        Ok(self
+            .runtime
+            .block_on(self.store.delete(&Self::location(path)))?)
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> Result<()> {
+        if !self.exists(path)? {
+            return Err(std::io::Error::new(
+                ErrorKind::NotFound,
+                format!("{} not found", path.display()),
+            ));
+        }
+        let mut prefixes = vec![Self::location(path)];
+        while let Some(prefix) = prefixes.pop() {
+            let list = self
+                .runtime
+                .block_on(self.store.list_with_delimiter(Some(&prefix)))?;
+            for meta in list.objects {
+                self.runtime.block_on(self.store.delete(&meta.location))?;
+            }
+            prefixes.extend(list.common_prefixes);
+        }
+        Ok(())
+    }
+
+    fn list_files(&self, path: &Path) -> Result<Vec<String>> {
+        Ok(self
+            .list(path)?
+            .objects
+            .iter()
+            .filter_map(|meta| meta.location.filename().map(str::to_string))
+            .collect())
+    }
+
+    fn sync_dir(&self, _path: &Path) -> Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,186 @@
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    collections::BTreeMap,
+    io::{ErrorKind, Read, Write},
+    path::{Component, Path},
+};
+
+use crate::{
+    BjwError, DataFormat, Database, DatabaseBuilder, Readable, Result, Updateable, HASH_EXT,
+    TIMESTAMP_MAGIC,
+};
+
+impl<T, F> Database<T, F>
+where
+    T: Serialize + DeserializeOwned + Readable + Updateable,
+    F: DataFormat<Data = T>,
+{
+    /// Writes the files of the current version, i.e. the version file, the checkpoint with its
+    /// hash and the log, to `writer` as a tar archive. The database is borrowed while the archive
+    /// is written, so no update or checkpoint can interleave and the files always match.
+    /// Updates still buffered by the log writer are flushed first (see [`Database::flush`]), so
+    /// the archive holds all of the state. Restore it with [`Database::restore_from`].
+    pub fn backup_to<W: Write>(&mut self, writer: W) -> Result<()> {
+        self.flush()?;
+        let naming = &self.options.naming;
+        let checkpoint = naming.checkpoint(self.version);
+        let log = naming.log(self.version);
+        let hash = naming.with_ext(&checkpoint, HASH_EXT);
+        // the log is only created on the first update, but a restored database should have one;
+        // its segments are simply concatenated, with the magic of timestamped ones kept only once
+        let mut log_data = Vec::new();
+        let mut timestamped = None;
+        for (_, filename) in self.log_segments(self.version)? {
+            let segment = self.backend.read(&self.path.join(filename))?;
+            if segment.is_empty() {
+                continue;
+            }
+            let segment_timestamped = segment.starts_with(TIMESTAMP_MAGIC);
+            match timestamped.replace(segment_timestamped) {
+                Some(previous) if previous != segment_timestamped => {
+                    return Err(std::io::Error::new(
+                        ErrorKind::Unsupported,
+                        "The log mixes segments with and without timestamps, create a checkpoint \
+                         before backing it up",
+                    )
+                    .into());
+                }
+                Some(_) if segment_timestamped => {
+                    log_data.extend(&segment[TIMESTAMP_MAGIC.len()..]);
+                }
+                _ => log_data.extend(segment),
+            }
+        }
+        let mut files = vec![
+            (
+                naming.version_file.clone(),
+                self.version.to_string().into_bytes(),
+            ),
+            (
+                checkpoint.clone(),
+                self.backend.read(&self.path.join(&checkpoint))?,
+            ),
+            (log, log_data),
+        ];
+        // databases created before hashes were introduced have no hash files
+        let hash_path = self.path.join(&hash);
+        if self.backend.exists(&hash_path)? {
+            files.push((hash, self.backend.read(&hash_path)?));
+        }
+
+        let mut archive = tar::Builder::new(writer);
+        for (filename, data) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            archive.append_data(&mut header, filename, data.as_slice())?;
+        }
+        archive.into_inner()?.flush()?;
+        Ok(())
+    }
+}
+
+impl<T, F> Database<T, F>
+where
+    T: Default + Serialize + DeserializeOwned + Readable + Updateable,
+    F: DataFormat<Data = T>,
+{
+    /// Restores a database written by [`Database::backup_to`] into `path` and opens it. See
+    /// [`DatabaseBuilder::restore_from`].
+    pub fn restore_from<R: Read, P: AsRef<Path>>(
+        reader: R,
+        path: P,
+        fmt: F,
+        force: bool,
+    ) -> Result<Database<T, F>> {
+        Self::builder(fmt).restore_from(reader, path, force)
+    }
+}
+
+impl<T, F> DatabaseBuilder<T, F>
+where
+    T: Default + Serialize + DeserializeOwned + Readable + Updateable,
+    F: DataFormat<Data = T>,
+{
+    /// Extracts a backup written by [`Database::backup_to`] into `path` and opens the database
+    /// from there. The archive is checked to contain a valid version with its checkpoint and log
+    /// before anything is written. A directory at `path` that is not empty is only replaced if
+    /// `force` is set, and it must not be open at the same time.
+    pub fn restore_from<R: Read, P: AsRef<Path>>(
+        self,
+        reader: R,
+        path: P,
+        force: bool,
+    ) -> Result<Database<T, F>> {
+        let path = path.as_ref();
+        let naming = &self.options.naming;
+        let mut files = read_archive(reader)?;
+        let version_str = files
+            .get(&naming.version_file)
+            .map(|version| String::from_utf8_lossy(version).into_owned())
+            .ok_or_else(|| BjwError::Corrupt("Backup does not contain a version".to_string()))?;
+        let version: u64 = version_str
+            .parse()
+            .map_err(|_| BjwError::VersionParse(version_str))?;
+        let checkpoint = naming.checkpoint(version);
+        let log = naming.log(version);
+        let hash = naming.with_ext(&checkpoint, HASH_EXT);
+        for filename in [&checkpoint, &log] {
+            if !files.contains_key(filename) {
+                return Err(BjwError::Corrupt(format!(
+                    "Backup does not contain {filename}"
+                )));
+            }
+        }
+
+        if self.backend.exists(path)? && !self.backend.list_files(path)?.is_empty() {
+            if !force {
+                return Err(std::io::Error::new(
+                    ErrorKind::AlreadyExists,
+                    format!("{} is not empty", path.display()),
+                )
+                .into());
+            }
+            self.backend.remove_dir_all(path)?;
+        }
+        self.backend.create_dir_all(path)?;
+        for filename in [checkpoint, hash, log] {
+            if let Some(data) = files.remove(&filename) {
+                self.backend.write(&path.join(filename), &data)?;
+            }
+        }
+        // the version file comes last, so an interrupted restore cannot be opened
+        self.backend.sync_dir(path)?;
+        self.backend.write(
+            &path.join(&naming.version_file),
+            &files[&naming.version_file],
+        )?;
+        self.backend.sync_dir(path)?;
+        self.open(path)
+    }
+}
+
+/// Reads all regular files at the top level of the tar archive, anything else is ignored.
+fn read_archive<R: Read>(reader: R) -> Result<BTreeMap<String, Vec<u8>>> {
+    let mut files = BTreeMap::new();
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let entry_path = entry.path()?;
+        let mut components = entry_path.components();
+        let (Some(Component::Normal(filename)), None) = (components.next(), components.next())
+        else {
+            continue;
+        };
+        let Some(filename) = filename.to_str().map(str::to_string) else {
+            continue;
+        };
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data)?;
+        files.insert(filename, data);
+    }
+    Ok(files)
+}
@@ -0,0 +1,200 @@
+use std::{
+    io::{BufRead, ErrorKind, Write},
+    path::Path,
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+#[cfg(feature = "bincode")]
+use crate::BincodeFormat;
+#[cfg(feature = "cbor")]
+use crate::CborFormat;
+#[cfg(feature = "msgpack")]
+use crate::MsgPackFormat;
+#[cfg(feature = "toml")]
+use crate::TomlFormat;
+use crate::{
+    parse_checkpoint_header, Backend, BjwError, DataFormat, Database, FsBackend, JsonFormat,
+    Naming, Readable, Result, Updateable,
+};
+
+/// One of the built-in formats, picked at runtime from the header of a checkpoint, see
+/// [`Database::open_autodetect`]. Only the plain formats enabled by features are known; wrappers
+/// such as `Compressed` write their own magic without naming the format they wrap, so databases
+/// written with them cannot be detected.
+pub enum AnyFormat<T> {
+    Json(JsonFormat<T>),
+    #[cfg(feature = "bincode")]
+    Bincode(BincodeFormat<T>),
+    #[cfg(feature = "msgpack")]
+    MsgPack(MsgPackFormat<T>),
+    #[cfg(feature = "cbor")]
+    Cbor(CborFormat<T>),
+    #[cfg(feature = "toml")]
+    Toml(TomlFormat<T>),
+}
+
+/// Evaluates `$call` with `$inner` bound to whichever format `$fmt` holds.
+macro_rules! delegate {
+    ($fmt:expr, $inner:ident => $call:expr) => {
+        match $fmt {
+            AnyFormat::Json($inner) => $call,
+            #[cfg(feature = "bincode")]
+            AnyFormat::Bincode($inner) => $call,
+            #[cfg(feature = "msgpack")]
+            AnyFormat::MsgPack($inner) => $call,
+            #[cfg(feature = "cbor")]
+            AnyFormat::Cbor($inner) => $call,
+            #[cfg(feature = "toml")]
+            AnyFormat::Toml($inner) => $call,
+        }
+    };
+}
+
+impl<T> AnyFormat<T>
+where
+    T: Serialize + DeserializeOwned + Updateable + Readable,
+{
+    /// All formats that can be detected, which is the registry `from_magic` looks them up in.
+    fn registry() -> Vec<Self> {
+        vec![
+            AnyFormat::Json(JsonFormat::new()),
+            #[cfg(feature = "bincode")]
+            AnyFormat::Bincode(BincodeFormat::new()),
+            #[cfg(feature = "msgpack")]
+            AnyFormat::MsgPack(MsgPackFormat::new()),
+            #[cfg(feature = "cbor")]
+            AnyFormat::Cbor(CborFormat::new()),
+            #[cfg(feature = "toml")]
+            AnyFormat::Toml(TomlFormat::new()),
+        ]
+    }
+
+    /// The built-in format whose [`DataFormat::MAGIC`] is `magic`.
+    pub fn from_magic(magic: &[u8]) -> Option<Self> {
+        Self::registry()
+            .into_iter()
+            .find(|fmt| fmt.magic() == magic)
+    }
+
+    /// Detects the format of the database at `path` from the header of its current checkpoint.
+    pub fn detect(backend: &dyn Backend, path: &Path, naming: &Naming) -> Result<Self> {
+        let mut version_path = path.join(naming.new_version_file());
+        if !backend.exists(&version_path)? {
+            version_path = path.join(&naming.version_file);
+        }
+        let version_str = String::from_utf8_lossy(&backend.read(&version_path)?).into_owned();
+        let version = version_str
+            .parse()
+            .map_err(|_| BjwError::VersionParse(version_str))?;
+        let checkpoint = backend.read(&path.join(naming.checkpoint(version)))?;
+        let Some(header) = parse_checkpoint_header(&checkpoint) else {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!("Checkpoint {version} has no header, so its format cannot be detected"),
+            )
+            .into());
+        };
+        let (magic, _) = header?;
+        Self::from_magic(magic).ok_or_else(|| {
+            std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Checkpoint {version} was written by format {:?}, which is not built in or \
+                     not enabled",
+                    String::from_utf8_lossy(magic)
+                ),
+            )
+            .into()
+        })
+    }
+}
+
+impl<T> Database<T, AnyFormat<T>>
+where
+    T: Default + Serialize + DeserializeOwned + Readable + Updateable,
+{
+    /// Opens the existing database at `path` with the built-in format its checkpoints were
+    /// written with, see [`AnyFormat`]. Use [`Database::format`] to find out which one it is.
+    pub fn open_autodetect<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let fmt = AnyFormat::detect(&FsBackend, path, &Naming::default())?;
+        Self::open(path, fmt)
+    }
+}
+
+impl<T> DataFormat for AnyFormat<T>
+where
+    T: Serialize + DeserializeOwned + Updateable + Readable,
+{
+    type Data = T;
+
+    fn magic(&self) -> &'static [u8] {
+        delegate!(self, fmt => fmt.magic())
+    }
+
+    fn format_version(&self) -> u16 {
+        delegate!(self, fmt => fmt.format_version())
+    }
+
+    /// Defaults to JSON, which is what a new database is created with.
+    fn new() -> Self {
+        AnyFormat::Json(JsonFormat::new())
+    }
+
+    fn serialize_data(&self, data: &T) -> Result<Vec<u8>> {
+        delegate!(self, fmt => fmt.serialize_data(data))
+    }
+
+    fn deserialize_data(&self, input: &[u8]) -> Result<T> {
+        delegate!(self, fmt => fmt.deserialize_data(input))
+    }
+
+    fn serialize_data_to<W: Write>(&self, data: &T, writer: W) -> Result<()> {
+        delegate!(self, fmt => fmt.serialize_data_to(data, writer))
+    }
+
+    fn serialize_params(&self, params: &<T as Updateable>::Args) -> Result<Vec<u8>> {
+        delegate!(self, fmt => fmt.serialize_params(params))
+    }
+
+    fn deserialize_params(&self, input: &[u8]) -> Result<Vec<<T as Updateable>::Args>> {
+        delegate!(self, fmt => fmt.deserialize_params(input))
+    }
+
+    fn serialize_params_into(
+        &self,
+        params: &<T as Updateable>::Args,
+        buf: &mut Vec<u8>,
+    ) -> Result<()> {
+        delegate!(self, fmt => fmt.serialize_params_into(params, buf))
+    }
+
+    fn supports_mmap(&self) -> bool {
+        delegate!(self, fmt => fmt.supports_mmap())
+    }
+
+    fn deserialize_log(&self, input: &[u8]) -> Result<(Vec<<T as Updateable>::Args>, bool)> {
+        delegate!(self, fmt => fmt.deserialize_log(input))
+    }
+
+    fn deserialize_log_prefix(
+        &self,
+        input: &[u8],
+    ) -> Result<(Vec<<T as Updateable>::Args>, usize)> {
+        delegate!(self, fmt => fmt.deserialize_log_prefix(input))
+    }
+
+    fn deserialize_params_stream<'a>(
+        &'a self,
+        reader: impl BufRead + 'a,
+    ) -> impl Iterator<Item = Result<<T as Updateable>::Args>> + 'a {
+        let updates: Box<dyn Iterator<Item = Result<<T as Updateable>::Args>> + 'a> =
+            delegate!(self, fmt => Box::new(fmt.deserialize_params_stream(reader)));
+        updates
+    }
+
+    fn serialize_batch(&self, params: &[<T as Updateable>::Args]) -> Result<Vec<u8>> {
+        delegate!(self, fmt => fmt.serialize_batch(params))
+    }
+}
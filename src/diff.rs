@@ -0,0 +1,114 @@
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    DataFormat, Database, DatabaseBuilder, Readable, Result, Updateable, DIFF_EXT, TMP_EXT,
+};
+
+/// Data that can describe how it changed compared to an older state of itself, which allows
+/// [`Database::create_diff_checkpoint`] to only store these changes.
+pub trait Diffable {
+    type Diff;
+
+    /// The changes from `base` to `self`.
+    fn diff(&self, base: &Self) -> Self::Diff;
+    /// Applies changes returned by [`Diffable::diff`], turning the base into the newer state.
+    fn apply_diff(&mut self, diff: Self::Diff);
+}
+
+/// A [`DataFormat`] that can also store the diffs of its data.
+pub trait DiffableFormat: DataFormat
+where
+    Self::Data: Diffable,
+{
+    fn serialize_diff(&self, diff: &<Self::Data as Diffable>::Diff) -> Result<Vec<u8>>;
+    fn deserialize_diff(&self, input: &[u8]) -> Result<<Self::Data as Diffable>::Diff>;
+}
+
+#[cfg(feature = "json")]
+impl<T> DiffableFormat for crate::JsonFormat<T>
+where
+    T: Serialize + DeserializeOwned + Readable + Updateable + Diffable,
+    T::Diff: Serialize + DeserializeOwned,
+{
+    fn serialize_diff(&self, diff: &T::Diff) -> Result<Vec<u8>> {
+        serde_json::to_vec(diff).map_err(crate::BjwError::serialize)
+    }
+
+    fn deserialize_diff(&self, input: &[u8]) -> Result<T::Diff> {
+        serde_json::from_slice(input).map_err(crate::BjwError::deserialize)
+    }
+}
+
+/// Applies the diff file content `input` to `base`, returning the base and the result.
+pub(crate) type DiffLoader<T, F> = fn(&F, T, &[u8]) -> Result<(T, T)>;
+
+fn load_diff<T, F>(fmt: &F, base: T, input: &[u8]) -> Result<(T, T)>
+where
+    T: Diffable + Clone,
+    F: DiffableFormat<Data = T>,
+{
+    let mut data = base.clone();
+    data.apply_diff(fmt.deserialize_diff(input)?);
+    Ok((base, data))
+}
+
+impl<T, F> Database<T, F>
+where
+    T: Default + Serialize + DeserializeOwned + Readable + Updateable + Diffable + Clone,
+    F: DiffableFormat<Data = T>,
+{
+    /// Persists the current state as a new version like [`Database::create_checkpoint`], but
+    /// only stores its diff to the last full checkpoint. That full checkpoint is kept in memory
+    /// (and on disk) as the base of all following diffs, which makes them much cheaper for large
+    /// data with few changes. If there is no base yet, i.e. after opening at a full checkpoint or
+    /// after [`Database::create_checkpoint`], a full checkpoint is created that becomes the base.
+    ///
+    /// The diffs accumulate all changes since the base, so a full checkpoint should still be
+    /// created once in a while. Opening a database at a diff requires
+    /// [`DatabaseBuilder::diff_checkpoints`], and [`Database::backup_to`] requires a full one.
+    pub fn create_diff_checkpoint(&mut self) -> Result<()> {
+        self.check_writable()?;
+        self.diff_loader = Some(load_diff::<T, F>);
+        let Some((base_version, base)) = &self.diff_base else {
+            self.create_checkpoint()?;
+            self.diff_base = Some((self.version, self.data.clone()));
+            return Ok(());
+        };
+        let mut ser = base_version.to_be_bytes().to_vec();
+        ser.extend(self.fmt.serialize_diff(&self.data.diff(base))?);
+
+        self.close_log()?;
+        self.version += 1;
+        self.log_segment = 0;
+        self.segment_bytes = 0;
+        let naming = &self.options.naming;
+        let filename = naming.with_ext(&naming.checkpoint(self.version), DIFF_EXT);
+        let tmp_filename = naming.with_ext(&filename, TMP_EXT);
+        self.backend.write(&self.path.join(&tmp_filename), &ser)?;
+        self.backend
+            .rename(&self.path.join(tmp_filename), &self.path.join(filename))?;
+        self.sync_dir()?;
+        self.create_logfile_if_required()?;
+        self.update_version_file()?;
+        self.poisoned = false;
+        self.log_bytes = 0;
+        self.unsynced_writes = 0;
+        self.updates_since_checkpoint = 0;
+        self.checkpoint_bytes = None;
+        self.auto_cleanup();
+        Ok(())
+    }
+}
+
+impl<T, F> DatabaseBuilder<T, F>
+where
+    T: Default + Serialize + DeserializeOwned + Readable + Updateable + Diffable + Clone,
+    F: DiffableFormat<Data = T>,
+{
+    /// Allows opening the database at a checkpoint created by
+    /// [`Database::create_diff_checkpoint`].
+    pub fn diff_checkpoints(mut self) -> Self {
+        self.diff_loader = Some(load_diff::<T, F>);
+        self
+    }
+}
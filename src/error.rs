@@ -0,0 +1,101 @@
+use std::{error::Error, fmt};
+
+/// The errors returned by a [`Database`](crate::Database) and its [`DataFormat`](crate::DataFormat)s.
+#[derive(Debug)]
+pub enum BjwError {
+    /// Accessing the storage failed, this also covers a database that is locked, opened
+    /// read-only or does not exist.
+    Io(std::io::Error),
+    /// Encoding the data or an update failed.
+    Serialize(Box<dyn Error + Send + Sync>),
+    /// Decoding stored data failed.
+    Deserialize(Box<dyn Error + Send + Sync>),
+    /// The version file does not hold a valid version.
+    VersionParse(String),
+    /// Stored data failed an integrity check, e.g. a checkpoint hash or an authentication tag.
+    Corrupt(String),
+}
+
+impl BjwError {
+    /// Wraps anything that can be turned into an error, handy for `map_err` in a custom format.
+    pub fn serialize<E: Into<Box<dyn Error + Send + Sync>>>(e: E) -> Self {
+        BjwError::Serialize(e.into())
+    }
+
+    /// See [`BjwError::serialize`].
+    pub fn deserialize<E: Into<Box<dyn Error + Send + Sync>>>(e: E) -> Self {
+        BjwError::Deserialize(e.into())
+    }
+}
+
+impl fmt::Display for BjwError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BjwError::Io(e) => write!(f, "I/O error: {e}"),
+            BjwError::Serialize(e) => write!(f, "Failed to serialize: {e}"),
+            BjwError::Deserialize(e) => write!(f, "Failed to deserialize: {e}"),
+            BjwError::VersionParse(version) => write!(f, "Could not parse version {version:?}"),
+            BjwError::Corrupt(msg) => write!(f, "Corrupt data: {msg}"),
+        }
+    }
+}
+
+impl Error for BjwError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            BjwError::Io(e) => Some(e),
+            BjwError::Serialize(e) | BjwError::Deserialize(e) => Some(e.as_ref()),
+            BjwError::VersionParse(_) | BjwError::Corrupt(_) => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for BjwError {
+    fn from(e: std::io::Error) -> Self {
+        BjwError::Io(e)
+    }
+}
+
+impl From<BjwError> for std::io::Error {
+    fn from(e: BjwError) -> Self {
+        match e {
+            BjwError::Io(e) => e,
+            BjwError::Serialize(e) => std::io::Error::new(std::io::ErrorKind::InvalidInput, e),
+            e => std::io::Error::new(std::io::ErrorKind::InvalidData, e),
+        }
+    }
+}
+
+/// Returned by derived update methods marked with `#[bjw(flatten_result)]`, which combines the
+/// database's own errors with the error returned by the method itself.
+#[derive(Debug)]
+pub enum UpdateError<E> {
+    /// Persisting the update failed, so it was not applied.
+    Db(BjwError),
+    /// The update was persisted and applied, but the method returned an error.
+    Update(E),
+}
+
+impl<E: fmt::Display> fmt::Display for UpdateError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UpdateError::Db(e) => e.fmt(f),
+            UpdateError::Update(e) => e.fmt(f),
+        }
+    }
+}
+
+impl<E: Error + 'static> Error for UpdateError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            UpdateError::Db(e) => Some(e),
+            UpdateError::Update(e) => Some(e),
+        }
+    }
+}
+
+impl<E> From<BjwError> for UpdateError<E> {
+    fn from(e: BjwError) -> Self {
+        UpdateError::Db(e)
+    }
+}
@@ -1,11 +1,51 @@
 use serde::{de::DeserializeOwned, Serialize};
 use std::{
-    fs::{File, OpenOptions},
-    io::{ErrorKind, Write},
+    io::{BufRead, ErrorKind, Read, Write},
+    marker::PhantomData,
+    ops::Deref,
     path::{Path, PathBuf},
+    time::SystemTime,
 };
 
-type Result<T> = std::io::Result<T>;
+mod append_only;
+#[cfg(feature = "tokio")]
+mod async_db;
+mod backend;
+#[cfg(feature = "backup")]
+mod backup;
+#[cfg(feature = "json")]
+mod detect;
+mod diff;
+mod error;
+#[cfg(feature = "parallel")]
+mod parallel;
+mod shared;
+mod subscribe;
+mod timestamps;
+pub use append_only::AppendOnlyDatabase;
+#[cfg(feature = "tokio")]
+pub use async_db::AsyncDatabase;
+#[cfg(any(test, feature = "testing"))]
+pub use backend::FaultInjectingBackend;
+#[cfg(feature = "object_store")]
+pub use backend::ObjectStoreBackend;
+pub use backend::{Backend, FsBackend, LogWriter, MemoryBackend};
+#[cfg(feature = "json")]
+pub use detect::AnyFormat;
+use diff::DiffLoader;
+pub use diff::{Diffable, DiffableFormat};
+pub use error::{BjwError, UpdateError};
+#[cfg(feature = "parallel")]
+pub use parallel::{MapData, ParallelFormat};
+#[cfg(feature = "rkyv")]
+pub use rkyv;
+pub use shared::{AutoCheckpoint, SharedDatabase, WriteGuard};
+use subscribe::Notifier;
+use timestamps::{
+    frame_overhead, nanos_since_epoch, read_log_header, timestamped_frame, TIMESTAMP_MAGIC,
+};
+
+pub type Result<T> = std::result::Result<T, BjwError>;
 
 pub trait Readable {
     type Args<'a>;
@@ -26,69 +66,530 @@ pub struct Database<T, F> {
     fmt: F,
     path: PathBuf,
     version: u64,
+    options: Options,
+    backend: Box<dyn Backend>,
+    log_bytes: u64,
+    unsynced_writes: u32,
+    updates_since_checkpoint: u64,
+    /// The log of the current version, if the backend can keep it open.
+    log_writer: Option<Box<dyn LogWriter>>,
+    /// Reused to serialize updates, see [`DataFormat::serialize_params_into`].
+    params_buf: Vec<u8>,
+    /// The size of the checkpoint of the current version, once it was needed for
+    /// [`DatabaseBuilder::auto_checkpoint_ratio`].
+    checkpoint_bytes: Option<u64>,
+    /// Set when a failed append may still have left its update in the log, see
+    /// [`Database::update`].
+    poisoned: bool,
+    /// The segment of the log of the current version that is appended to, and its size.
+    log_segment: u64,
+    segment_bytes: u64,
+    /// Whether the updates in the current segment are timestamped, once that is known, see
+    /// [`DatabaseBuilder::log_timestamps`].
+    segment_timestamps: Option<bool>,
+    /// The latest timestamp an update was appended with, in nanoseconds since the Unix epoch.
+    last_timestamp: u64,
+    /// The full checkpoint (and its version) that diff checkpoints are created against.
+    diff_base: Option<(u64, T)>,
+    diff_loader: Option<DiffLoader<T, F>>,
+    #[cfg(feature = "parallel")]
+    sharding: Option<parallel::Sharding<T, F>>,
+    subscribers: Option<Box<dyn Notifier<T>>>,
+    _lock: Box<dyn Send + Sync>,
+}
+
+/// An append to the log of the current version, see [`Database::begin_append`].
+struct LogAppend {
+    writer: Option<Box<dyn LogWriter>>,
+    path: PathBuf,
+    sync: bool,
+    /// The timestamp to frame the update with, see [`DatabaseBuilder::log_timestamps`].
+    timestamp: Option<u64>,
+    starts_segment: bool,
+}
+
+impl LogAppend {
+    /// How many bytes appending `ser` adds to the log.
+    fn framed_len(&self, ser: &[u8]) -> u64 {
+        let overhead = match self.timestamp {
+            Some(_) => frame_overhead(self.starts_segment),
+            None => 0,
+        };
+        ser.len() as u64 + overhead
+    }
+}
+
+/// The log of a version checkpointed while replaying, which the rest of the replayed log is
+/// copied into, see [`DatabaseBuilder::max_replay_updates`].
+struct CarriedLog {
+    version: u64,
+    path: PathBuf,
+    writer: Option<Box<dyn LogWriter>>,
+    /// Without a writer, the latest update is only appended once the next one is known, so that
+    /// the last one can sync the log.
+    unwritten: Option<Vec<u8>>,
+    timestamped: bool,
+    last_timestamp: u64,
+    bytes: u64,
+    updates: u64,
+}
+
+/// Controls how often the log is fsynced after appending an update.
+///
+/// Only `EveryWrite` guarantees that an update is durable once `update` returns. With the other
+/// policies a crash may lose the most recent updates (but never corrupt older ones), in
+/// exchange for much higher write throughput. Creating a checkpoint always makes the whole
+/// state durable.
+///
+/// Backends that keep the log open (like [`FsBackend`]) buffer updates between syncs, so a crash
+/// of the process itself may lose them as well; use [`Database::flush`] to write them out.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SyncPolicy {
+    #[default]
+    EveryWrite,
+    EveryN(u32),
+    Never,
+}
+
+/// Describes how replaying the log went when opening a database.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ReplayReport {
+    /// The number of updates from the log that were applied on top of the checkpoint.
+    pub entries_applied: usize,
+    /// Whether replay stopped early at an entry that could not be decoded, i.e. whether
+    /// updates at the end of the log were lost (typically due to a crash while appending).
+    pub truncated: bool,
+}
+
+/// A snapshot of the state of a database, see [`Database::stats`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DbStats {
+    pub version: u64,
+    /// The size of the log, including all of its segments.
+    pub log_file_bytes: u64,
+    pub checkpoint_file_bytes: u64,
+    /// The number of updates in the current log, including those replayed on open.
+    pub updates_since_checkpoint: u64,
+}
+
+/// What [`Database::verify`] found out about the files of the current version.
+#[derive(Debug)]
+pub struct VerifyReport {
+    pub version: u64,
+    /// Why the version file does not hold the current version, if it does not.
+    pub version_file: Option<BjwError>,
+    /// Why the checkpoint could not be loaded, if it could not.
+    pub checkpoint: Option<BjwError>,
+    /// The number of updates in the log that could be decoded.
+    pub log_entries: usize,
+    /// Whether the log ends in an entry that cannot be decoded, see [`ReplayReport::truncated`].
+    pub log_truncated: bool,
+    /// Why the log could not be read, if it could not.
+    pub log: Option<BjwError>,
+}
+
+impl VerifyReport {
+    /// Whether no problem was found.
+    pub fn is_ok(&self) -> bool {
+        self.version_file.is_none()
+            && self.checkpoint.is_none()
+            && !self.log_truncated
+            && self.log.is_none()
+    }
+}
+
+/// The names of the files of a database, see [`DatabaseBuilder::naming`]. Checkpoints and logs
+/// are named `<prefix><delim><version>`, the defaults result in `checkpoint.3` and `logfile.3`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Naming {
+    pub checkpoint_prefix: String,
+    pub log_prefix: String,
+    /// Holds the current version, a new one is written to `new_<version_file>` first.
+    pub version_file: String,
+    /// Is locked as long as the database is open.
+    pub lock_file: String,
+    pub delim: char,
+}
+
+impl Default for Naming {
+    fn default() -> Self {
+        Naming {
+            checkpoint_prefix: "checkpoint".to_string(),
+            log_prefix: "logfile".to_string(),
+            version_file: "version".to_string(),
+            lock_file: ".lock".to_string(),
+            delim: '.',
+        }
+    }
+}
+
+impl Naming {
+    fn checkpoint(&self, version: u64) -> String {
+        format!("{}{}{version}", self.checkpoint_prefix, self.delim)
+    }
+
+    fn log(&self, version: u64) -> String {
+        format!("{}{}{version}", self.log_prefix, self.delim)
+    }
+
+    /// `filename` with the extension `ext` appended, e.g. `checkpoint.3.tmp`.
+    fn with_ext(&self, filename: &str, ext: &str) -> String {
+        format!("{filename}{}{ext}", self.delim)
+    }
+
+    fn strip_ext<'a>(&self, filename: &'a str, ext: &str) -> Option<&'a str> {
+        filename.strip_suffix(ext)?.strip_suffix(self.delim)
+    }
+
+    fn new_version_file(&self) -> String {
+        format!("new_{}", self.version_file)
+    }
+
+    /// Splits the name of a checkpoint or log file (without extension) into prefix and version.
+    fn split_version<'a>(&self, filename: &'a str) -> Option<(&'a str, u64)> {
+        let (prefix, version) = filename.rsplit_once(self.delim)?;
+        if prefix != self.checkpoint_prefix && prefix != self.log_prefix {
+            return None;
+        }
+        Some((prefix, parse_number(version)?))
+    }
+
+    /// The name of segment `segment` of the log of `version`, e.g. `logfile.3.1`. The first
+    /// segment is the log itself, see [`DatabaseBuilder::log_segment_bytes`].
+    fn log_segment(&self, version: u64, segment: u64) -> String {
+        match segment {
+            0 => self.log(version),
+            _ => self.with_ext(&self.log(version), &segment.to_string()),
+        }
+    }
+
+    /// Splits the name of a log segment (without extension) into version and segment.
+    fn split_log_segment(&self, filename: &str) -> Option<(u64, u64)> {
+        if let Some((prefix, version)) = self.split_version(filename) {
+            return (prefix == self.log_prefix).then_some((version, 0));
+        }
+        let (base, segment) = filename.rsplit_once(self.delim)?;
+        match (self.split_version(base)?, parse_number(segment)?) {
+            ((prefix, version), segment) if prefix == self.log_prefix && segment > 0 => {
+                Some((version, segment))
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether `filename` is one of the files of a database with these names.
+    fn owns(&self, filename: &str) -> bool {
+        let base = filename
+            .rsplit_once(self.delim)
+            .map_or(filename, |(base, _)| base);
+        filename == self.version_file
+            || filename == self.new_version_file()
+            || self.split_version(filename).is_some()
+            || self.split_version(base).is_some()
+            || self.split_log_segment(base).is_some()
+    }
+}
+
+/// Parses a version or segment number exactly as it is written into filenames, so that e.g.
+/// `checkpoint.03` or `checkpoint.+3` are not mistaken for files of the database.
+fn parse_number(number: &str) -> Option<u64> {
+    let canonical =
+        number.bytes().all(|b| b.is_ascii_digit()) && (number == "0" || !number.starts_with('0'));
+    canonical.then(|| number.parse().ok()).flatten()
+}
+
+#[derive(Clone, Debug, Default)]
+struct Options {
+    auto_checkpoint_bytes: Option<u64>,
+    auto_checkpoint_ratio: Option<f64>,
+    max_replay_updates: Option<usize>,
+    sync_policy: SyncPolicy,
+    read_only: bool,
+    append_only: bool,
+    rebuild_from_logs: bool,
+    wait_for_lock: bool,
+    checkpoint_fallback: bool,
+    keep_versions: usize,
+    /// Set by `DatabaseBuilder::auto_cleanup(false)`.
+    manual_cleanup: bool,
+    #[cfg(feature = "zstd")]
+    compress_old_logs: bool,
+    log_segment_bytes: Option<u64>,
+    log_timestamps: bool,
+    naming: Naming,
+}
+
+/// Converts the content of a checkpoint file that is not valid for the current type (and the
+/// version of the checkpoint), see [`DatabaseBuilder::migrate`].
+pub type Migration<T> = dyn Fn(&[u8], u64) -> Result<T> + Send;
+
+/// Collects the options for opening a [`Database`], see [`Database::builder`].
+pub struct DatabaseBuilder<T, F> {
+    fmt: F,
+    options: Options,
+    backend: Box<dyn Backend>,
+    migration: Option<Box<Migration<T>>>,
+    diff_loader: Option<DiffLoader<T, F>>,
+    #[cfg(feature = "parallel")]
+    sharding: Option<parallel::Sharding<T, F>>,
+    _phantom: PhantomData<T>,
 }
 
 pub trait DataFormat {
     type Data: Serialize + DeserializeOwned + Readable + Updateable;
 
+    /// Identifies the format in a header line preceding every checkpoint (`bjw:<magic>:<format
+    /// version>`), so that opening a database with another format fails with a clear error
+    /// instead of misparsing it. Checkpoints without a header, i.e. those written before headers
+    /// were introduced, are read as they are. The default is empty, which writes no header.
+    const MAGIC: &'static [u8] = b"";
+    /// Stored next to [`DataFormat::MAGIC`], to be increased whenever the way the format writes
+    /// checkpoints changes incompatibly.
+    const FORMAT_VERSION: u16 = 1;
+
+    /// [`DataFormat::MAGIC`] of this format, which is what the header is written and checked
+    /// with. Formats that pick their encoding at runtime override it, see [`AnyFormat`].
+    fn magic(&self) -> &'static [u8] {
+        Self::MAGIC
+    }
+
+    /// [`DataFormat::FORMAT_VERSION`] of this format, see [`DataFormat::magic`].
+    fn format_version(&self) -> u16 {
+        Self::FORMAT_VERSION
+    }
+
     fn new() -> Self;
     fn serialize_data(&self, data: &Self::Data) -> Result<Vec<u8>>;
     fn deserialize_data(&self, input: &[u8]) -> Result<Self::Data>;
+
+    /// Serializes `data` like [`DataFormat::serialize_data`], but into `writer`, which is used
+    /// for checkpoints. The default serializes everything at once, so formats should override it
+    /// if they can write piece by piece.
+    fn serialize_data_to<W: Write>(&self, data: &Self::Data, mut writer: W) -> Result<()> {
+        writer.write_all(&self.serialize_data(data)?)?;
+        Ok(())
+    }
+
     fn serialize_params(&self, params: &<Self::Data as Updateable>::Args) -> Result<Vec<u8>>;
     fn deserialize_params(&self, input: &[u8]) -> Result<Vec<<Self::Data as Updateable>::Args>>;
+
+    /// Appends what [`DataFormat::serialize_params`] returns to `buf`, which the database keeps
+    /// around for all updates to save an allocation each. The default just copies the output of
+    /// `serialize_params`, formats override it to serialize straight into `buf` (and let
+    /// `serialize_params` delegate to it).
+    fn serialize_params_into(
+        &self,
+        params: &<Self::Data as Updateable>::Args,
+        buf: &mut Vec<u8>,
+    ) -> Result<()> {
+        buf.extend(self.serialize_params(params)?);
+        Ok(())
+    }
+
+    /// Whether [`DataFormat::deserialize_data`] works straight on its input, so that with the
+    /// `mmap` feature a checkpoint is mapped into memory instead of copied, see
+    /// [`Backend::read_mapped`]. Formats that decode the whole input into a new buffer first
+    /// gain nothing from it.
+    fn supports_mmap(&self) -> bool {
+        false
+    }
+
+    /// Like `deserialize_params`, but also tells whether decoding stopped early at a corrupt or
+    /// truncated entry. The default cannot tell and always reports a complete log.
+    fn deserialize_log(
+        &self,
+        input: &[u8],
+    ) -> Result<(Vec<<Self::Data as Updateable>::Args>, bool)> {
+        Ok((self.deserialize_params(input)?, false))
+    }
+
+    /// Like [`DataFormat::deserialize_log`], but returns how many bytes at the start of `input`
+    /// the decoded updates were read from instead of whether decoding stopped early. A read-only
+    /// database continues reading the log right after them, see [`Database::refresh`]. The
+    /// default cannot tell where a truncated log stops being valid, so it decodes nothing of one.
+    fn deserialize_log_prefix(
+        &self,
+        input: &[u8],
+    ) -> Result<(Vec<<Self::Data as Updateable>::Args>, usize)> {
+        match self.deserialize_log(input)? {
+            (updates, false) => Ok((updates, input.len())),
+            (_, true) => Ok((Vec::new(), 0)),
+        }
+    }
+
+    /// Decodes the updates of a log one after another while reading it from `reader`, which is
+    /// how the log is replayed. If the log ends in an entry that cannot be decoded, the last item
+    /// is a [`BjwError::Corrupt`], which replaying reports as truncation; any other error fails
+    /// it. The default reads the whole log and hands it to `deserialize_log`, formats override it
+    /// to keep memory usage independent of the size of the log.
+    fn deserialize_params_stream<'a>(
+        &'a self,
+        mut reader: impl BufRead + 'a,
+    ) -> impl Iterator<Item = Result<<Self::Data as Updateable>::Args>> + 'a {
+        let mut input = Vec::new();
+        let log = match reader.read_to_end(&mut input) {
+            Ok(_) => self.deserialize_log(&input),
+            Err(e) => Err(e.into()),
+        };
+        let (updates, error) = match log {
+            Ok((updates, truncated)) => (
+                updates,
+                truncated.then(|| BjwError::Corrupt("The log ends in a corrupt entry".to_string())),
+            ),
+            Err(e) => (Vec::new(), Some(e)),
+        };
+        updates.into_iter().map(Ok).chain(error.map(Err))
+    }
+
+    /// Serializes a group of updates that is appended to the log at once. Replaying a group
+    /// must yield either all of its updates or none, so formats override this with some kind of
+    /// group framing; the default just concatenates the updates and gives no such guarantee.
+    fn serialize_batch(&self, params: &[<Self::Data as Updateable>::Args]) -> Result<Vec<u8>> {
+        let mut ser = Vec::new();
+        for params in params {
+            ser.extend(self.serialize_params(params)?);
+        }
+        Ok(ser)
+    }
 }
 
-const VERSION_FILE: &str = "version";
-const NEW_VERSION_FILE: &str = "new_version";
-const CHECKPOINT_PREFIX: &str = "checkpoint";
-const LOG_PREFIX: &str = "logfile";
-const DELIM: char = '.';
+const TMP_EXT: &str = "tmp";
+const HASH_EXT: &str = "sha256";
+const COMPACT_EXT: &str = "compact";
+const DIFF_EXT: &str = "diff";
+const ZST_EXT: &str = "zst";
 
 impl<T, F> Database<T, F>
 where
     T: Default + Serialize + DeserializeOwned + Readable + Updateable,
     F: DataFormat<Data = T>,
 {
+    /// Opens the database at `path`, creating it if it does not exist yet. The directory is
+    /// locked for as long as the database is open, so this fails with `WouldBlock` if another
+    /// process (or another handle) has it open already. Use
+    /// [`DatabaseBuilder::wait_for_lock`] to block until it becomes available instead.
     pub fn open<P: AsRef<Path>>(path: P, fmt: F) -> Result<Database<T, F>> {
-        let path = path.as_ref().to_path_buf();
-        if !path.exists() {
-            std::fs::create_dir_all(&path)?;
-            let db = Database {
-                data: <T as Default>::default(),
-                fmt,
-                path,
-                version: 0,
-            };
-            db.write_checkpoint_file()?;
-            db.create_logfile_if_required()?;
-            db.update_version_file()?;
-            Ok(db)
-        } else {
-            let new_version_path = path.join(NEW_VERSION_FILE);
-            let version_path = path.join(VERSION_FILE);
-            if new_version_path.exists() {
-                std::fs::rename(&new_version_path, &version_path)?;
-            }
-            let version_str = std::fs::read_to_string(version_path)?;
-            let version: u64 = version_str.parse().map_err(|_| {
-                std::io::Error::new(ErrorKind::InvalidData, "Could not parse version")
-            })?;
-            let mut db = Database {
-                data: <T as Default>::default(),
-                fmt,
-                path,
-                version,
-            };
-            db.read_checkpoint_file()?;
-            db.replay_updates()?;
-            Ok(db)
+        Self::builder(fmt).open(path)
+    }
+
+    /// Opens the database like [`Database::open`] and also reports how many updates were
+    /// replayed from the log, and whether the log ended in a corrupt or truncated entry.
+    pub fn open_with_report<P: AsRef<Path>>(
+        path: P,
+        fmt: F,
+    ) -> Result<(Database<T, F>, ReplayReport)> {
+        Self::builder(fmt).open_with_report(path)
+    }
+
+    /// Opens the database like [`Database::open`], converting a checkpoint that cannot be
+    /// deserialized with `migrate`, see [`DatabaseBuilder::migrate`].
+    pub fn open_with_migration<P, M>(path: P, fmt: F, migrate: M) -> Result<Database<T, F>>
+    where
+        P: AsRef<Path>,
+        M: Fn(&[u8], u64) -> Result<T> + Send + 'static,
+    {
+        Self::builder(fmt).migrate(migrate).open(path)
+    }
+
+    /// Opens an existing database without ever writing to it. `update` and `create_checkpoint`
+    /// fail with `PermissionDenied`. As nothing is written, the directory is not locked.
+    pub fn open_read_only<P: AsRef<Path>>(path: P, fmt: F) -> Result<Database<T, F>> {
+        Self::builder(fmt).read_only().open(path)
+    }
+
+    /// Opens the database at `path` stored via `backend`, see [`DatabaseBuilder::backend`].
+    pub fn open_with_backend<P, B>(path: P, fmt: F, backend: B) -> Result<Database<T, F>>
+    where
+        P: AsRef<Path>,
+        B: Backend + 'static,
+    {
+        Self::builder(fmt).backend(backend).open(path)
+    }
+
+    /// Opens the database at `path` for ingesting updates only, see [`AppendOnlyDatabase`]. As
+    /// it has no state in memory, the handle has no read API, nor `update` (which has to return
+    /// the result of the update).
+    pub fn open_append_only<P: AsRef<Path>>(path: P, fmt: F) -> Result<AppendOnlyDatabase<T, F>> {
+        Self::builder(fmt).open_append_only(path)
+    }
+
+    /// Opens a fresh database that lives in memory only and is gone once it is dropped.
+    pub fn open_in_memory(fmt: F) -> Result<Database<T, F>> {
+        Self::builder(fmt)
+            .backend(MemoryBackend::new())
+            .open(PathBuf::new())
+    }
+}
+
+impl<T, F> Database<T, F>
+where
+    T: Serialize + DeserializeOwned + Readable + Updateable,
+    F: DataFormat<Data = T>,
+{
+    /// Opens the database like [`Database::open`], but a new database starts out with `init()`
+    /// instead of `T::default()`, e.g. to seed it. For an existing one, the result of `init` is
+    /// replaced by the stored state.
+    pub fn open_or_init<P, I>(path: P, fmt: F, init: I) -> Result<Database<T, F>>
+    where
+        P: AsRef<Path>,
+        I: FnOnce() -> T,
+    {
+        Self::builder(fmt).open_or_init(path, init)
+    }
+
+    /// Starts collecting options for opening a database, the other constructors are shortcuts
+    /// for the common cases.
+    pub fn builder(fmt: F) -> DatabaseBuilder<T, F> {
+        DatabaseBuilder {
+            fmt,
+            options: Options::default(),
+            backend: Box::new(FsBackend),
+            migration: None,
+            diff_loader: None,
+            #[cfg(feature = "parallel")]
+            sharding: None,
+            _phantom: PhantomData,
         }
     }
 
+    /// The directory of the database, which is empty for [`Database::open_in_memory`].
     pub fn path(&self) -> &PathBuf {
         &self.path
     }
 
+    /// The format the database was opened with.
+    pub fn format(&self) -> &F {
+        &self.fmt
+    }
+
+    /// The current version, i.e. the number of the checkpoint and log files in use.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// The log file updates are currently appended to, e.g. to watch it for changes. With
+    /// [`DatabaseBuilder::log_segment_bytes`], this is the last segment of the log. It is only
+    /// created on the first update after a checkpoint with some backends.
+    pub fn current_log_path(&self) -> PathBuf {
+        let naming = &self.options.naming;
+        self.path
+            .join(naming.log_segment(self.version, self.log_segment))
+    }
+
+    /// The checkpoint file of the current version, which is a `.diff` file for a diff checkpoint,
+    /// see [`DatabaseBuilder::diff_checkpoints`].
+    pub fn current_checkpoint_path(&self) -> PathBuf {
+        let naming = &self.options.naming;
+        let path = self.path.join(naming.checkpoint(self.version));
+        let diff_path = self
+            .path
+            .join(naming.with_ext(&naming.checkpoint(self.version), DIFF_EXT));
+        match self.backend.exists(&diff_path) {
+            Ok(true) => diff_path,
+            _ => path,
+        }
+    }
+
     pub fn read(&self, parameters: &<T as Readable>::Args<'_>) -> <T as Readable>::ReturnType {
         self.data.read(parameters)
     }
@@ -97,252 +598,6984 @@ where
         &self.data
     }
 
+    /// Runs `f` on the data and returns its result, e.g. to compute a projection of it without
+    /// cloning the whole state. Unlike [`Database::read_all`], this also works through a lock
+    /// guard like the one of [`SharedDatabase::read`], as the result is owned.
+    pub fn with_data<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        f(&self.data)
+    }
+
+    /// Persists `parameters` in the log and then applies them to the data, returning what the
+    /// update returned. The data only changes once the update is written (and synced as the
+    /// [`SyncPolicy`] demands), so on an error it is left as it was.
+    ///
+    /// If writing or syncing fails after (part of) the update reached the log, it may still be
+    /// replayed when the database is opened again. Further updates fail then until
+    /// [`Database::create_checkpoint`] persists the state without it, or the database is
+    /// reopened with the state on disk.
     pub fn update(
         &mut self,
         parameters: &<T as Updateable>::Args,
     ) -> Result<<T as Updateable>::ReturnType> {
-        self.extend_update_log(parameters)?;
-        Ok(self.data.update(parameters))
+        self.check_writable()?;
+        self.log_update(parameters)?;
+        let ret = self.data.update(parameters);
+        self.finish_update(std::slice::from_ref(parameters));
+        Ok(ret)
     }
 
-    pub fn create_checkpoint(&mut self) -> Result<()> {
-        self.version += 1;
-        self.write_checkpoint_file()?;
-        self.create_logfile_if_required()?;
-        self.update_version_file()?;
-        if let Err(e) = self.cleanup() {
-            log::warn!("Failed to cleanup: {:?}", e);
-        };
+    /// Persists `parameters` like [`Database::update`], but without returning what the update
+    /// returns. This is how updates are ingested into an [`AppendOnlyDatabase`], which only
+    /// appends them to the log.
+    pub fn append(&mut self, parameters: &<T as Updateable>::Args) -> Result<()> {
+        self.check_writable()?;
+        self.log_update(parameters)?;
+        if !self.options.append_only {
+            self.data.update(parameters);
+        }
+        self.finish_update(std::slice::from_ref(parameters));
         Ok(())
     }
 
-    pub fn delete(self) -> Result<()> {
-        std::fs::remove_dir_all(self.path)?;
-        Ok(())
+    fn log_update(&mut self, parameters: &<T as Updateable>::Args) -> Result<()> {
+        let mut buf = std::mem::take(&mut self.params_buf);
+        buf.clear();
+        let logged = self
+            .fmt
+            .serialize_params_into(parameters, &mut buf)
+            .and_then(|_| self.extend_update_log(&buf));
+        self.params_buf = buf;
+        logged
     }
 
-    fn replay_updates(&mut self) -> Result<()> {
-        let log_filename = format!("{LOG_PREFIX}{DELIM}{}", self.version);
-        let ser = std::fs::read(self.path.join(log_filename))?;
-        let updates = self.fmt.deserialize_params(&ser)?;
-        for params in updates {
-            self.data.update(&params);
+    /// Applies all `parameters` in order as one atomic group. They are appended to the log at
+    /// once (so there is at most a single fsync), and a partially written group is skipped as a
+    /// whole when the log is replayed.
+    pub fn update_batch(
+        &mut self,
+        parameters: &[<T as Updateable>::Args],
+    ) -> Result<Vec<<T as Updateable>::ReturnType>> {
+        self.check_writable()?;
+        if parameters.is_empty() {
+            return Ok(Vec::new());
         }
-        Ok(())
+        let ser = self.fmt.serialize_batch(parameters)?;
+        self.extend_update_log(&ser)?;
+        let ret = parameters
+            .iter()
+            .map(|params| self.data.update(params))
+            .collect();
+        self.finish_update(parameters);
+        Ok(ret)
     }
 
-    fn create_logfile_if_required(&self) -> Result<PathBuf> {
-        let filename = format!("{LOG_PREFIX}{DELIM}{}", self.version);
-        let path = self.path.join(filename);
-        if !path.exists() {
-            let file = File::create(&path)?;
-            file.sync_all()?;
-        }
-        Ok(path.clone())
+    /// Persists the current state as a new version. The new checkpoint and log file are made
+    /// durable (including their directory entries) before the version file is switched over, so
+    /// a crash at any point leaves either the old or the new version intact.
+    ///
+    /// The version file decides which one survives: once it was switched (or the new version
+    /// written next to it), opening the database continues at the new version. Otherwise, the
+    /// files of the new version are removed when the database is opened again, as the old
+    /// checkpoint together with its log holds the same state.
+    pub fn create_checkpoint(&mut self) -> Result<()> {
+        self.create_checkpoint_with_version(self.version + 1)
     }
 
-    fn extend_update_log(&self, params: &<T as Updateable>::Args) -> Result<()> {
-        let path = self.create_logfile_if_required()?;
-        let ser = self.fmt.serialize_params(params)?;
-        let mut file = OpenOptions::new().append(true).open(path)?;
-        file.write_all(&ser)?;
-        file.sync_all()?;
-        Ok(())
+    /// Creates a checkpoint like [`Database::create_checkpoint`], but as `version` instead of the
+    /// next one, e.g. to align the versions with an external clock. `version` must be greater
+    /// than the current one, the versions in between are skipped.
+    pub fn create_checkpoint_with_version(&mut self, version: u64) -> Result<()> {
+        self.check_writable()?;
+        if version <= self.version {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Version {version} is not greater than the current version {}",
+                    self.version
+                ),
+            )
+            .into());
+        }
+        // the old version has to be complete in case the new one does not make it
+        self.close_log()?;
+        if self.options.append_only {
+            // the state is only built to fold the log into the new checkpoint
+            let mut folded = self.read_checkpoint_file(self.version)?;
+            self.replay_log(self.version, &mut folded)?;
+            let empty = std::mem::replace(&mut self.data, folded);
+            let created = self.switch_to_version(version);
+            self.data = empty;
+            return created;
+        }
+        self.switch_to_version(version)
     }
 
-    fn read_checkpoint_file(&mut self) -> Result<()> {
-        let filename = format!("{CHECKPOINT_PREFIX}{DELIM}{}", self.version);
-        let ser = std::fs::read(self.path.join(filename))?;
-        let data: T = self.fmt.deserialize_data(&ser)?;
-        self.data = data;
+    /// Writes the current state as the checkpoint of `version` and makes it the current one.
+    fn switch_to_version(&mut self, version: u64) -> Result<()> {
+        self.version = version;
+        self.log_segment = 0;
+        self.segment_bytes = 0;
+        self.write_checkpoint_file()?;
+        self.create_logfile_if_required()?;
+        self.update_version_file()?;
+        self.poisoned = false;
+        self.log_bytes = 0;
+        self.unsynced_writes = 0;
+        self.updates_since_checkpoint = 0;
+        self.checkpoint_bytes = None;
+        self.diff_base = None;
+        self.auto_cleanup();
         Ok(())
     }
 
-    fn write_checkpoint_file(&self) -> Result<()> {
-        let filename = format!("{CHECKPOINT_PREFIX}{DELIM}{}", self.version);
-        let mut file = File::create(self.path.join(filename))?;
-        let ser = self.fmt.serialize_data(&self.data)?;
-        file.write_all(&ser)?;
-        file.sync_all()?;
-        Ok(())
+    /// Removes the files of all but the `keep_versions` versions before the current one, for
+    /// databases opened with [`DatabaseBuilder::auto_cleanup`] disabled. The previous version is
+    /// always kept with [`DatabaseBuilder::fallback_to_previous_checkpoint`].
+    pub fn prune(&mut self, keep_versions: usize) -> Result<()> {
+        self.check_writable()?;
+        self.cleanup(Some(keep_versions))
     }
 
-    fn update_version_file(&self) -> Result<()> {
-        let mut file = File::create(self.path.join(NEW_VERSION_FILE))?;
-        file.write_all(self.version.to_string().as_bytes())?;
-        file.sync_all()?;
-        std::fs::rename(
-            self.path.join(NEW_VERSION_FILE),
-            self.path.join(VERSION_FILE),
+    /// Rewrites the checkpoint of the current version from the live state and empties its log,
+    /// keeping the version number. The new checkpoint is written to a temporary file first, and
+    /// the old log is only moved aside (as a marker of the compaction) before that file replaces
+    /// the checkpoint. A compaction interrupted after that point is completed the next time the
+    /// database is opened writable, so the log is never replayed on top of the new checkpoint.
+    pub fn compact(&mut self) -> Result<()> {
+        self.check_writable()?;
+        self.close_log()?;
+        let naming = &self.options.naming;
+        let filename = naming.checkpoint(self.version);
+        self.write_data(
+            &self.path.join(naming.with_ext(&filename, TMP_EXT)),
+            &self.data,
         )?;
+        self.sync_dir()?;
+        let log_path = self.create_logfile_if_required()?;
+        let compact_filename = naming.with_ext(&naming.log(self.version), COMPACT_EXT);
+        self.backend
+            .rename(&log_path, &self.path.join(compact_filename))?;
+        self.sync_dir()?;
+        self.finish_compaction()?;
+        self.poisoned = false;
+        self.log_segment = 0;
+        self.segment_bytes = 0;
+        self.log_bytes = 0;
+        self.unsynced_writes = 0;
+        self.updates_since_checkpoint = 0;
+        self.checkpoint_bytes = None;
         Ok(())
     }
 
-    fn cleanup(&self) -> Result<()> {
-        for entry in std::fs::read_dir(&self.path)? {
-            let entry = entry?;
-            if entry.metadata()?.is_file() {
-                if let Ok(filename) = entry.file_name().into_string() {
-                    if self.is_outdated_file(&filename) {
-                        std::fs::remove_file(entry.path())?;
-                    }
-                }
+    /// Whether there are updates since the last checkpoint, i.e. whether the log of the current
+    /// version is not empty, e.g. to decide whether to compact. Unlike replaying the log, this
+    /// only looks at its size.
+    pub fn has_pending_updates(&self) -> Result<bool> {
+        // updates still buffered by the log writer are not on disk yet
+        if self.log_bytes > 0 {
+            return Ok(true);
+        }
+        for (_, filename) in self.log_segments(self.version)? {
+            let path = self.path.join(filename);
+            if self.backend.exists(&path)? && self.backend.file_size(&path)? > 0 {
+                return Ok(true);
             }
         }
-        Ok(())
+        Ok(false)
     }
 
-    fn is_outdated_file(&self, filename: &str) -> bool {
-        if filename == NEW_VERSION_FILE {
-            return true;
+    /// Collects some statistics about the current version, e.g. for monitoring.
+    pub fn stats(&self) -> Result<DbStats> {
+        let naming = &self.options.naming;
+        let mut checkpoint_path = self.path.join(naming.checkpoint(self.version));
+        if !self.backend.exists(&checkpoint_path)? {
+            checkpoint_path = self
+                .path
+                .join(naming.with_ext(&naming.checkpoint(self.version), DIFF_EXT));
+        }
+        let mut log_file_bytes = 0;
+        for (_, filename) in self.log_segments(self.version)? {
+            log_file_bytes += self.backend.file_size(&self.path.join(filename))?;
+        }
+        Ok(DbStats {
+            version: self.version,
+            log_file_bytes,
+            checkpoint_file_bytes: self.backend.file_size(&checkpoint_path)?,
+            updates_since_checkpoint: self.updates_since_checkpoint,
+        })
+    }
+
+    /// Checks that the files of the current version are intact, i.e. that the version file
+    /// points to it, that its checkpoint matches its hash and can be deserialized, and that
+    /// its log can be decoded completely. Nothing is modified, and all problems are collected
+    /// in the report. Updates still buffered by the backend may look like a truncated log, so
+    /// call [`Database::flush`] first.
+    pub fn verify(&self) -> Result<VerifyReport> {
+        let version_path = self.path.join(&self.options.naming.version_file);
+        let version_file = match self.backend.read(&version_path) {
+            Ok(content) => {
+                let content = String::from_utf8_lossy(&content).into_owned();
+                (content.parse() != Ok(self.version)).then_some(BjwError::VersionParse(content))
+            }
+            Err(e) => Some(e.into()),
+        };
+        let checkpoint = self.load_checkpoint_or_diff(self.version, None).err();
+
+        let mut report = VerifyReport {
+            version: self.version,
+            version_file,
+            checkpoint,
+            log_entries: 0,
+            log_truncated: false,
+            log: None,
+        };
+        let segments = match self.log_segments(self.version) {
+            Ok(segments) => segments,
+            Err(e) => {
+                report.log = Some(e);
+                return Ok(report);
+            }
         };
-        if let Some((base, ext)) = filename.rsplit_once(DELIM) {
-            if base == CHECKPOINT_PREFIX || base == LOG_PREFIX {
-                if let Ok(version) = ext.parse::<u64>() {
-                    if version < self.version {
-                        return true;
+        for (_, filename) in segments {
+            match self.open_log(&filename) {
+                Ok(Some((reader, _))) => {
+                    for args in self.decode_log(reader) {
+                        match args {
+                            Ok(_) => report.log_entries += 1,
+                            Err(BjwError::Corrupt(_)) => report.log_truncated = true,
+                            Err(e) => report.log = Some(e),
+                        }
                     }
                 }
+                Ok(None) => {}
+                Err(e) => report.log = Some(e),
             }
         }
-        false
+        Ok(report)
     }
-}
 
-impl<T: Clone, F> Database<T, F> {
-    pub fn clone_data(&self) -> T {
-        self.data.clone()
+    /// Rewrites a damaged database from its live state, which was rebuilt when it was opened from
+    /// the newest checkpoint that could be loaded plus all log entries that could be decoded.
+    /// Open it with [`DatabaseBuilder::fallback_to_previous_checkpoint`] if its current
+    /// checkpoint is corrupt. If [`Database::verify`] finds a problem, a new checkpoint of the
+    /// live state is created, which starts a fresh log as well.
+    ///
+    /// This discards what could not be decoded: the corrupt entry a log ends in, together with
+    /// anything appended to it before the database was opened (which replaying stopped short
+    /// of), and all updates of a corrupt checkpoint that were not in the previous log. Returns
+    /// the problems found before repairing.
+    pub fn repair(&mut self) -> Result<VerifyReport> {
+        self.check_writable()?;
+        self.flush()?;
+        let report = self.verify()?;
+        if !report.is_ok() {
+            log::warn!("Repairing version {}: {report:?}", self.version);
+            self.create_checkpoint()?;
+        }
+        Ok(report)
     }
-}
 
-#[cfg(feature = "json")]
-use std::marker::PhantomData;
-
-#[cfg(feature = "json")]
-pub struct JsonFormat<T> {
-    _phantom: PhantomData<T>,
-}
+    /// Reconstructs the state as of `version` from its checkpoint and log, without touching the
+    /// live data. Only versions whose files were not cleaned up yet are available, so usually
+    /// just the current one (see [`DatabaseBuilder::fallback_to_previous_checkpoint`] and
+    /// [`Database::list_versions`]).
+    pub fn view_at(&self, version: u64) -> Result<T> {
+        let mut data = self.read_checkpoint_file(version)?;
+        self.replay_log(version, &mut data)?;
+        Ok(data)
+    }
 
-#[cfg(feature = "json")]
-impl<T> DataFormat for JsonFormat<T>
-where
-    T: Serialize + DeserializeOwned + Updateable + Readable,
-{
-    type Data = T;
+    /// Decodes the updates recorded in the log of the current version, in the order they were
+    /// applied, e.g. for auditing. A corrupt entry at the end of the log (or of one of its
+    /// segments) shows up as a [`BjwError::Corrupt`]. Updates still buffered by the backend are
+    /// missing unless [`Database::flush`] is called first.
+    pub fn iter_updates(
+        &self,
+    ) -> Result<impl Iterator<Item = Result<<T as Updateable>::Args>> + '_> {
+        Ok(self
+            .iter_updates_with_timestamps()?
+            .map(|update| update.map(|(_, args)| args)))
+    }
 
-    fn new() -> Self {
-        JsonFormat::<T> {
-            _phantom: PhantomData,
-        }
+    /// Folds the updates of [`Database::iter_updates`] into `init`, e.g. to compute a projection
+    /// of the log that is not part of the data itself. The data is not touched.
+    pub fn fold_updates<S>(
+        &self,
+        init: S,
+        mut f: impl FnMut(S, &<T as Updateable>::Args) -> S,
+    ) -> Result<S> {
+        self.iter_updates()?
+            .try_fold(init, |state, args| Ok(f(state, &args?)))
     }
 
-    fn serialize_data(&self, data: &Self::Data) -> Result<Vec<u8>> {
-        Ok(serde_json::to_string(data)?.as_bytes().to_vec())
+    /// The number of updates recorded in the log of each version of [`Database::list_versions`],
+    /// as an overview of activity over time that reconstructs no state. A version whose log was
+    /// cleaned up or never written to counts none, a corrupt entry fails like in
+    /// [`Database::iter_updates`].
+    pub fn history(&self) -> Result<Vec<(u64, usize)>> {
+        self.list_versions()?
+            .into_iter()
+            .map(|version| {
+                let updates = self
+                    .iter_version_updates(version)?
+                    .try_fold(0, |count, update| update.map(|_| count + 1))?;
+                Ok((version, updates))
+            })
+            .collect()
     }
 
-    fn deserialize_data(&self, input: &[u8]) -> Result<Self::Data> {
-        let str = std::str::from_utf8(input)
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
-        Ok(serde_json::from_str(str)?)
+    /// Serializes the current state with the database's format and writes it to `writer`,
+    /// without going through a checkpoint. Read it back with [`DataFormat::deserialize_data`].
+    pub fn export<W: Write>(&self, mut writer: W) -> Result<()> {
+        self.fmt.serialize_data_to(&self.data, &mut writer)?;
+        writer.flush()?;
+        Ok(())
     }
 
-    fn serialize_params(&self, params: &<Self::Data as Updateable>::Args) -> Result<Vec<u8>> {
-        let mut string = serde_json::to_string(params)?;
-        string.push('\n');
-        Ok(string.as_bytes().to_vec())
+    /// Replaces the state with one serialized by [`Database::export`] (or any other output of
+    /// [`DataFormat::serialize_data`]) and persists it as a new version. Nothing changes if
+    /// `reader` does not hold valid data.
+    pub fn import<R: Read>(&mut self, mut reader: R) -> Result<()> {
+        self.check_writable()?;
+        let mut ser = Vec::new();
+        reader.read_to_end(&mut ser)?;
+        self.data = self.fmt.deserialize_data(&ser)?;
+        // the current log does not apply to the imported state, so it needs a fresh one
+        self.create_checkpoint()
     }
 
-    fn deserialize_params(&self, input: &[u8]) -> Result<Vec<<Self::Data as Updateable>::Args>> {
-        let str = std::str::from_utf8(input)
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
-        let mut updates = Vec::new();
-        for line in str.split('\n') {
-            if line.is_empty() {
-                continue;
-            }
-            match serde_json::from_str(line) {
-                Ok(params) => updates.push(params),
-                Err(e) => {
-                    log::error!("Failed to deserialize an update (error: {e}); skipping all remaining ones!");
-                    return Ok(updates);
-                }
-            }
-        }
-        Ok(updates)
+    pub fn delete(mut self) -> Result<()> {
+        self.check_writable()?;
+        self.log_writer = None;
+        self.unsynced_writes = 0;
+        self.backend.remove_dir_all(&self.path)?;
+        Ok(())
     }
-}
 
-#[cfg(feature = "derive")]
-pub use bjw_db_derive::derive_bjw_db;
+    /// Writes out updates buffered by the backend and syncs the log, regardless of the
+    /// [`SyncPolicy`].
+    pub fn flush(&mut self) -> Result<()> {
+        self.sync_log()?;
+        self.release_updates();
+        Ok(())
+    }
 
-#[cfg(test)]
-#[cfg(feature = "derive")]
-mod tests {
-    use crate as bjw_db;
+    /// Flushes and syncs the log like [`Database::flush`], then closes the database. Dropping it
+    /// does the same, but can only log errors.
+    pub fn close(mut self) -> Result<()> {
+        self.close_log()
+    }
 
-    use super::*;
-    use serde::Deserialize;
-    use std::collections::BTreeMap;
-    use tempfile::TempDir;
+    /// Sends the updates queued for subscribers once the log is synced.
+    fn release_updates(&mut self) {
+        if self.unsynced_writes == 0 {
+            if let Some(subscribers) = &mut self.subscribers {
+                subscribers.release();
+            }
+        }
+    }
 
-    #[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
-    struct KeyValueStore {
-        store: BTreeMap<String, String>,
+    /// Flushes and closes the log writer, which is reopened on the next update.
+    fn close_log(&mut self) -> Result<()> {
+        self.flush()?;
+        self.log_writer = None;
+        Ok(())
     }
 
-    #[bjw_db_derive::derive_bjw_db]
-    impl KeyValueStore {
-        pub fn insert(&mut self, key: String, value: String) {
-            self.store.insert(key, value);
+    /// Everything that follows once `parameters` were logged and applied to the data.
+    fn finish_update(&mut self, parameters: &[<T as Updateable>::Args]) {
+        if let Some(subscribers) = &mut self.subscribers {
+            parameters
+                .iter()
+                .for_each(|params| subscribers.queue(params));
         }
+        self.release_updates();
+        self.updates_since_checkpoint += parameters.len() as u64;
+        self.auto_checkpoint_if_required();
+    }
 
-        pub fn insert_with_check(&mut self, key: String, value: String) -> bool {
-            if self.store.contains_key(&key) {
-                false
-            } else {
-                self.insert(key, value);
-                true
+    fn auto_checkpoint_if_required(&mut self) {
+        if self.auto_checkpoint_due() {
+            // the updates are already persisted, so a failure here is not fatal
+            if let Err(e) = self.create_checkpoint() {
+                log::warn!("Failed to create automatic checkpoint: {:?}", e);
             }
         }
+    }
 
-        pub fn get(&self, key: &str) -> Option<String> {
-            self.store.get(key).cloned()
+    /// Whether the log grew beyond [`DatabaseBuilder::auto_checkpoint_bytes`] or
+    /// [`DatabaseBuilder::auto_checkpoint_ratio`].
+    fn auto_checkpoint_due(&mut self) -> bool {
+        if let Some(threshold) = self.options.auto_checkpoint_bytes {
+            if self.log_bytes > threshold {
+                return true;
+            }
         }
-
-        pub fn unused(&self, _key: &str) -> u64 {
+        let Some(ratio) = self.options.auto_checkpoint_ratio else {
+            return false;
+        };
+        let checkpoint_bytes = match self.checkpoint_bytes {
+            Some(bytes) => bytes,
+            None => match self.backend.file_size(&self.current_checkpoint_path()) {
+                Ok(bytes) => *self.checkpoint_bytes.insert(bytes),
+                Err(e) => {
+                    log::warn!("Failed to get the size of the checkpoint: {:?}", e);
+                    return false;
+                }
+            },
+        };
+        self.log_bytes as f64 > ratio * checkpoint_bytes as f64
+    }
+
+    fn check_writable(&self) -> Result<()> {
+        if self.options.read_only {
+            return Err(std::io::Error::new(
+                ErrorKind::PermissionDenied,
+                "Database was opened read-only",
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Pulls in the updates another process appended to the log since the database was opened or
+    /// last refreshed, which is only possible with [`Database::open_read_only`], e.g. for a
+    /// replica. The log is read on from where it was consumed, but if the writer created a new
+    /// checkpoint in the meantime, that one is loaded instead. Returns the number of updates
+    /// applied.
+    ///
+    /// An update the writer is just appending cannot be decoded yet. The log is only consumed up
+    /// to the last complete update, so a later refresh reads on from there and picks it up once
+    /// it is complete.
+    pub fn refresh(&mut self) -> Result<usize> {
+        if !self.options.read_only {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                "Only databases opened read-only can be refreshed",
+            )
+            .into());
+        }
+        let version_path = self.path.join(&self.options.naming.version_file);
+        let version_str = String::from_utf8_lossy(&self.backend.read(&version_path)?).into_owned();
+        let version = version_str
+            .parse()
+            .map_err(|_| BjwError::VersionParse(version_str))?;
+        if version != self.version {
+            self.version = version;
+            self.updates_since_checkpoint = 0;
+            self.checkpoint_bytes = None;
+            return self.reload();
+        }
+        let mut applied = Vec::new();
+        let mut consumed = (self.log_segment, self.segment_bytes);
+        for (segment, filename) in self.log_segments(self.version)? {
+            if segment < self.log_segment {
+                continue;
+            }
+            let offset = if segment == self.log_segment {
+                self.segment_bytes
+            } else {
+                0
+            };
+            // the writer emptied the log by compacting it
+            if self.backend.file_size(&self.path.join(&filename))? < offset {
+                return self.reload();
+            }
+            // the header tells whether the segment is timestamped, so it is read in any case
+            let mut input = Vec::new();
+            let (timestamped, start) = {
+                let mut reader = self.backend.open_reader(&self.path.join(filename))?;
+                let (timestamped, head) = read_log_header(&mut reader)?;
+                // the updates of a timestamped segment start after its magic
+                let (start, read) = if timestamped {
+                    let magic = TIMESTAMP_MAGIC.len() as u64;
+                    (offset.max(magic), magic)
+                } else {
+                    (offset, head.len() as u64)
+                };
+                input.extend(head.iter().skip(start as usize));
+                std::io::copy(
+                    &mut reader.by_ref().take(start.saturating_sub(read)),
+                    &mut std::io::sink(),
+                )?;
+                reader.read_to_end(&mut input)?;
+                (timestamped, start)
+            };
+            // an update the writer is just appending is left for the next refresh
+            let (updates, len) = self.decode_log_prefix(&input, timestamped)?;
+            applied.extend(updates);
+            let end = start + len as u64;
+            consumed = (segment, end);
+            self.log_bytes += end - offset;
+        }
+        for params in &applied {
+            self.data.update(params);
+        }
+        (self.log_segment, self.segment_bytes) = consumed;
+        self.updates_since_checkpoint += applied.len() as u64;
+        Ok(applied.len())
+    }
+
+    /// Loads the current version from scratch for [`Database::refresh`], returning how many
+    /// more updates of its log were applied than before.
+    fn reload(&mut self) -> Result<usize> {
+        let before = self.updates_since_checkpoint;
+        self.log_segment = 0;
+        self.segment_bytes = 0;
+        self.log_bytes = 0;
+        self.load_latest(None)?;
+        Ok(self.updates_since_checkpoint.saturating_sub(before) as usize)
+    }
+
+    /// Continues appending to the log of the current version without reading it, as
+    /// [`Database::open_append_only`] does instead of [`Database::load_latest`].
+    fn resume_log(&mut self) -> Result<()> {
+        for (segment, filename) in self.log_segments(self.version)? {
+            self.log_segment = segment;
+            self.segment_bytes = self.backend.file_size(&self.path.join(filename))?;
+            self.log_bytes += self.segment_bytes;
+        }
+        Ok(())
+    }
+
+    /// Loads the checkpoint of the current version and replays its log. If the checkpoint cannot
+    /// be loaded and the fallback is enabled, the previous checkpoint is loaded together with its
+    /// log instead. Also returns whether the checkpoint had to be migrated.
+    fn load_latest(&mut self, migration: Option<&Migration<T>>) -> Result<(ReplayReport, bool)> {
+        let (mut data, fallback, migrated) = match self
+            .load_checkpoint_or_diff(self.version, migration)
+        {
+            Ok((data, migrated, diff_base)) => {
+                self.diff_base = diff_base;
+                (data, ReplayReport::default(), migrated)
+            }
+            Err(e) if self.options.checkpoint_fallback && self.version > 0 => {
+                let e = self.explain_missing_checkpoint(e);
+                log::error!(
+                    "Failed to read checkpoint {} (error: {e}); falling back to the previous one!",
+                    self.version
+                );
+                // versions may have been skipped, see `create_checkpoint_with_version`
+                let previous = self
+                    .list_versions()
+                    .ok()
+                    .and_then(|versions| versions.into_iter().rfind(|v| *v < self.version))
+                    .unwrap_or(self.version - 1);
+                let Ok((mut data, migrated, _)) = self.load_checkpoint_or_diff(previous, migration)
+                else {
+                    return Err(e);
+                };
+                let (report, _) = self.replay_log(previous, &mut data).map_err(|_| e)?;
+                (data, report, migrated)
+            }
+            Err(e) => return Err(self.explain_missing_checkpoint(e)),
+        };
+        let max = (!self.options.read_only)
+            .then_some(self.options.max_replay_updates)
+            .flatten();
+        let (report, log_bytes, carried) =
+            self.replay_log_checkpointing(self.version, &mut data, max)?;
+        self.data = data;
+        if let Some(carried) = carried {
+            self.switch_to_carried_log(carried)?;
+        } else {
+            self.use_replayed_log(&report, log_bytes)?;
+        }
+        Ok((
+            ReplayReport {
+                entries_applied: fallback.entries_applied + report.entries_applied,
+                truncated: fallback.truncated || report.truncated,
+            },
+            migrated,
+        ))
+    }
+
+    /// Continues appending to the log of the current version after it was replayed.
+    fn use_replayed_log(&mut self, report: &ReplayReport, log_bytes: u64) -> Result<()> {
+        self.log_bytes = log_bytes;
+        // appending continues in the last segment, unless it ends in a corrupt entry
+        if let Some((segment, filename)) = self.log_segments(self.version)?.pop() {
+            self.log_segment = segment;
+            // a replica keeps reading the segment after its last complete update, see `refresh`
+            self.segment_bytes = if report.truncated && self.options.read_only {
+                self.valid_segment_len(&filename)?
+            } else {
+                self.backend.file_size(&self.path.join(filename))?
+            };
+            if report.truncated
+                && self.options.log_segment_bytes.is_some()
+                && !self.options.read_only
+            {
+                self.log_segment += 1;
+                self.segment_bytes = 0;
+            }
+        }
+        self.updates_since_checkpoint = report.entries_applied as u64;
+        Ok(())
+    }
+
+    /// Replays the logs of all versions up to the current one onto the data, which is still the
+    /// initial one, see [`DatabaseBuilder::rebuild_from_logs`].
+    fn rebuild_from_logs(&mut self) -> Result<ReplayReport> {
+        if self.options.read_only || self.options.append_only {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                "Only databases opened writable and not append-only can be rebuilt",
+            )
+            .into());
+        }
+        let naming = &self.options.naming;
+        let mut versions = self.list_versions()?;
+        versions.extend(
+            self.backend
+                .list_files(&self.path)?
+                .iter()
+                .filter_map(|filename| {
+                    naming
+                        .split_log_segment(naming.strip_ext(filename, ZST_EXT).unwrap_or(filename))
+                })
+                .map(|(version, _)| version),
+        );
+        versions.retain(|version| *version <= self.version);
+        versions.sort_unstable();
+        versions.dedup();
+        // versions skipped by `create_checkpoint_with_version` have no files, but only cleaning
+        // up removes the oldest ones
+        let oldest = versions.first().copied().unwrap_or(self.version);
+        if oldest > 0 {
+            return Err(BjwError::Corrupt(format!(
+                "Cannot rebuild version {} from the logs, those before version {oldest} were \
+                 cleaned up",
+                self.version
+            )));
+        }
+        let mut report = ReplayReport::default();
+        for version in versions {
+            // every version starts with a log, only that of the current one may still be missing
+            // after a compaction
+            let segments = self.log_segments(version)?;
+            if segments.is_empty() && version < self.version {
+                return Err(BjwError::Corrupt(format!(
+                    "Cannot rebuild version {} from the logs, that of version {version} is missing",
+                    self.version
+                )));
+            }
+            for (_, filename) in segments {
+                let Some((reader, _)) = self.open_log(&filename)? else {
+                    continue;
+                };
+                // decoded first, as the data is updated in place
+                let updates: Vec<_> = self.decode_log(reader).collect();
+                for update in updates {
+                    match update {
+                        Ok((_, params)) => {
+                            self.data.update(&params);
+                            report.entries_applied += 1;
+                        }
+                        Err(BjwError::Corrupt(e)) if version == self.version => {
+                            log::error!("{e}; skipping all remaining updates of {filename}!");
+                            report.truncated = true;
+                            break;
+                        }
+                        Err(BjwError::Corrupt(e)) => {
+                            return Err(BjwError::Corrupt(format!(
+                                "{e} in {filename}, so version {} cannot be rebuilt",
+                                self.version
+                            )));
+                        }
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+        }
+        log::warn!(
+            "Rebuilt version {} from {} updates in the logs",
+            self.version,
+            report.entries_applied
+        );
+        Ok(report)
+    }
+
+    /// Replaces the error of loading the checkpoint of the current version by one naming the
+    /// latest checkpoint that is present, if the checkpoint does not exist at all, e.g. because
+    /// the version file was changed by hand.
+    fn explain_missing_checkpoint(&self, e: BjwError) -> BjwError {
+        let naming = &self.options.naming;
+        let checkpoint = naming.checkpoint(self.version);
+        let exists =
+            |filename: &str| !matches!(self.backend.exists(&self.path.join(filename)), Ok(false));
+        if exists(&checkpoint) || exists(&naming.with_ext(&checkpoint, DIFF_EXT)) {
+            return e;
+        }
+        let Ok(versions) = self.list_versions() else {
+            return e;
+        };
+        let present = match versions.last().copied() {
+            Some(version) => format!("the latest one present is {}", naming.checkpoint(version)),
+            None => "there is none at all".to_string(),
+        };
+        BjwError::Corrupt(format!(
+            "The version file points at version {}, but {checkpoint} does not exist and {present}",
+            self.version
+        ))
+    }
+
+    /// Applies the log of `version` to `data`, returning how that went and the log's size. A
+    /// corrupt entry skips the rest of its segment, see [`DatabaseBuilder::log_segment_bytes`].
+    fn replay_log(&self, version: u64, data: &mut T) -> Result<(ReplayReport, u64)> {
+        self.replay_log_checkpointing(version, data, None)
+            .map(|(report, log_bytes, _)| (report, log_bytes))
+    }
+
+    /// Like [`Database::replay_log`], but checkpoints `data` as the next version whenever `max`
+    /// updates were applied since the last checkpoint, and copies the rest of the log into the
+    /// log of that version. Returns the log of the last version checkpointed like this.
+    fn replay_log_checkpointing(
+        &self,
+        version: u64,
+        data: &mut T,
+        max: Option<usize>,
+    ) -> Result<(ReplayReport, u64, Option<CarriedLog>)> {
+        let mut report = ReplayReport::default();
+        let mut log_bytes = 0;
+        let mut carried: Option<CarriedLog> = None;
+        let mut since_checkpoint = 0;
+        for (_, filename) in self.log_segments(version)? {
+            let Some((reader, segment_bytes)) = self.open_log(&filename)? else {
+                continue;
+            };
+            log_bytes += segment_bytes;
+            for params in self.decode_log(reader) {
+                match params {
+                    Ok((timestamp, params)) => {
+                        if max.is_some_and(|max| since_checkpoint >= max.max(1)) {
+                            let next = match carried.take() {
+                                Some(mut previous) => {
+                                    self.finish_carried_log(&mut previous)?;
+                                    previous.version + 1
+                                }
+                                None => {
+                                    // files of newer versions are left over from an interrupted
+                                    // checkpoint, and must not be mixed into the new ones
+                                    self.cleanup(None)?;
+                                    version + 1
+                                }
+                            };
+                            carried = Some(self.start_carried_log(next, data)?);
+                            since_checkpoint = 0;
+                        }
+                        data.update(&params);
+                        report.entries_applied += 1;
+                        since_checkpoint += 1;
+                        if let Some(carried) = &mut carried {
+                            self.carry_update(carried, timestamp, &params)?;
+                        }
+                    }
+                    Err(BjwError::Corrupt(e)) => {
+                        log::error!("{e}; skipping all remaining updates of {filename}!");
+                        report.truncated = true;
+                        break;
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        if let Some(carried) = &mut carried {
+            self.finish_carried_log(carried)?;
+        }
+        Ok((report, log_bytes, carried))
+    }
+
+    /// Checkpoints `data` as `version`, and starts its log empty.
+    fn start_carried_log(&self, version: u64, data: &T) -> Result<CarriedLog> {
+        self.write_checkpoint(version, data)?;
+        let path = self.path.join(self.options.naming.log_segment(version, 0));
+        let writer = self.backend.file_writer(&path)?;
+        if writer.is_none() {
+            self.backend.write(&path, &[])?;
+        }
+        self.sync_dir()?;
+        Ok(CarriedLog {
+            version,
+            path,
+            writer,
+            unwritten: None,
+            timestamped: self.options.log_timestamps,
+            last_timestamp: self.last_timestamp,
+            bytes: 0,
+            updates: 0,
+        })
+    }
+
+    /// Appends a replayed update to `carried`, keeping the time it was originally appended at.
+    fn carry_update(
+        &self,
+        carried: &mut CarriedLog,
+        timestamp: Option<SystemTime>,
+        params: &<T as Updateable>::Args,
+    ) -> Result<()> {
+        let mut ser = self.fmt.serialize_params(params)?;
+        if carried.timestamped {
+            let nanos = timestamp.map_or(carried.last_timestamp, nanos_since_epoch);
+            carried.last_timestamp = carried.last_timestamp.max(nanos);
+            ser = timestamped_frame(&ser, nanos, carried.bytes == 0)?;
+        }
+        carried.bytes += ser.len() as u64;
+        carried.updates += 1;
+        match &mut carried.writer {
+            Some(writer) => writer.write_all(&ser)?,
+            None => {
+                if let Some(previous) = carried.unwritten.replace(ser) {
+                    self.backend.append(&carried.path, &previous, false)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Makes everything appended to `carried` durable.
+    fn finish_carried_log(&self, carried: &mut CarriedLog) -> Result<()> {
+        if let Some(mut writer) = carried.writer.take() {
+            writer.sync()?;
+        }
+        if let Some(last) = carried.unwritten.take() {
+            self.backend.append(&carried.path, &last, true)?;
+        }
+        Ok(())
+    }
+
+    /// Continues with the version last checkpointed while replaying, whose checkpoint and log
+    /// hold everything replayed.
+    fn switch_to_carried_log(&mut self, carried: CarriedLog) -> Result<()> {
+        self.version = carried.version;
+        self.log_segment = 0;
+        self.segment_bytes = carried.bytes;
+        self.log_bytes = carried.bytes;
+        self.segment_timestamps = Some(carried.timestamped);
+        self.last_timestamp = self.last_timestamp.max(carried.last_timestamp);
+        self.updates_since_checkpoint = carried.updates;
+        self.diff_base = None;
+        self.checkpoint_bytes = None;
+        self.update_version_file()
+    }
+
+    /// How many bytes at the start of the log segment `filename` hold complete updates, which is
+    /// where [`Database::refresh`] reads on from.
+    fn valid_segment_len(&self, filename: &str) -> Result<u64> {
+        let mut reader = self.backend.open_reader(&self.path.join(filename))?;
+        let (timestamped, mut input) = read_log_header(&mut reader)?;
+        reader.read_to_end(&mut input)?;
+        let (_, len) = self.decode_log_prefix(&input, timestamped)?;
+        let magic = if timestamped {
+            TIMESTAMP_MAGIC.len()
+        } else {
+            0
+        };
+        Ok((magic + len) as u64)
+    }
+
+    /// Opens the log segment `filename` together with its size, if there is one. Logs of old
+    /// versions may be compressed, see [`DatabaseBuilder::compress_old_logs`].
+    fn open_log(&self, filename: &str) -> Result<Option<(Box<dyn BufRead + '_>, u64)>> {
+        let naming = &self.options.naming;
+        let log_path = self.path.join(filename);
+        if self.backend.exists(&log_path)? {
+            let log_bytes = self.backend.file_size(&log_path)?;
+            return Ok(Some((self.backend.open_reader(&log_path)?, log_bytes)));
+        }
+        let zst_path = self.path.join(naming.with_ext(filename, ZST_EXT));
+        if !self.backend.exists(&zst_path)? {
+            return Ok(None);
+        }
+        #[cfg(feature = "zstd")]
+        {
+            let ser = zstd::decode_all(self.backend.open_reader(&zst_path)?)
+                .map_err(BjwError::deserialize)?;
+            let log_bytes = ser.len() as u64;
+            Ok(Some((Box::new(std::io::Cursor::new(ser)), log_bytes)))
+        }
+        #[cfg(not(feature = "zstd"))]
+        Err(std::io::Error::other(format!(
+            "{} can only be read with the zstd feature",
+            zst_path.display()
+        ))
+        .into())
+    }
+
+    fn create_logfile_if_required(&self) -> Result<PathBuf> {
+        self.create_log_segment_if_required(0)
+    }
+
+    fn create_log_segment_if_required(&self, segment: u64) -> Result<PathBuf> {
+        let path = self
+            .path
+            .join(self.options.naming.log_segment(self.version, segment));
+        // never truncates a log another handle created in the meantime
+        if self.backend.create_new(&path)? {
+            self.sync_dir()?;
+        }
+        Ok(path)
+    }
+
+    fn extend_update_log(&mut self, ser: &[u8]) -> Result<()> {
+        let mut append = self.begin_append()?;
+        let written = self.write_append(&mut append, ser);
+        self.finish_append(append, ser, written)
+    }
+
+    /// Prepares appending to the log, the actual writing in [`Database::write_append`] only
+    /// needs shared access then, see [`SharedDatabase`].
+    fn begin_append(&mut self) -> Result<LogAppend> {
+        if self.poisoned {
+            return Err(std::io::Error::other(
+                "A failed update may have been left in the log, create a checkpoint or reopen \
+                 the database to continue",
+            )
+            .into());
+        }
+        self.rotate_log_if_required()?;
+        self.unsynced_writes += 1;
+        let sync = match self.options.sync_policy {
+            SyncPolicy::EveryWrite => true,
+            SyncPolicy::EveryN(n) => self.unsynced_writes >= n,
+            SyncPolicy::Never => false,
+        };
+        let path = if self.log_writer.is_none() {
+            let path = self.create_log_segment_if_required(self.log_segment)?;
+            self.log_writer = self.backend.log_writer(&path)?;
+            path
+        } else {
+            self.path.join(
+                self.options
+                    .naming
+                    .log_segment(self.version, self.log_segment),
+            )
+        };
+        let timestamp = if self.segment_timestamped(&path)? {
+            Some(self.next_timestamp())
+        } else {
+            None
+        };
+        Ok(LogAppend {
+            writer: self.log_writer.take(),
+            path,
+            sync,
+            timestamp,
+            starts_segment: self.segment_bytes == 0,
+        })
+    }
+
+    fn write_append(&self, append: &mut LogAppend, ser: &[u8]) -> Result<()> {
+        let framed;
+        let ser = match append.timestamp {
+            Some(nanos) => {
+                framed = timestamped_frame(ser, nanos, append.starts_segment)?;
+                &framed
+            }
+            None => ser,
+        };
+        match &mut append.writer {
+            Some(log_writer) => {
+                log_writer.write_all(ser)?;
+                if append.sync {
+                    log_writer.sync()?;
+                }
+            }
+            None => self.backend.append(&append.path, ser, append.sync)?,
+        }
+        Ok(())
+    }
+
+    /// Hands the log writer back, and accounts for `ser` if it was `written`.
+    fn finish_append(&mut self, append: LogAppend, ser: &[u8], written: Result<()>) -> Result<()> {
+        let bytes = append.framed_len(ser);
+        self.log_writer = append.writer;
+        if let Err(e) = written {
+            // the log must not have changed, or the failed update might still be replayed
+            let size = self.backend.file_size(&append.path);
+            self.poisoned = size.map_or(true, |size| size != self.segment_bytes);
+            return Err(e);
+        }
+        if append.sync {
+            self.unsynced_writes = 0;
+        }
+        self.log_bytes += bytes;
+        self.segment_bytes += bytes;
+        Ok(())
+    }
+
+    /// Starts the next segment of the log once the current one is full, after syncing it, see
+    /// [`DatabaseBuilder::log_segment_bytes`].
+    fn rotate_log_if_required(&mut self) -> Result<()> {
+        match self.options.log_segment_bytes {
+            Some(limit) if self.segment_bytes > 0 && self.segment_bytes >= limit => {}
+            _ => return Ok(()),
+        }
+        self.close_log()?;
+        self.log_segment += 1;
+        self.segment_bytes = 0;
+        Ok(())
+    }
+
+    fn read_checkpoint_file(&self, version: u64) -> Result<T> {
+        self.load_checkpoint_or_diff(version, None)
+            .map(|(data, _, _)| data)
+    }
+
+    /// Loads the checkpoint of `version` like [`Database::load_checkpoint`], which may also be a
+    /// diff against an older full checkpoint. In that case, the full one is returned as well.
+    #[allow(clippy::type_complexity)]
+    fn load_checkpoint_or_diff(
+        &self,
+        version: u64,
+        migration: Option<&Migration<T>>,
+    ) -> Result<(T, bool, Option<(u64, T)>)> {
+        let naming = &self.options.naming;
+        let checkpoint = naming.checkpoint(version);
+        let diff_path = self.path.join(naming.with_ext(&checkpoint, DIFF_EXT));
+        if self.backend.exists(&self.path.join(&checkpoint))? || !self.backend.exists(&diff_path)? {
+            let (data, migrated) = self.load_checkpoint(version, migration)?;
+            return Ok((data, migrated, None));
+        }
+        let Some(load_diff) = self.diff_loader else {
+            return Err(std::io::Error::new(
+                ErrorKind::Unsupported,
+                format!("Checkpoint {version} is a diff, which requires DatabaseBuilder::diff_checkpoints"),
+            )
+            .into());
+        };
+        let ser = self.backend.read(&diff_path)?;
+        let Some((base_version, diff)) = ser.split_first_chunk::<8>() else {
+            return Err(BjwError::Corrupt(format!(
+                "Diff of checkpoint {version} is truncated"
+            )));
+        };
+        let base_version = u64::from_be_bytes(*base_version);
+        let (base, migrated) = self.load_checkpoint(base_version, migration)?;
+        let (base, data) = load_diff(&self.fmt, base, diff)?;
+        Ok((data, migrated, Some((base_version, base))))
+    }
+
+    /// Reads the checkpoint of `version` like [`Database::read_checkpoint_file`], but hands it to
+    /// `migration` if it cannot be deserialized. Also returns whether that was the case.
+    fn load_checkpoint(&self, version: u64, migration: Option<&Migration<T>>) -> Result<(T, bool)> {
+        let ser = self.read_checkpoint_bytes(version)?;
+        let ser = strip_checkpoint_header(&self.fmt, version, &ser)?;
+        match (self.deserialize_checkpoint(ser), migration) {
+            (Ok(data), _) => Ok((data, false)),
+            (Err(e), Some(migrate)) => {
+                log::info!("Migrating checkpoint {version} (error: {e})");
+                Ok((migrate(ser, version)?, true))
+            }
+            (Err(e), None) => Err(e),
+        }
+    }
+
+    /// Deserializes the content of a checkpoint file, which may be sharded.
+    fn deserialize_checkpoint(&self, ser: &[u8]) -> Result<T> {
+        #[cfg(feature = "parallel")]
+        if let Some(shards) = parallel::split_shards(ser) {
+            let Some(sharding) = &self.sharding else {
+                return Err(std::io::Error::new(
+                    ErrorKind::Unsupported,
+                    "The checkpoint is sharded, which requires DatabaseBuilder::parallel_checkpoints",
+                )
+                .into());
+            };
+            return (sharding.deserialize)(&self.fmt, &shards?, sharding.threads);
+        }
+        self.fmt.deserialize_data(ser)
+    }
+
+    /// The content of the checkpoint of `version`, after checking it against its hash.
+    fn read_checkpoint_bytes(&self, version: u64) -> Result<Box<dyn Deref<Target = [u8]> + '_>> {
+        let filename = self.options.naming.checkpoint(version);
+        let path = self.path.join(&filename);
+        if !self.backend.exists(&path)? {
+            return Err(std::io::Error::new(
+                ErrorKind::NotFound,
+                format!("Checkpoint {version} does not exist (anymore)"),
+            )
+            .into());
+        }
+        let ser = if self.fmt.supports_mmap() {
+            self.backend.read_mapped(&path)?
+        } else {
+            Box::new(self.backend.read(&path)?)
+        };
+        // databases created before hashes were introduced have no hash files
+        let hash_path = self
+            .path
+            .join(self.options.naming.with_ext(&filename, HASH_EXT));
+        if self.backend.exists(&hash_path)? && self.backend.read(&hash_path)? != sha256_hex(&ser) {
+            return Err(BjwError::Corrupt(format!(
+                "Checkpoint {version} does not match its hash"
+            )));
+        }
+        Ok(ser)
+    }
+
+    fn write_checkpoint_file(&self) -> Result<()> {
+        self.write_checkpoint(self.version, &self.data)
+    }
+
+    /// Writes `data` as the checkpoint of `version`, which need not be the current one.
+    fn write_checkpoint(&self, version: u64, data: &T) -> Result<()> {
+        // write to a temporary file first, so the checkpoint only ever appears completely
+        let naming = &self.options.naming;
+        let filename = naming.checkpoint(version);
+        let tmp_filename = naming.with_ext(&filename, TMP_EXT);
+        let hash = self.write_data(&self.path.join(&tmp_filename), data)?;
+        self.backend
+            .rename(&self.path.join(tmp_filename), &self.path.join(&filename))?;
+        self.backend
+            .write(&self.path.join(naming.with_ext(&filename, HASH_EXT)), &hash)?;
+        self.sync_dir()?;
+        Ok(())
+    }
+
+    /// Serializes `data` into the file at `path`, without holding all of it in memory if the
+    /// backend supports that, and returns the hash of the file.
+    fn write_data(&self, path: &Path, data: &T) -> Result<Vec<u8>> {
+        let header = checkpoint_header(&self.fmt);
+        #[cfg(feature = "parallel")]
+        if let Some(sharding) = &self.sharding {
+            let mut ser = header;
+            ser.extend((sharding.serialize)(&self.fmt, data, sharding.threads)?);
+            self.backend.write(path, &ser)?;
+            return Ok(sha256_hex(&ser));
+        }
+        let Some(writer) = self.backend.file_writer(path)? else {
+            let mut ser = header;
+            self.fmt.serialize_data_to(data, &mut ser)?;
+            self.backend.write(path, &ser)?;
+            return Ok(sha256_hex(&ser));
+        };
+        let mut writer = HashingWriter {
+            inner: writer,
+            hasher: sha2::Sha256::default(),
+        };
+        writer.write_all(&header)?;
+        self.fmt.serialize_data_to(data, &mut writer)?;
+        writer.inner.sync()?;
+        Ok(hex(&sha2::Digest::finalize(writer.hasher)))
+    }
+
+    /// The second half of [`Database::compact`], which only runs once the old log was moved aside,
+    /// so it can be repeated safely after a crash.
+    fn finish_compaction(&self) -> Result<()> {
+        let naming = &self.options.naming;
+        let filename = naming.checkpoint(self.version);
+        let path = self.path.join(&filename);
+        let tmp_path = self.path.join(naming.with_ext(&filename, TMP_EXT));
+        // the temporary file is gone if it already replaced the checkpoint
+        if self.backend.exists(&tmp_path)? {
+            self.backend.rename(&tmp_path, &path)?;
+        }
+        let ser = self.backend.read(&path)?;
+        self.backend.write(
+            &self.path.join(naming.with_ext(&filename, HASH_EXT)),
+            &sha256_hex(&ser),
+        )?;
+        let log_filename = naming.log(self.version);
+        self.backend.write(&self.path.join(&log_filename), &[])?;
+        // the other segments belong to the old log as well
+        for (segment, filename) in self.log_segments(self.version)? {
+            if segment > 0 {
+                self.backend.remove_file(&self.path.join(filename))?;
+            }
+        }
+        self.sync_dir()?;
+        self.backend
+            .remove_file(&self.path.join(naming.with_ext(&log_filename, COMPACT_EXT)))?;
+        self.sync_dir()
+    }
+
+    /// Falls back to the highest version with an intact checkpoint if the version file is
+    /// damaged, e.g. empty after a crash, and rewrites it unless opened read-only.
+    fn recover_version(&mut self, version_str: String) -> Result<()> {
+        let naming = &self.options.naming;
+        let mut versions: Vec<u64> = self
+            .backend
+            .list_files(&self.path)?
+            .iter()
+            .filter_map(|filename| naming.split_version(filename))
+            .filter(|(prefix, _)| *prefix == naming.checkpoint_prefix)
+            .map(|(_, version)| version)
+            .collect();
+        versions.sort_unstable();
+        // without its hash, a checkpoint cannot be told apart from a partially written one
+        let Some(version) = versions.into_iter().rev().find(|&version| {
+            let hash_filename = naming.with_ext(&naming.checkpoint(version), HASH_EXT);
+            matches!(
+                self.backend.exists(&self.path.join(hash_filename)),
+                Ok(true)
+            ) && self.read_checkpoint_bytes(version).is_ok()
+        }) else {
+            return Err(BjwError::VersionParse(version_str));
+        };
+        log::error!(
+            "Could not parse version {version_str:?}; recovering from checkpoint {version}!"
+        );
+        self.version = version;
+        if !self.options.read_only {
+            self.update_version_file()?;
+        }
+        Ok(())
+    }
+
+    fn update_version_file(&self) -> Result<()> {
+        let naming = &self.options.naming;
+        let new_version_path = self.path.join(naming.new_version_file());
+        self.backend
+            .write(&new_version_path, self.version.to_string().as_bytes())?;
+        self.backend
+            .rename(&new_version_path, &self.path.join(&naming.version_file))?;
+        self.sync_dir()?;
+        Ok(())
+    }
+
+    /// Creating and renaming files only becomes durable once the directory itself is synced.
+    fn sync_dir(&self) -> Result<()> {
+        Ok(self.backend.sync_dir(&self.path)?)
+    }
+
+    /// Cleans up after creating a checkpoint, unless disabled.
+    fn auto_cleanup(&self) {
+        if self.options.manual_cleanup {
+            return;
+        }
+        if let Err(e) = self.cleanup(Some(self.options.keep_versions)) {
+            log::warn!("Failed to cleanup: {:?}", e);
+        };
+    }
+
+    /// Removes leftovers and the files of other versions than the current one and the `keep`
+    /// before it, or of no older version at all without `keep`.
+    fn cleanup(&self, keep: Option<usize>) -> Result<()> {
+        // the previous version is needed to fall back to
+        let keep = keep.map(|keep| keep.max(self.options.checkpoint_fallback.into()));
+        // counted by the checkpoints present, as versions may have been skipped
+        let oldest_kept = match keep {
+            Some(0) => Some(self.version),
+            Some(keep) => {
+                let versions = self.list_versions()?;
+                let previous = versions.iter().rev().filter(|v| **v < self.version);
+                Some(previous.copied().nth(keep - 1).unwrap_or_default())
+            }
+            None => None,
+        };
+        for filename in self.backend.list_files(&self.path)? {
+            if self.is_outdated_file(&filename, oldest_kept) {
+                self.backend.remove_file(&self.path.join(filename))?;
+                continue;
+            }
+            #[cfg(feature = "zstd")]
+            if self.options.compress_old_logs {
+                let naming = &self.options.naming;
+                if matches!(naming.split_log_segment(&filename), Some((version, _))
+                    if version < self.version)
+                {
+                    self.compress_log(&filename)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces the old log `filename` by its compressed variant, which is made durable before
+    /// the original is removed.
+    #[cfg(feature = "zstd")]
+    fn compress_log(&self, filename: &str) -> Result<()> {
+        let path = self.path.join(filename);
+        let ser = zstd::encode_all(
+            self.backend.open_reader(&path)?,
+            zstd::DEFAULT_COMPRESSION_LEVEL,
+        )?;
+        let zst_filename = self.options.naming.with_ext(filename, ZST_EXT);
+        self.backend.write(&self.path.join(zst_filename), &ser)?;
+        self.sync_dir()?;
+        self.backend.remove_file(&path)?;
+        Ok(())
+    }
+
+    /// Whether `filename` is a leftover, or belongs to a version before `oldest_kept` or after
+    /// the current one.
+    fn is_outdated_file(&self, filename: &str, oldest_kept: Option<u64>) -> bool {
+        let naming = &self.options.naming;
+        if filename == naming.new_version_file() {
+            return true;
+        };
+        // leftover of an interrupted checkpoint
+        if let Some(base) = naming.strip_ext(filename, TMP_EXT) {
+            let base = naming.strip_ext(base, DIFF_EXT).unwrap_or(base);
+            return matches!(naming.split_version(base), Some((prefix, _)) if prefix == naming.checkpoint_prefix);
+        }
+        // hash and diff files share the fate of their checkpoint, compressed logs that of the log
+        let filename = naming
+            .strip_ext(filename, HASH_EXT)
+            .or_else(|| naming.strip_ext(filename, DIFF_EXT))
+            .or_else(|| naming.strip_ext(filename, ZST_EXT))
+            .unwrap_or(filename);
+        let diff_base = self.diff_base.as_ref().map(|(version, _)| *version);
+        let version = match naming.split_version(filename) {
+            Some((prefix, version))
+                if prefix == naming.checkpoint_prefix && Some(version) == diff_base =>
+            {
+                return false;
+            }
+            Some((_, version)) => version,
+            // further log segments share the fate of the log
+            None => match naming.split_log_segment(filename) {
+                Some((version, _)) => version,
+                None => return false,
+            },
+        };
+        oldest_kept.is_some_and(|oldest_kept| version < oldest_kept) || version > self.version
+    }
+}
+
+impl<T, F> DatabaseBuilder<T, F>
+where
+    T: Serialize + DeserializeOwned + Readable + Updateable,
+    F: DataFormat<Data = T>,
+{
+    /// Automatically creates a checkpoint once the current log (all of its segments) grows
+    /// beyond `bytes`.
+    pub fn auto_checkpoint_bytes(mut self, bytes: u64) -> Self {
+        self.options.auto_checkpoint_bytes = Some(bytes);
+        self
+    }
+
+    /// Automatically creates a checkpoint once the current log grows beyond `ratio` times the
+    /// size of the current checkpoint, so that a small database checkpoints sooner than a large
+    /// one. Can be combined with [`DatabaseBuilder::auto_checkpoint_bytes`], either triggers it.
+    pub fn auto_checkpoint_ratio(mut self, ratio: f64) -> Self {
+        self.options.auto_checkpoint_ratio = Some(ratio);
+        self
+    }
+
+    /// Creates a checkpoint while opening whenever `updates` were replayed from the log since the
+    /// last one, so that the next open replays at most `updates` again. This heals logs that grew
+    /// long without checkpoints, e.g. with [`DatabaseBuilder::auto_checkpoint_bytes`] unset.
+    /// Replay continues after each checkpoint, copying the rest of the log into the log of the
+    /// new version, which becomes the current one once the whole log was replayed. The open
+    /// exceeding the limit thus still reads the whole log, and writes all but the first
+    /// `updates` of it again.
+    pub fn max_replay_updates(mut self, updates: usize) -> Self {
+        self.options.max_replay_updates = Some(updates);
+        self
+    }
+
+    /// Opens the database without ever writing to it, see [`Database::open_read_only`].
+    pub fn read_only(mut self) -> Self {
+        self.options.read_only = true;
+        self
+    }
+
+    /// Ignores the checkpoint of the current version when opening, and rebuilds the data from
+    /// scratch instead, e.g. after that checkpoint was lost: the logs of all versions up to the
+    /// current one are replayed in order on top of the initial data (the default, or what
+    /// [`DatabaseBuilder::open_or_init`] creates), and a fresh checkpoint of the result is
+    /// created. This needs the logs of every version since the database was created, see
+    /// [`DatabaseBuilder::keep_versions`]. Opening fails if one of them was cleaned up or is
+    /// corrupt, only the log of the current version may end in a corrupt entry like after a
+    /// crash. The logs do not contain what was added by [`Database::import`] or a migration,
+    /// and no longer contain the updates removed by [`Database::compact`].
+    pub fn rebuild_from_logs(mut self) -> Self {
+        self.options.rebuild_from_logs = true;
+        self
+    }
+
+    /// Blocks until other handles holding the database's lock release it, instead of failing
+    /// with `WouldBlock`.
+    pub fn wait_for_lock(mut self) -> Self {
+        self.options.wait_for_lock = true;
+        self
+    }
+
+    /// Falls back to the previous checkpoint (and replays its log) if the current one is corrupt,
+    /// instead of failing to open. To make this possible, the files of the previous version are
+    /// kept around when creating a checkpoint.
+    pub fn fallback_to_previous_checkpoint(mut self) -> Self {
+        self.options.checkpoint_fallback = true;
+        self
+    }
+
+    /// Keeps the files of the `versions` versions before the current one when creating a
+    /// checkpoint, so they remain available to [`Database::view_at`] or for a manual rollback.
+    /// By default, only the current version is kept.
+    pub fn keep_versions(mut self, versions: usize) -> Self {
+        self.options.keep_versions = versions;
+        self
+    }
+
+    /// Compresses the logs of the versions kept by [`DatabaseBuilder::keep_versions`] with zstd
+    /// once they are old, i.e. whenever old files are cleaned up. They are decompressed when
+    /// read again, e.g. by [`Database::view_at`].
+    #[cfg(feature = "zstd")]
+    pub fn compress_old_logs(mut self) -> Self {
+        self.options.compress_old_logs = true;
+        self
+    }
+
+    /// Continues the log of a version in a new segment once the current one is at least `bytes`
+    /// large, which bounds the size of every log file. The first segment is the log itself
+    /// (`logfile.3`), the following ones get a number appended (`logfile.3.1`, `logfile.3.2`,
+    /// ...), and they are replayed in this order. A segment is synced before the next one is
+    /// started, so a corrupt entry at the end of a segment can only stem from a crash. Replaying
+    /// then skips the rest of that segment only, as a segment is also started after opening a
+    /// database whose log ended in a corrupt entry. Segments are read regardless of this option.
+    pub fn log_segment_bytes(mut self, bytes: u64) -> Self {
+        self.options.log_segment_bytes = Some(bytes);
+        self
+    }
+
+    /// Records when each update was appended in the log, which
+    /// [`Database::iter_updates_with_timestamps`] yields, e.g. for audits or to expire entries.
+    /// This adds 12 bytes to every update. Whether a log segment is timestamped is decided when
+    /// it is started, so the option can be turned on and off for an existing database.
+    pub fn log_timestamps(mut self) -> Self {
+        self.options.log_timestamps = true;
+        self
+    }
+
+    /// Removes the files of old versions whenever a checkpoint is created (the default), according
+    /// to [`DatabaseBuilder::keep_versions`]. Without it, all versions are kept until they are
+    /// removed with [`Database::prune`].
+    pub fn auto_cleanup(mut self, enabled: bool) -> Self {
+        self.options.manual_cleanup = !enabled;
+        self
+    }
+
+    /// Converts the checkpoint with `migrate` when it cannot be deserialized as `T`, e.g. because
+    /// it was written before a field was added. `migrate` gets the content of the checkpoint file
+    /// and its version. The log is replayed on top of the converted state as usual, and unless the
+    /// database is opened read-only, a new checkpoint in the current format is created right away.
+    pub fn migrate<M>(mut self, migrate: M) -> Self
+    where
+        M: Fn(&[u8], u64) -> Result<T> + Send + 'static,
+    {
+        self.migration = Some(Box::new(migrate));
+        self
+    }
+
+    /// Names the files of the database according to `naming` instead of the defaults, e.g. to
+    /// store several databases in the same directory. Each of them needs distinct names for all
+    /// its files, including the lock file.
+    pub fn naming(mut self, naming: Naming) -> Self {
+        self.options.naming = naming;
+        self
+    }
+
+    /// Stores the database via `backend` instead of the local file system.
+    pub fn backend<B: Backend + 'static>(mut self, backend: B) -> Self {
+        self.backend = Box::new(backend);
+        self
+    }
+
+    /// Sets how often the log is fsynced, see [`SyncPolicy`] for the tradeoffs.
+    pub fn sync_policy(mut self, policy: SyncPolicy) -> Self {
+        self.options.sync_policy = policy;
+        self
+    }
+
+    /// See [`Database::open_or_init`].
+    pub fn open_or_init<P, I>(self, path: P, init: I) -> Result<Database<T, F>>
+    where
+        P: AsRef<Path>,
+        I: FnOnce() -> T,
+    {
+        self.open_or_init_with_report(path, init).map(|(db, _)| db)
+    }
+
+    fn open_or_init_with_report<P, I>(
+        self,
+        path: P,
+        init: I,
+    ) -> Result<(Database<T, F>, ReplayReport)>
+    where
+        P: AsRef<Path>,
+        I: FnOnce() -> T,
+    {
+        let DatabaseBuilder {
+            fmt,
+            options,
+            backend,
+            migration,
+            diff_loader,
+            #[cfg(feature = "parallel")]
+            sharding,
+            ..
+        } = self;
+        let path = path.as_ref().to_path_buf();
+        let naming = &options.naming;
+        if backend.exists(&path)? && !backend.is_dir(&path)? {
+            return Err(std::io::Error::new(
+                ErrorKind::NotADirectory,
+                format!(
+                    "{} is not a directory, a database is a directory of files",
+                    path.display()
+                ),
+            )
+            .into());
+        }
+        // whether the database is new is only decided under the lock, as another handle may be
+        // creating it (or checkpointing it) while this one waits for the lock
+        let lock: Box<dyn Send + Sync> = if options.read_only {
+            Box::new(())
+        } else {
+            backend.create_dir_all(&path)?;
+            backend.lock(&path.join(&naming.lock_file), options.wait_for_lock)?
+        };
+        let is_new = !backend.exists(&path)?
+            || !backend
+                .list_files(&path)?
+                .iter()
+                .any(|filename| naming.owns(filename));
+        if is_new {
+            if options.read_only {
+                return Err(std::io::Error::new(
+                    ErrorKind::NotFound,
+                    "Cannot create a database in read-only mode",
+                )
+                .into());
+            }
+            let db = Database {
+                data: init(),
+                fmt,
+                path,
+                version: 0,
+                options,
+                backend,
+                log_bytes: 0,
+                unsynced_writes: 0,
+                updates_since_checkpoint: 0,
+                log_writer: None,
+                params_buf: Vec::new(),
+                checkpoint_bytes: None,
+                poisoned: false,
+                segment_timestamps: None,
+                last_timestamp: 0,
+                log_segment: 0,
+                segment_bytes: 0,
+                diff_base: None,
+                subscribers: None,
+                diff_loader,
+                #[cfg(feature = "parallel")]
+                sharding,
+                _lock: lock,
+            };
+            // never points an existing database back at the initial state, even if listing the
+            // directory missed its files (object stores may list them only eventually)
+            if db
+                .backend
+                .exists(&db.path.join(&db.options.naming.version_file))?
+            {
+                return Err(std::io::Error::new(
+                    ErrorKind::AlreadyExists,
+                    "The database was created by another handle while opening it",
+                )
+                .into());
+            }
+            db.write_checkpoint_file()?;
+            db.create_logfile_if_required()?;
+            db.update_version_file()?;
+            Ok((db, ReplayReport::default()))
+        } else {
+            let new_version_path = path.join(naming.new_version_file());
+            let version_path = path.join(&naming.version_file);
+            if backend.exists(&new_version_path)? && !options.read_only {
+                backend.rename(&new_version_path, &version_path)?;
+            }
+            let version_str = String::from_utf8_lossy(&backend.read(&version_path)?).into_owned();
+            let parsed_version = version_str.parse().ok();
+            let mut db = Database {
+                data: init(),
+                fmt,
+                path,
+                version: parsed_version.unwrap_or_default(),
+                options,
+                backend,
+                log_bytes: 0,
+                unsynced_writes: 0,
+                updates_since_checkpoint: 0,
+                log_writer: None,
+                params_buf: Vec::new(),
+                checkpoint_bytes: None,
+                poisoned: false,
+                segment_timestamps: None,
+                last_timestamp: 0,
+                log_segment: 0,
+                segment_bytes: 0,
+                diff_base: None,
+                subscribers: None,
+                diff_loader,
+                #[cfg(feature = "parallel")]
+                sharding,
+                _lock: lock,
+            };
+            if parsed_version.is_none() {
+                db.recover_version(version_str)?;
+            }
+            let version = db.version;
+            let naming = &db.options.naming;
+            let compact_path = db
+                .path
+                .join(naming.with_ext(&naming.log(version), COMPACT_EXT));
+            if db.backend.exists(&compact_path)? {
+                if db.options.read_only {
+                    return Err(std::io::Error::other(
+                        "An interrupted compaction has to be completed by opening the database writable",
+                    )
+                    .into());
+                }
+                db.finish_compaction()?;
+            }
+            let (report, migrated) = if db.options.rebuild_from_logs {
+                // the rebuilt data is checkpointed right away, like migrated data
+                (db.rebuild_from_logs()?, true)
+            } else if db.options.append_only {
+                db.resume_log()?;
+                (ReplayReport::default(), false)
+            } else {
+                db.load_latest(migration.as_deref())?
+            };
+            if !db.options.read_only {
+                // files of a newer version are left over from an interrupted checkpoint, and
+                // must not clash with the next one
+                let keep = (!db.options.manual_cleanup).then_some(db.options.keep_versions);
+                db.cleanup(keep)?;
+            }
+            if migrated && !db.options.read_only {
+                db.create_checkpoint()?;
+            }
+            Ok((db, report))
+        }
+    }
+}
+
+impl<T, F> DatabaseBuilder<T, F>
+where
+    T: Default + Serialize + DeserializeOwned + Readable + Updateable,
+    F: DataFormat<Data = T>,
+{
+    pub fn open<P: AsRef<Path>>(self, path: P) -> Result<Database<T, F>> {
+        self.open_with_report(path).map(|(db, _)| db)
+    }
+
+    /// Opens the database for appending updates only, see [`Database::open_append_only`].
+    pub fn open_append_only<P: AsRef<Path>>(mut self, path: P) -> Result<AppendOnlyDatabase<T, F>> {
+        self.options.append_only = true;
+        self.open(path).map(AppendOnlyDatabase::new)
+    }
+
+    /// Opens the database like [`DatabaseBuilder::open`] and reports how replaying the log went.
+    pub fn open_with_report<P: AsRef<Path>>(
+        self,
+        path: P,
+    ) -> Result<(Database<T, F>, ReplayReport)> {
+        self.open_or_init_with_report(path, T::default)
+    }
+}
+
+/// Starts the header line of a checkpoint, see [`DataFormat::MAGIC`]. No format writes a
+/// checkpoint starting like this on its own.
+const HEADER_PREFIX: &[u8] = b"bjw:";
+
+/// The header line of the checkpoints written by `fmt`, see [`DataFormat::MAGIC`].
+fn checkpoint_header<F: DataFormat>(fmt: &F) -> Vec<u8> {
+    if fmt.magic().is_empty() {
+        return Vec::new();
+    }
+    let mut header = HEADER_PREFIX.to_vec();
+    header.extend(fmt.magic());
+    header.extend(format!(":{}\n", fmt.format_version()).as_bytes());
+    header
+}
+
+/// The magic and format version in the header line at the start of `input`, if there is one.
+fn parse_checkpoint_header(input: &[u8]) -> Option<Result<(&[u8], &[u8])>> {
+    let rest = input.strip_prefix(HEADER_PREFIX)?;
+    let Some(end) = rest.iter().position(|&b| b == b'\n') else {
+        return Some(Err(BjwError::Corrupt(
+            "The header of the checkpoint is truncated".to_string(),
+        )));
+    };
+    let header = &rest[..end];
+    let split = header
+        .iter()
+        .rposition(|&b| b == b':')
+        .unwrap_or(header.len());
+    Some(Ok((
+        &header[..split],
+        header.get(split + 1..).unwrap_or_default(),
+    )))
+}
+
+/// The content of the checkpoint `ser` of `version` after its header, which must match `fmt`.
+fn strip_checkpoint_header<'a, F: DataFormat>(
+    fmt: &F,
+    version: u64,
+    ser: &'a [u8],
+) -> Result<&'a [u8]> {
+    let Some(header) = parse_checkpoint_header(ser) else {
+        return Ok(ser);
+    };
+    let (magic, format_version) = header?;
+    if magic != fmt.magic() || format_version != fmt.format_version().to_string().as_bytes() {
+        return Err(std::io::Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "Checkpoint {version} was written by format {:?} (version {}), but the database \
+                 is opened with format {:?} (version {})",
+                String::from_utf8_lossy(magic),
+                String::from_utf8_lossy(format_version),
+                String::from_utf8_lossy(fmt.magic()),
+                fmt.format_version()
+            ),
+        )
+        .into());
+    }
+    Ok(&ser[checkpoint_header(fmt).len()..])
+}
+
+/// The hex encoded SHA-256 of `data`, as stored next to every checkpoint.
+fn sha256_hex(data: &[u8]) -> Vec<u8> {
+    use sha2::Digest;
+
+    hex(&sha2::Sha256::digest(data))
+}
+
+fn hex(digest: &[u8]) -> Vec<u8> {
+    digest
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect::<String>()
+        .into_bytes()
+}
+
+/// Hashes everything written to `inner` on the way, see [`sha256_hex`].
+struct HashingWriter {
+    inner: Box<dyn LogWriter>,
+    hasher: sha2::Sha256,
+}
+
+impl Write for HashingWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        sha2::Digest::update(&mut self.hasher, &buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<T, F> Drop for Database<T, F> {
+    fn drop(&mut self) {
+        // updates the sync policy did not sync yet must not get lost
+        if let Err(e) = self.sync_log() {
+            log::warn!("Failed to flush the log: {:?}", e);
+        }
+    }
+}
+
+impl<T, F> Database<T, F> {
+    /// Writes out buffered updates and syncs the current log segment, if anything was written
+    /// to it since the last sync.
+    fn sync_log(&mut self) -> Result<()> {
+        if let Some(log_writer) = &mut self.log_writer {
+            log_writer.sync()?;
+        } else if self.unsynced_writes > 0 {
+            let naming = &self.options.naming;
+            let path = self
+                .path
+                .join(naming.log_segment(self.version, self.log_segment));
+            self.backend.append(&path, &[], true)?;
+        }
+        self.unsynced_writes = 0;
+        Ok(())
+    }
+
+    /// The segments of the log of `version` that exist, in the order they were written, as their
+    /// numbers and filenames (without the extension of compressed ones). A missing log just means
+    /// there were no updates, it is created on the next one.
+    fn log_segments(&self, version: u64) -> Result<Vec<(u64, String)>> {
+        let naming = &self.options.naming;
+        let mut segments: Vec<_> = self
+            .backend
+            .list_files(&self.path)?
+            .iter()
+            .filter_map(|filename| {
+                naming.split_log_segment(naming.strip_ext(filename, ZST_EXT).unwrap_or(filename))
+            })
+            .filter(|(log_version, _)| *log_version == version)
+            .map(|(_, segment)| (segment, naming.log_segment(version, segment)))
+            .collect();
+        segments.sort_unstable();
+        // a compressed segment may still exist next to the original
+        segments.dedup();
+        Ok(segments)
+    }
+
+    /// The versions that have a checkpoint (or a diff checkpoint) on disk, in ascending order.
+    /// These are the ones [`Database::view_at`] can reconstruct, how many of them survive a new
+    /// checkpoint is up to [`DatabaseBuilder::keep_versions`].
+    pub fn list_versions(&self) -> Result<Vec<u64>> {
+        let naming = &self.options.naming;
+        let mut versions: Vec<_> = self
+            .backend
+            .list_files(&self.path)?
+            .iter()
+            .filter_map(|filename| {
+                naming.split_version(naming.strip_ext(filename, DIFF_EXT).unwrap_or(filename))
+            })
+            .filter(|(prefix, _)| *prefix == naming.checkpoint_prefix)
+            .map(|(_, version)| version)
+            .collect();
+        versions.sort_unstable();
+        versions.dedup();
+        Ok(versions)
+    }
+}
+
+impl<T: Clone, F> Database<T, F> {
+    pub fn clone_data(&self) -> T {
+        self.data.clone()
+    }
+
+    /// A clone of the data together with the version it belongs to, i.e. the data is the
+    /// checkpoint of that version with (some of) its log applied. Both are taken while the
+    /// database is borrowed, so behind a lock (see [`SharedDatabase::read`]) they are consistent.
+    pub fn snapshot(&self) -> (u64, T) {
+        (self.version, self.data.clone())
+    }
+}
+
+impl<T: Clone + Updateable, F> Database<T, F> {
+    /// Applies `parameters` to a clone of the data and returns what the update would, without
+    /// logging it or changing the state, e.g. to validate an update before committing it.
+    pub fn peek_update(
+        &self,
+        parameters: &<T as Updateable>::Args,
+    ) -> <T as Updateable>::ReturnType {
+        self.data.clone().update(parameters)
+    }
+}
+
+#[cfg(feature = "json")]
+pub struct JsonFormat<T> {
+    options: JsonOptions,
+    _phantom: PhantomData<T>,
+}
+
+/// How [`JsonFormat`] reads and writes checkpoints, see [`JsonFormat::with_options`].
+///
+/// These can be changed for an existing database, as every checkpoint written with them can be
+/// read with the default options again, except that a lenient format may lose precision of
+/// integers beyond 2^53. How numbers are handled is decided by serde_json itself at compile time,
+/// the features `json-arbitrary-precision` and `json-float-roundtrip` enable its
+/// `arbitrary_precision` (numbers in a `serde_json::Value` keep all their digits) and
+/// `float_roundtrip` (floats are read back exactly, slightly slower) features. They apply to
+/// checkpoints and the log alike, but only to this format. Both only make more values round-trip,
+/// so they are safe to turn on; turning `arbitrary_precision` off again rounds numbers in a
+/// `serde_json::Value` that do not fit into an `f64`, `i64` or `u64` the next time they are read.
+#[cfg(feature = "json")]
+#[derive(Clone, Copy, Debug, Default)]
+#[non_exhaustive]
+pub struct JsonOptions {
+    /// Pretty-prints checkpoints to make them easier to inspect. The log always stays one
+    /// update per line.
+    pub pretty: bool,
+    /// Reads checkpoints as JSON5, see [`JsonFormat::lenient`].
+    #[cfg(feature = "json-relaxed")]
+    pub lenient: bool,
+}
+
+#[cfg(feature = "json")]
+impl<T> JsonFormat<T> {
+    /// A JSON format configured by `options`, which [`JsonFormat::pretty`] and
+    /// [`JsonFormat::lenient`] are shorthands for.
+    pub fn with_options(options: JsonOptions) -> Self {
+        JsonFormat::<T> {
+            options,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Pretty-prints checkpoints to make them easier to inspect. The log always stays one
+    /// update per line.
+    pub fn pretty() -> Self {
+        Self::with_options(JsonOptions {
+            pretty: true,
+            ..Default::default()
+        })
+    }
+
+    /// Pretty-prints checkpoints like [`JsonFormat::pretty`], and reads them as JSON5, so that
+    /// hand-edited checkpoints may contain comments and trailing commas. Numbers are read as
+    /// floats then, so integers beyond 2^53 lose precision. The log is still parsed strictly, so
+    /// that corrupt entries are not mistaken for valid ones.
+    #[cfg(feature = "json-relaxed")]
+    pub fn lenient() -> Self {
+        Self::with_options(JsonOptions {
+            pretty: true,
+            lenient: true,
+        })
+    }
+
+    /// The options this format was created with.
+    pub fn options(&self) -> JsonOptions {
+        self.options
+    }
+}
+
+#[cfg(feature = "json")]
+impl<T> DataFormat for JsonFormat<T>
+where
+    T: Serialize + DeserializeOwned + Updateable + Readable,
+{
+    type Data = T;
+    const MAGIC: &'static [u8] = b"json";
+
+    fn new() -> Self {
+        Self::with_options(JsonOptions::default())
+    }
+
+    fn serialize_data(&self, data: &Self::Data) -> Result<Vec<u8>> {
+        let string = if self.options.pretty {
+            serde_json::to_string_pretty(data)
+        } else {
+            serde_json::to_string(data)
+        }
+        .map_err(BjwError::serialize)?;
+        Ok(string.as_bytes().to_vec())
+    }
+
+    fn serialize_data_to<W: Write>(&self, data: &Self::Data, writer: W) -> Result<()> {
+        if self.options.pretty {
+            serde_json::to_writer_pretty(writer, data)
+        } else {
+            serde_json::to_writer(writer, data)
+        }
+        .map_err(BjwError::serialize)
+    }
+
+    fn deserialize_data(&self, input: &[u8]) -> Result<Self::Data> {
+        let str = std::str::from_utf8(input).map_err(BjwError::deserialize)?;
+        #[cfg(feature = "json-relaxed")]
+        if self.options.lenient {
+            return json5::from_str(str).map_err(BjwError::deserialize);
+        }
+        serde_json::from_str(str).map_err(BjwError::deserialize)
+    }
+
+    fn supports_mmap(&self) -> bool {
+        true
+    }
+
+    fn serialize_params(&self, params: &<Self::Data as Updateable>::Args) -> Result<Vec<u8>> {
+        let mut ser = Vec::new();
+        self.serialize_params_into(params, &mut ser)?;
+        Ok(ser)
+    }
+
+    fn serialize_params_into(
+        &self,
+        params: &<Self::Data as Updateable>::Args,
+        buf: &mut Vec<u8>,
+    ) -> Result<()> {
+        serde_json::to_writer(&mut *buf, params).map_err(BjwError::serialize)?;
+        buf.push(b'\n');
+        Ok(())
+    }
+
+    fn deserialize_params(&self, input: &[u8]) -> Result<Vec<<Self::Data as Updateable>::Args>> {
+        self.deserialize_log(input).map(|(updates, _)| updates)
+    }
+
+    fn deserialize_log(
+        &self,
+        input: &[u8],
+    ) -> Result<(Vec<<Self::Data as Updateable>::Args>, bool)> {
+        let (updates, len) = self.deserialize_log_prefix(input)?;
+        Ok((updates, len < input.len()))
+    }
+
+    fn deserialize_log_prefix(
+        &self,
+        input: &[u8],
+    ) -> Result<(Vec<<Self::Data as Updateable>::Args>, usize)> {
+        let mut updates = Vec::new();
+        let mut len = 0;
+        for line in input.split_inclusive(|byte| *byte == b'\n') {
+            let content = line.strip_suffix(b"\n").unwrap_or(line);
+            if !content.is_empty() {
+                match parse_json_line(content) {
+                    Ok(params) => updates.extend(params),
+                    Err(e) => {
+                        log::error!("Failed to deserialize an update (error: {e}); skipping all remaining ones!");
+                        break;
+                    }
+                }
+            }
+            len += line.len();
+        }
+        Ok((updates, len))
+    }
+
+    fn deserialize_params_stream<'a>(
+        &'a self,
+        mut reader: impl BufRead + 'a,
+    ) -> impl Iterator<Item = Result<<Self::Data as Updateable>::Args>> + 'a {
+        // the updates of a group are only handed out once the whole line was parsed
+        let mut pending = std::collections::VecDeque::new();
+        let mut done = false;
+        std::iter::from_fn(move || loop {
+            if let Some(params) = pending.pop_front() {
+                return Some(Ok(params));
+            }
+            if done {
+                return None;
+            }
+            let mut line = Vec::new();
+            match reader.read_until(b'\n', &mut line) {
+                Ok(0) => return None,
+                Ok(_) => {}
+                Err(e) => {
+                    done = true;
+                    return Some(Err(e.into()));
+                }
+            }
+            let line = line.strip_suffix(b"\n").unwrap_or(&line);
+            if line.is_empty() {
+                continue;
+            }
+            match parse_json_line(line) {
+                Ok(params) => pending.extend(params),
+                Err(e) => {
+                    done = true;
+                    return Some(Err(BjwError::Corrupt(format!(
+                        "Failed to deserialize an update (error: {e})"
+                    ))));
+                }
+            }
+        })
+    }
+
+    fn serialize_batch(&self, params: &[<Self::Data as Updateable>::Args]) -> Result<Vec<u8>> {
+        // the whole group goes into a single line, which is either complete or fails to parse
+        let mut string = format!(
+            "{JSON_BATCH_MARKER}{}",
+            serde_json::to_string(params).map_err(BjwError::serialize)?
+        );
+        string.push('\n');
+        Ok(string.as_bytes().to_vec())
+    }
+}
+
+/// Starts a log line holding a whole group of updates, it can never start a JSON value.
+#[cfg(feature = "json")]
+const JSON_BATCH_MARKER: char = '#';
+
+/// Parses a line of the log without its newline, which holds one update or a group of them.
+#[cfg(feature = "json")]
+fn parse_json_line<A: DeserializeOwned>(line: &[u8]) -> serde_json::Result<Vec<A>> {
+    match line.strip_prefix(&[JSON_BATCH_MARKER as u8]) {
+        Some(batch) => serde_json::from_slice(batch),
+        None => serde_json::from_slice(line).map(|params| vec![params]),
+    }
+}
+
+#[cfg(any(
+    feature = "bincode",
+    feature = "rkyv",
+    feature = "zstd",
+    feature = "encryption",
+    feature = "checksum"
+))]
+const FRAME_LEN_SIZE: usize = std::mem::size_of::<u32>();
+
+/// Set in the length header of a frame holding a whole group of updates.
+#[cfg(any(
+    feature = "bincode",
+    feature = "rkyv",
+    feature = "zstd",
+    feature = "encryption",
+    feature = "checksum"
+))]
+const GROUP_FLAG: u32 = 1 << 31;
+
+/// Prefixes `payload` with its length as a little endian u32, so that encodings which are not
+/// self-delimiting can be appended to the log.
+#[cfg(any(
+    feature = "bincode",
+    feature = "rkyv",
+    feature = "zstd",
+    feature = "encryption",
+    feature = "checksum"
+))]
+fn write_frame(payload: &[u8]) -> Result<Vec<u8>> {
+    write_flagged_frame(payload, 0)
+}
+
+/// Like `write_frame`, but marks the frame as holding a whole group of updates.
+#[cfg(any(feature = "bincode", feature = "rkyv"))]
+fn write_group_frame(payload: &[u8]) -> Result<Vec<u8>> {
+    write_flagged_frame(payload, GROUP_FLAG)
+}
+
+#[cfg(any(
+    feature = "bincode",
+    feature = "rkyv",
+    feature = "zstd",
+    feature = "encryption",
+    feature = "checksum"
+))]
+fn write_flagged_frame(payload: &[u8], flags: u32) -> Result<Vec<u8>> {
+    let len = u32::try_from(payload.len())
+        .ok()
+        .filter(|len| len & GROUP_FLAG == 0)
+        .ok_or_else(|| BjwError::serialize("Update too large for a single frame"))?;
+    let mut ser = Vec::with_capacity(FRAME_LEN_SIZE + payload.len());
+    ser.extend_from_slice(&(len | flags).to_le_bytes());
+    ser.extend_from_slice(payload);
+    Ok(ser)
+}
+
+/// Splits a log written via `write_frame` into its frames, each together with whether it is a
+/// group frame. A truncated frame is dropped together with everything after it, which is
+/// reported by the returned flag.
+#[cfg(any(
+    feature = "bincode",
+    feature = "rkyv",
+    feature = "zstd",
+    feature = "encryption",
+    feature = "checksum"
+))]
+fn read_frames(input: &[u8]) -> (Vec<(&[u8], bool)>, bool) {
+    let mut frames = Vec::new();
+    let mut rest = input;
+    while !rest.is_empty() {
+        if rest.len() < FRAME_LEN_SIZE {
+            log::error!("Found a truncated frame header; skipping all remaining updates!");
+            return (frames, true);
+        }
+        let (len, tail) = rest.split_at(FRAME_LEN_SIZE);
+        let header = u32::from_le_bytes(len.try_into().unwrap());
+        let len = (header & !GROUP_FLAG) as usize;
+        if tail.len() < len {
+            log::error!("Found a truncated frame; skipping all remaining updates!");
+            return (frames, true);
+        }
+        let (frame, tail) = tail.split_at(len);
+        frames.push((frame, header & GROUP_FLAG != 0));
+        rest = tail;
+    }
+    (frames, false)
+}
+
+#[cfg(feature = "bincode")]
+pub struct BincodeFormat<T> {
+    _phantom: PhantomData<T>,
+}
+
+#[cfg(feature = "bincode")]
+impl<T> DataFormat for BincodeFormat<T>
+where
+    T: Serialize + DeserializeOwned + Updateable + Readable,
+{
+    type Data = T;
+    const MAGIC: &'static [u8] = b"bincode";
+
+    fn new() -> Self {
+        BincodeFormat::<T> {
+            _phantom: PhantomData,
+        }
+    }
+
+    fn serialize_data(&self, data: &Self::Data) -> Result<Vec<u8>> {
+        bincode::serialize(data).map_err(BjwError::serialize)
+    }
+
+    fn deserialize_data(&self, input: &[u8]) -> Result<Self::Data> {
+        bincode::deserialize(input).map_err(BjwError::deserialize)
+    }
+
+    fn supports_mmap(&self) -> bool {
+        true
+    }
+
+    fn serialize_params(&self, params: &<Self::Data as Updateable>::Args) -> Result<Vec<u8>> {
+        // bincode is not self-delimiting, so every update is prefixed with its length
+        let ser = bincode::serialize(params).map_err(BjwError::serialize)?;
+        write_frame(&ser)
+    }
+
+    fn deserialize_params(&self, input: &[u8]) -> Result<Vec<<Self::Data as Updateable>::Args>> {
+        self.deserialize_log(input).map(|(updates, _)| updates)
+    }
+
+    fn deserialize_log(
+        &self,
+        input: &[u8],
+    ) -> Result<(Vec<<Self::Data as Updateable>::Args>, bool)> {
+        let (updates, len) = self.deserialize_log_prefix(input)?;
+        Ok((updates, len < input.len()))
+    }
+
+    fn deserialize_log_prefix(
+        &self,
+        input: &[u8],
+    ) -> Result<(Vec<<Self::Data as Updateable>::Args>, usize)> {
+        let mut updates = Vec::new();
+        let mut len = 0;
+        for (frame, group) in read_frames(input).0 {
+            let parsed = if group {
+                bincode::deserialize(frame)
+            } else {
+                bincode::deserialize(frame).map(|params| vec![params])
+            };
+            match parsed {
+                Ok(params) => updates.extend(params),
+                Err(e) => {
+                    log::error!("Failed to deserialize an update (error: {e}); skipping all remaining ones!");
+                    break;
+                }
+            }
+            len += FRAME_LEN_SIZE + frame.len();
+        }
+        Ok((updates, len))
+    }
+
+    fn serialize_batch(&self, params: &[<Self::Data as Updateable>::Args]) -> Result<Vec<u8>> {
+        let ser = bincode::serialize(params).map_err(BjwError::serialize)?;
+        write_group_frame(&ser)
+    }
+}
+
+/// Stores checkpoints and updates with rkyv, whose archives are read without parsing. The data
+/// and its updates need rkyv's derives in addition to serde's, see [`RkyvCodec`]. The data is
+/// still deserialized into an owned value, as the database updates it in place, so opening a
+/// large checkpoint mostly saves the parsing: numbers gain most, while allocating strings and
+/// collections costs the same as with any format (see `benches/rkyv.rs`).
+#[cfg(feature = "rkyv")]
+pub struct RkyvFormat<T> {
+    _phantom: PhantomData<T>,
+}
+
+/// Types [`RkyvFormat`] can write and read back, which are those deriving rkyv's `Archive`,
+/// `Serialize` and `Deserialize` (with its default validation).
+#[cfg(feature = "rkyv")]
+pub trait RkyvCodec: Sized {
+    fn to_rkyv(&self) -> Result<Vec<u8>>;
+    fn from_rkyv(input: &[u8]) -> Result<Self>;
+}
+
+#[cfg(feature = "rkyv")]
+impl<V> RkyvCodec for V
+where
+    V: rkyv::Archive
+        + for<'a> rkyv::Serialize<
+            rkyv::api::high::HighSerializer<
+                rkyv::util::AlignedVec,
+                rkyv::ser::allocator::ArenaHandle<'a>,
+                rkyv::rancor::Error,
+            >,
+        >,
+    V::Archived: for<'a> rkyv::bytecheck::CheckBytes<rkyv::api::high::HighValidator<'a, rkyv::rancor::Error>>
+        + rkyv::Deserialize<V, rkyv::api::high::HighDeserializer<rkyv::rancor::Error>>,
+{
+    fn to_rkyv(&self) -> Result<Vec<u8>> {
+        rkyv::to_bytes::<rkyv::rancor::Error>(self)
+            .map(|bytes| bytes.into_vec())
+            .map_err(BjwError::serialize)
+    }
+
+    fn from_rkyv(input: &[u8]) -> Result<Self> {
+        // archives have to be aligned, which a checkpoint behind its header or a frame is not
+        let mut aligned = rkyv::util::AlignedVec::<16>::with_capacity(input.len());
+        aligned.extend_from_slice(input);
+        rkyv::from_bytes::<V, rkyv::rancor::Error>(&aligned).map_err(BjwError::deserialize)
+    }
+}
+
+#[cfg(feature = "rkyv")]
+impl<T> DataFormat for RkyvFormat<T>
+where
+    T: Serialize + DeserializeOwned + Updateable + Readable + RkyvCodec,
+    <T as Updateable>::Args: RkyvCodec,
+{
+    type Data = T;
+    const MAGIC: &'static [u8] = b"rkyv";
+
+    fn new() -> Self {
+        RkyvFormat::<T> {
+            _phantom: PhantomData,
+        }
+    }
+
+    fn serialize_data(&self, data: &Self::Data) -> Result<Vec<u8>> {
+        data.to_rkyv()
+    }
+
+    fn deserialize_data(&self, input: &[u8]) -> Result<Self::Data> {
+        T::from_rkyv(input)
+    }
+
+    fn serialize_params(&self, params: &<Self::Data as Updateable>::Args) -> Result<Vec<u8>> {
+        // archives are not self-delimiting, so every update is prefixed with its length
+        write_frame(&params.to_rkyv()?)
+    }
+
+    fn deserialize_params(&self, input: &[u8]) -> Result<Vec<<Self::Data as Updateable>::Args>> {
+        self.deserialize_log(input).map(|(updates, _)| updates)
+    }
+
+    fn deserialize_log(
+        &self,
+        input: &[u8],
+    ) -> Result<(Vec<<Self::Data as Updateable>::Args>, bool)> {
+        let (updates, len) = self.deserialize_log_prefix(input)?;
+        Ok((updates, len < input.len()))
+    }
+
+    fn deserialize_log_prefix(
+        &self,
+        input: &[u8],
+    ) -> Result<(Vec<<Self::Data as Updateable>::Args>, usize)> {
+        let mut updates = Vec::new();
+        let mut len = 0;
+        for (frame, group) in read_frames(input).0 {
+            // a group holds the frames of its updates
+            let parsed = if group {
+                match read_frames(frame) {
+                    (inner, false) => inner
+                        .into_iter()
+                        .map(|(inner, _)| <T as Updateable>::Args::from_rkyv(inner))
+                        .collect(),
+                    (_, true) => Err(BjwError::deserialize("Truncated frame inside a group")),
+                }
+            } else {
+                <T as Updateable>::Args::from_rkyv(frame).map(|params| vec![params])
+            };
+            match parsed {
+                Ok(params) => updates.extend(params),
+                Err(e) => {
+                    log::error!("Failed to deserialize an update (error: {e}); skipping all remaining ones!");
+                    break;
+                }
+            }
+            len += FRAME_LEN_SIZE + frame.len();
+        }
+        Ok((updates, len))
+    }
+
+    fn serialize_batch(&self, params: &[<Self::Data as Updateable>::Args]) -> Result<Vec<u8>> {
+        let mut ser = Vec::new();
+        for params in params {
+            ser.extend(write_frame(&params.to_rkyv()?)?);
+        }
+        write_group_frame(&ser)
+    }
+}
+
+#[cfg(feature = "msgpack")]
+pub struct MsgPackFormat<T> {
+    _phantom: PhantomData<T>,
+}
+
+#[cfg(feature = "msgpack")]
+impl<T> DataFormat for MsgPackFormat<T>
+where
+    T: Serialize + DeserializeOwned + Updateable + Readable,
+{
+    type Data = T;
+    const MAGIC: &'static [u8] = b"msgpack";
+
+    fn new() -> Self {
+        MsgPackFormat::<T> {
+            _phantom: PhantomData,
+        }
+    }
+
+    fn serialize_data(&self, data: &Self::Data) -> Result<Vec<u8>> {
+        rmp_serde::to_vec_named(data).map_err(BjwError::serialize)
+    }
+
+    fn deserialize_data(&self, input: &[u8]) -> Result<Self::Data> {
+        rmp_serde::from_slice(input).map_err(BjwError::deserialize)
+    }
+
+    fn supports_mmap(&self) -> bool {
+        true
+    }
+
+    fn serialize_params(&self, params: &<Self::Data as Updateable>::Args) -> Result<Vec<u8>> {
+        let mut ser = Vec::new();
+        self.serialize_params_into(params, &mut ser)?;
+        Ok(ser)
+    }
+
+    fn serialize_params_into(
+        &self,
+        params: &<Self::Data as Updateable>::Args,
+        buf: &mut Vec<u8>,
+    ) -> Result<()> {
+        // MessagePack values are self-delimiting, so they can simply be concatenated
+        rmp_serde::encode::write_named(buf, params).map_err(BjwError::serialize)
+    }
+
+    fn deserialize_params(&self, input: &[u8]) -> Result<Vec<<Self::Data as Updateable>::Args>> {
+        self.deserialize_log(input).map(|(updates, _)| updates)
+    }
+
+    fn deserialize_log(
+        &self,
+        input: &[u8],
+    ) -> Result<(Vec<<Self::Data as Updateable>::Args>, bool)> {
+        let (updates, len) = self.deserialize_log_prefix(input)?;
+        Ok((updates, len < input.len()))
+    }
+
+    fn deserialize_log_prefix(
+        &self,
+        input: &[u8],
+    ) -> Result<(Vec<<Self::Data as Updateable>::Args>, usize)> {
+        let mut updates = Vec::new();
+        let mut len = 0;
+        let mut rest = input;
+        while !rest.is_empty() {
+            let group = rest[0] == MSGPACK_BATCH_MARKER;
+            if group {
+                rest = &rest[1..];
+            }
+            let mut de = rmp_serde::Deserializer::new(&mut rest);
+            let parsed = if group {
+                serde::Deserialize::deserialize(&mut de)
+            } else {
+                serde::Deserialize::deserialize(&mut de).map(|params| vec![params])
+            };
+            match parsed {
+                Ok(params) => updates.extend(params),
+                Err(e) => {
+                    log::error!("Failed to deserialize an update (error: {e}); skipping all remaining ones!");
+                    break;
+                }
+            }
+            len = input.len() - rest.len();
+        }
+        Ok((updates, len))
+    }
+
+    fn serialize_batch(&self, params: &[<Self::Data as Updateable>::Args]) -> Result<Vec<u8>> {
+        // a group is a single array, which fails to deserialize if it was only partially written
+        let mut ser = vec![MSGPACK_BATCH_MARKER];
+        ser.extend(rmp_serde::to_vec_named(params).map_err(BjwError::serialize)?);
+        Ok(ser)
+    }
+}
+
+/// Precedes a group of updates in the log, MessagePack never uses this byte.
+#[cfg(feature = "msgpack")]
+const MSGPACK_BATCH_MARKER: u8 = 0xc1;
+
+#[cfg(feature = "cbor")]
+pub struct CborFormat<T> {
+    _phantom: PhantomData<T>,
+}
+
+#[cfg(feature = "cbor")]
+impl<T> DataFormat for CborFormat<T>
+where
+    T: Serialize + DeserializeOwned + Updateable + Readable,
+{
+    type Data = T;
+    const MAGIC: &'static [u8] = b"cbor";
+
+    fn new() -> Self {
+        CborFormat::<T> {
+            _phantom: PhantomData,
+        }
+    }
+
+    fn serialize_data(&self, data: &Self::Data) -> Result<Vec<u8>> {
+        self.serialize_data_item(data)
+    }
+
+    fn deserialize_data(&self, input: &[u8]) -> Result<Self::Data> {
+        ciborium::from_reader(input).map_err(BjwError::deserialize)
+    }
+
+    fn supports_mmap(&self) -> bool {
+        true
+    }
+
+    fn serialize_params(&self, params: &<Self::Data as Updateable>::Args) -> Result<Vec<u8>> {
+        self.serialize_data_item(params)
+    }
+
+    fn serialize_params_into(
+        &self,
+        params: &<Self::Data as Updateable>::Args,
+        buf: &mut Vec<u8>,
+    ) -> Result<()> {
+        // CBOR data items are self-delimiting, so the log is a plain sequence of them
+        ciborium::into_writer(params, buf).map_err(BjwError::serialize)
+    }
+
+    fn deserialize_params(&self, input: &[u8]) -> Result<Vec<<Self::Data as Updateable>::Args>> {
+        self.deserialize_log(input).map(|(updates, _)| updates)
+    }
+
+    fn deserialize_log(
+        &self,
+        input: &[u8],
+    ) -> Result<(Vec<<Self::Data as Updateable>::Args>, bool)> {
+        let (updates, len) = self.deserialize_log_prefix(input)?;
+        Ok((updates, len < input.len()))
+    }
+
+    fn deserialize_log_prefix(
+        &self,
+        input: &[u8],
+    ) -> Result<(Vec<<Self::Data as Updateable>::Args>, usize)> {
+        let mut updates = Vec::new();
+        let mut len = 0;
+        let mut rest = input;
+        while !rest.is_empty() {
+            let group = rest[0] == CBOR_BATCH_MARKER;
+            if group {
+                rest = &rest[1..];
+            }
+            let parsed = if group {
+                ciborium::from_reader(&mut rest)
+            } else {
+                ciborium::from_reader(&mut rest).map(|params| vec![params])
+            };
+            match parsed {
+                Ok(params) => updates.extend(params),
+                Err(e) => {
+                    log::error!("Failed to deserialize an update (error: {e}); skipping all remaining ones!");
+                    break;
+                }
+            }
+            len = input.len() - rest.len();
+        }
+        Ok((updates, len))
+    }
+
+    fn serialize_batch(&self, params: &[<Self::Data as Updateable>::Args]) -> Result<Vec<u8>> {
+        // a group is a single array, which fails to deserialize if it was only partially written
+        let mut ser = vec![CBOR_BATCH_MARKER];
+        ser.extend(self.serialize_data_item(&params)?);
+        Ok(ser)
+    }
+}
+
+/// Precedes a group of updates in the log, no well-formed CBOR data item starts with it.
+#[cfg(feature = "cbor")]
+const CBOR_BATCH_MARKER: u8 = 0x1f;
+
+#[cfg(feature = "cbor")]
+impl<T> CborFormat<T> {
+    fn serialize_data_item<S: Serialize>(&self, item: &S) -> Result<Vec<u8>> {
+        let mut ser = Vec::new();
+        ciborium::into_writer(item, &mut ser).map_err(BjwError::serialize)?;
+        Ok(ser)
+    }
+}
+
+/// Writes checkpoints as TOML, so they can be read and edited by hand. The data has to
+/// serialize to a TOML table, e.g. a struct with string map keys. TOML does not lend itself to an
+/// append-only log, so the log is written just like the one of [`JsonFormat`], one update per
+/// line. When editing a checkpoint, keep its header line (see [`DataFormat::MAGIC`]) and remove
+/// its `.sha256` file afterwards, or it is rejected as corrupt.
+#[cfg(feature = "toml")]
+pub struct TomlFormat<T> {
+    log: JsonFormat<T>,
+}
+
+#[cfg(feature = "toml")]
+impl<T> DataFormat for TomlFormat<T>
+where
+    T: Serialize + DeserializeOwned + Updateable + Readable,
+{
+    type Data = T;
+    const MAGIC: &'static [u8] = b"toml";
+
+    fn new() -> Self {
+        TomlFormat {
+            log: JsonFormat::new(),
+        }
+    }
+
+    fn serialize_data(&self, data: &Self::Data) -> Result<Vec<u8>> {
+        toml::to_string_pretty(data)
+            .map(String::into_bytes)
+            .map_err(BjwError::serialize)
+    }
+
+    fn deserialize_data(&self, input: &[u8]) -> Result<Self::Data> {
+        let str = std::str::from_utf8(input).map_err(BjwError::deserialize)?;
+        toml::from_str(str).map_err(BjwError::deserialize)
+    }
+
+    fn serialize_params(&self, params: &<Self::Data as Updateable>::Args) -> Result<Vec<u8>> {
+        self.log.serialize_params(params)
+    }
+
+    fn serialize_params_into(
+        &self,
+        params: &<Self::Data as Updateable>::Args,
+        buf: &mut Vec<u8>,
+    ) -> Result<()> {
+        self.log.serialize_params_into(params, buf)
+    }
+
+    fn deserialize_params(&self, input: &[u8]) -> Result<Vec<<Self::Data as Updateable>::Args>> {
+        self.log.deserialize_params(input)
+    }
+
+    fn deserialize_log(
+        &self,
+        input: &[u8],
+    ) -> Result<(Vec<<Self::Data as Updateable>::Args>, bool)> {
+        self.log.deserialize_log(input)
+    }
+
+    fn deserialize_log_prefix(
+        &self,
+        input: &[u8],
+    ) -> Result<(Vec<<Self::Data as Updateable>::Args>, usize)> {
+        self.log.deserialize_log_prefix(input)
+    }
+
+    fn deserialize_params_stream<'a>(
+        &'a self,
+        reader: impl BufRead + 'a,
+    ) -> impl Iterator<Item = Result<<Self::Data as Updateable>::Args>> + 'a {
+        self.log.deserialize_params_stream(reader)
+    }
+
+    fn serialize_batch(&self, params: &[<Self::Data as Updateable>::Args]) -> Result<Vec<u8>> {
+        self.log.serialize_batch(params)
+    }
+}
+
+/// Wraps another format and compresses everything it produces with zstd. Checkpoints are
+/// compressed as a whole, while every update is compressed into its own frame so the log can
+/// still be appended to.
+#[cfg(feature = "zstd")]
+pub struct Compressed<F> {
+    inner: F,
+    level: i32,
+}
+
+#[cfg(feature = "zstd")]
+impl<F> Compressed<F> {
+    pub fn with_level(inner: F, level: i32) -> Self {
+        Compressed { inner, level }
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl<F: DataFormat> DataFormat for Compressed<F> {
+    type Data = F::Data;
+    const MAGIC: &'static [u8] = b"zstd";
+
+    fn new() -> Self {
+        Compressed {
+            inner: F::new(),
+            level: zstd::DEFAULT_COMPRESSION_LEVEL,
+        }
+    }
+
+    fn serialize_data(&self, data: &Self::Data) -> Result<Vec<u8>> {
+        let ser = self.inner.serialize_data(data)?;
+        Ok(zstd::encode_all(ser.as_slice(), self.level)?)
+    }
+
+    fn deserialize_data(&self, input: &[u8]) -> Result<Self::Data> {
+        let ser = zstd::decode_all(input).map_err(BjwError::deserialize)?;
+        self.inner.deserialize_data(&ser)
+    }
+
+    fn serialize_params(&self, params: &<Self::Data as Updateable>::Args) -> Result<Vec<u8>> {
+        let ser = self.inner.serialize_params(params)?;
+        write_frame(&zstd::encode_all(ser.as_slice(), self.level)?)
+    }
+
+    fn deserialize_params(&self, input: &[u8]) -> Result<Vec<<Self::Data as Updateable>::Args>> {
+        self.deserialize_log(input).map(|(updates, _)| updates)
+    }
+
+    fn deserialize_log(
+        &self,
+        input: &[u8],
+    ) -> Result<(Vec<<Self::Data as Updateable>::Args>, bool)> {
+        let mut updates = Vec::new();
+        let (frames, truncated) = read_frames(input);
+        for (frame, _) in frames {
+            match zstd::decode_all(frame) {
+                Ok(ser) => {
+                    let (inner, inner_truncated) = self.inner.deserialize_log(&ser)?;
+                    updates.extend(inner);
+                    if inner_truncated {
+                        return Ok((updates, true));
+                    }
+                }
+                Err(e) => {
+                    log::error!(
+                        "Failed to decompress an update (error: {e}); skipping all remaining ones!"
+                    );
+                    return Ok((updates, true));
+                }
+            }
+        }
+        Ok((updates, truncated))
+    }
+
+    /// Only whole frames count, as reading the log cannot resume within one.
+    fn deserialize_log_prefix(
+        &self,
+        input: &[u8],
+    ) -> Result<(Vec<<Self::Data as Updateable>::Args>, usize)> {
+        let mut updates = Vec::new();
+        let mut len = 0;
+        for (frame, _) in read_frames(input).0 {
+            let Ok(ser) = zstd::decode_all(frame) else {
+                break;
+            };
+            let (inner, inner_len) = self.inner.deserialize_log_prefix(&ser)?;
+            if inner_len < ser.len() {
+                break;
+            }
+            updates.extend(inner);
+            len += FRAME_LEN_SIZE + frame.len();
+        }
+        Ok((updates, len))
+    }
+
+    fn serialize_batch(&self, params: &[<Self::Data as Updateable>::Args]) -> Result<Vec<u8>> {
+        // the inner format's group framing ends up in a single frame
+        let ser = self.inner.serialize_batch(params)?;
+        write_frame(&zstd::encode_all(ser.as_slice(), self.level)?)
+    }
+}
+
+#[cfg(feature = "encryption")]
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    XChaCha20Poly1305, XNonce,
+};
+
+#[cfg(feature = "encryption")]
+const NONCE_SIZE: usize = 24;
+
+/// Wraps another format and encrypts everything it produces with XChaCha20-Poly1305. Every
+/// checkpoint and every single update get their own random nonce, which is stored in front of
+/// the ciphertext, so log entries can be authenticated one at a time.
+#[cfg(feature = "encryption")]
+pub struct Encrypted<F> {
+    inner: F,
+    /// `None` if created by [`DataFormat::new`], which has no key to use.
+    cipher: Option<XChaCha20Poly1305>,
+}
+
+#[cfg(feature = "encryption")]
+impl<F> Encrypted<F> {
+    pub fn new(inner: F, key: &[u8; 32]) -> Self {
+        Encrypted {
+            inner,
+            cipher: Some(XChaCha20Poly1305::new(key.into())),
+        }
+    }
+
+    fn cipher(&self) -> Result<&XChaCha20Poly1305> {
+        self.cipher.as_ref().ok_or_else(|| {
+            std::io::Error::new(
+                ErrorKind::InvalidInput,
+                "Encrypted has no key, create it with Encrypted::new(inner, key)",
+            )
+            .into()
+        })
+    }
+
+    fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher()?
+            .encrypt(&nonce, plaintext)
+            .map_err(|_| BjwError::serialize("Failed to encrypt"))?;
+        let mut ser = Vec::with_capacity(NONCE_SIZE + ciphertext.len());
+        ser.extend_from_slice(&nonce);
+        ser.extend_from_slice(&ciphertext);
+        Ok(ser)
+    }
+
+    fn decrypt(&self, input: &[u8]) -> Result<Vec<u8>> {
+        if input.len() < NONCE_SIZE {
+            return Err(BjwError::Corrupt(
+                "Encrypted input is too short".to_string(),
+            ));
+        }
+        let (nonce, ciphertext) = input.split_at(NONCE_SIZE);
+        self.cipher()?
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .map_err(|_| BjwError::Corrupt("Failed to authenticate".to_string()))
+    }
+}
+
+#[cfg(feature = "encryption")]
+impl<F: DataFormat> DataFormat for Encrypted<F> {
+    type Data = F::Data;
+    const MAGIC: &'static [u8] = b"encrypted";
+
+    /// There is no sensible default key, so the format this creates fails to read or write
+    /// anything. Use `Encrypted::new` with a key instead.
+    fn new() -> Self {
+        Encrypted {
+            inner: F::new(),
+            cipher: None,
+        }
+    }
+
+    fn serialize_data(&self, data: &Self::Data) -> Result<Vec<u8>> {
+        self.encrypt(&self.inner.serialize_data(data)?)
+    }
+
+    fn deserialize_data(&self, input: &[u8]) -> Result<Self::Data> {
+        self.inner.deserialize_data(&self.decrypt(input)?)
+    }
+
+    fn serialize_params(&self, params: &<Self::Data as Updateable>::Args) -> Result<Vec<u8>> {
+        write_frame(&self.encrypt(&self.inner.serialize_params(params)?)?)
+    }
+
+    fn deserialize_params(&self, input: &[u8]) -> Result<Vec<<Self::Data as Updateable>::Args>> {
+        self.deserialize_log(input).map(|(updates, _)| updates)
+    }
+
+    fn deserialize_log(
+        &self,
+        input: &[u8],
+    ) -> Result<(Vec<<Self::Data as Updateable>::Args>, bool)> {
+        let mut updates = Vec::new();
+        let (frames, truncated) = read_frames(input);
+        for (frame, _) in frames {
+            match self.decrypt(frame) {
+                Ok(ser) => {
+                    let (inner, inner_truncated) = self.inner.deserialize_log(&ser)?;
+                    updates.extend(inner);
+                    if inner_truncated {
+                        return Ok((updates, true));
+                    }
+                }
+                Err(e) => {
+                    log::error!(
+                        "Failed to decrypt an update (error: {e}); skipping all remaining ones!"
+                    );
+                    return Ok((updates, true));
+                }
+            }
+        }
+        Ok((updates, truncated))
+    }
+
+    /// Only whole frames count, as reading the log cannot resume within one.
+    fn deserialize_log_prefix(
+        &self,
+        input: &[u8],
+    ) -> Result<(Vec<<Self::Data as Updateable>::Args>, usize)> {
+        let mut updates = Vec::new();
+        let mut len = 0;
+        for (frame, _) in read_frames(input).0 {
+            let Ok(ser) = self.decrypt(frame) else {
+                break;
+            };
+            let (inner, inner_len) = self.inner.deserialize_log_prefix(&ser)?;
+            if inner_len < ser.len() {
+                break;
+            }
+            updates.extend(inner);
+            len += FRAME_LEN_SIZE + frame.len();
+        }
+        Ok((updates, len))
+    }
+
+    fn serialize_batch(&self, params: &[<Self::Data as Updateable>::Args]) -> Result<Vec<u8>> {
+        write_frame(&self.encrypt(&self.inner.serialize_batch(params)?)?)
+    }
+}
+
+#[cfg(feature = "checksum")]
+const CHECKSUM_SIZE: usize = std::mem::size_of::<u32>();
+
+/// Wraps another format and stores a CRC32 in front of every update in the log, so that replay
+/// also stops at entries that were corrupted but still happen to deserialize. Checkpoints are
+/// passed through unchanged.
+#[cfg(feature = "checksum")]
+pub struct Checksummed<F> {
+    inner: F,
+}
+
+#[cfg(feature = "checksum")]
+impl<F> Checksummed<F> {
+    pub fn new(inner: F) -> Self {
+        Checksummed { inner }
+    }
+
+    fn checksummed_frame(payload: &[u8]) -> Result<Vec<u8>> {
+        let mut ser = Vec::with_capacity(CHECKSUM_SIZE + payload.len());
+        ser.extend_from_slice(&crc32fast::hash(payload).to_le_bytes());
+        ser.extend_from_slice(payload);
+        write_frame(&ser)
+    }
+}
+
+#[cfg(feature = "checksum")]
+impl<F: DataFormat> DataFormat for Checksummed<F> {
+    type Data = F::Data;
+    const MAGIC: &'static [u8] = b"checksummed";
+
+    fn new() -> Self {
+        Checksummed { inner: F::new() }
+    }
+
+    fn serialize_data(&self, data: &Self::Data) -> Result<Vec<u8>> {
+        self.inner.serialize_data(data)
+    }
+
+    fn deserialize_data(&self, input: &[u8]) -> Result<Self::Data> {
+        self.inner.deserialize_data(input)
+    }
+
+    fn serialize_data_to<W: Write>(&self, data: &Self::Data, writer: W) -> Result<()> {
+        self.inner.serialize_data_to(data, writer)
+    }
+
+    fn supports_mmap(&self) -> bool {
+        self.inner.supports_mmap()
+    }
+
+    fn serialize_params(&self, params: &<Self::Data as Updateable>::Args) -> Result<Vec<u8>> {
+        Self::checksummed_frame(&self.inner.serialize_params(params)?)
+    }
+
+    fn deserialize_params(&self, input: &[u8]) -> Result<Vec<<Self::Data as Updateable>::Args>> {
+        self.deserialize_log(input).map(|(updates, _)| updates)
+    }
+
+    fn deserialize_log(
+        &self,
+        input: &[u8],
+    ) -> Result<(Vec<<Self::Data as Updateable>::Args>, bool)> {
+        let mut updates = Vec::new();
+        let (frames, truncated) = read_frames(input);
+        for (frame, _) in frames {
+            let verified = (frame.len() >= CHECKSUM_SIZE)
+                .then(|| frame.split_at(CHECKSUM_SIZE))
+                .filter(|(crc, payload)| {
+                    u32::from_le_bytes((*crc).try_into().unwrap()) == crc32fast::hash(payload)
+                });
+            match verified {
+                Some((_, payload)) => {
+                    let (inner, inner_truncated) = self.inner.deserialize_log(payload)?;
+                    updates.extend(inner);
+                    if inner_truncated {
+                        return Ok((updates, true));
+                    }
+                }
+                None => {
+                    log::error!(
+                        "Found an update with a wrong checksum; skipping all remaining ones!"
+                    );
+                    return Ok((updates, true));
+                }
+            }
+        }
+        Ok((updates, truncated))
+    }
+
+    /// Only whole frames count, as reading the log cannot resume within one.
+    fn deserialize_log_prefix(
+        &self,
+        input: &[u8],
+    ) -> Result<(Vec<<Self::Data as Updateable>::Args>, usize)> {
+        let mut updates = Vec::new();
+        let mut len = 0;
+        for (frame, _) in read_frames(input).0 {
+            let verified = (frame.len() >= CHECKSUM_SIZE)
+                .then(|| frame.split_at(CHECKSUM_SIZE))
+                .filter(|(crc, payload)| {
+                    u32::from_le_bytes((*crc).try_into().unwrap()) == crc32fast::hash(payload)
+                });
+            let Some((_, ser)) = verified else {
+                break;
+            };
+            let (inner, inner_len) = self.inner.deserialize_log_prefix(ser)?;
+            if inner_len < ser.len() {
+                break;
+            }
+            updates.extend(inner);
+            len += FRAME_LEN_SIZE + frame.len();
+        }
+        Ok((updates, len))
+    }
+
+    fn serialize_batch(&self, params: &[<Self::Data as Updateable>::Args]) -> Result<Vec<u8>> {
+        Self::checksummed_frame(&self.inner.serialize_batch(params)?)
+    }
+}
+
+#[cfg(feature = "derive")]
+pub use bjw_db_derive::{bjw_db_impls, derive_bjw_db};
+
+#[cfg(test)]
+#[cfg(feature = "derive")]
+mod tests {
+    use crate as bjw_db;
+
+    use super::*;
+    use serde::Deserialize;
+    use std::collections::BTreeMap;
+    use tempfile::TempDir;
+
+    #[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+    struct KeyValueStore {
+        store: BTreeMap<String, String>,
+    }
+
+    #[bjw_db_derive::derive_bjw_db]
+    impl KeyValueStore {
+        /// Inserts `value` under `key`, replacing the previous one.
+        pub fn insert(&mut self, key: String, value: String) {
+            self.store.insert(key, value);
+        }
+
+        pub fn insert_with_check(&mut self, key: String, value: String) -> bool {
+            if self.store.contains_key(&key) {
+                false
+            } else {
+                self.insert(key, value);
+                true
+            }
+        }
+
+        pub fn get(&self, key: &str) -> Option<String> {
+            self.store.get(key).cloned()
+        }
+
+        pub fn unused(&self, _key: &str) -> u64 {
             unreachable!()
         }
     }
 
-    #[test]
-    fn test_normal_operation() {
-        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+    #[test]
+    fn test_normal_operation() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+
+        // create new db
+        let path = tempdir.path().join("kv-store");
+        let mut db = KeyValueStoreDb::open(&path).unwrap();
+        db.insert("key".to_string(), "value".to_string()).unwrap();
+        db.insert("more".to_string(), "value".to_string()).unwrap();
+        assert!(!db
+            .insert_with_check("key".to_string(), "".to_string())
+            .unwrap());
+        assert_eq!(db.get("key"), Some("value".to_string()));
+
+        // create a checkpoint
+        db.create_checkpoint().unwrap();
+        db.insert("another".to_string(), "pair".to_string())
+            .unwrap();
+
+        // re-open db
+        let data = db.clone_data();
+        drop(db);
+        let mut db = KeyValueStoreDb::open(&path).unwrap();
+        assert_eq!(data, db.clone_data());
+
+        // create a checkpoint and don't update, but re-open right away (-> tests empty log)
+        db.create_checkpoint().unwrap();
+        drop(db);
+        let db = KeyValueStoreDb::open(&path).unwrap();
+
+        // delete
+        db.delete().unwrap();
+    }
+
+    #[test]
+    fn test_auto_checkpoint() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("kv-store");
+        let fmt = JsonFormat::<KeyValueStore>::new();
+        let mut db = Database::builder(fmt)
+            .auto_checkpoint_bytes(100)
+            .open(&path)
+            .unwrap();
+        for i in 0..10 {
+            let params = KeyValueStoreUpdateParams::Insert(format!("key {i}"), "value".to_string());
+            db.update(&params).unwrap();
+        }
+
+        // every update is 29 bytes, so every fourth one triggers a checkpoint
+        let version = std::fs::read_to_string(path.join("version")).unwrap();
+        assert_eq!(version, "2");
+        let log = std::fs::read_to_string(path.join("logfile.2")).unwrap();
+        assert_eq!(log.lines().count(), 2);
+
+        let data = db.clone_data();
+        drop(db);
+        let db = KeyValueStoreDb::open(&path).unwrap();
+        assert_eq!(data, db.clone_data());
+    }
+
+    #[test]
+    fn test_auto_checkpoint_ratio() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("kv-store");
+        let init = KeyValueStore {
+            store: (0..10)
+                .map(|i| (format!("init {i}"), "value".to_string()))
+                .collect(),
+        };
+        let mut db = Database::builder(JsonFormat::<KeyValueStore>::new())
+            .auto_checkpoint_ratio(0.5)
+            .open_or_init(&path, || init)
+            .unwrap();
+        let params = |i: usize| KeyValueStoreUpdateParams::Insert(format!("k{i:03}"), "v".into());
+        let update_bytes = db.format().serialize_params(&params(0)).unwrap().len() as u64;
+
+        // the threshold grows with the checkpoint
+        let mut i = 0;
+        for version in 1..=2 {
+            let checkpoint_bytes = db.stats().unwrap().checkpoint_file_bytes;
+            let expected = checkpoint_bytes / 2 / update_bytes + 1;
+            for n in 1..=expected {
+                assert_eq!(db.stats().unwrap().version, version - 1);
+                db.update(&params(i)).unwrap();
+                i += 1;
+                assert_eq!(
+                    db.stats().unwrap().version,
+                    version - 1 + (n == expected) as u64
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_update_batch() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("kv-store");
+        let mut db = KeyValueStoreDb::open(&path).unwrap();
+        db.insert("key".to_string(), "value".to_string()).unwrap();
+        let ret = db
+            .update_batch(&[
+                KeyValueStoreUpdateParams::Insert_with_check("key".to_string(), "".to_string()),
+                KeyValueStoreUpdateParams::Insert_with_check("new".to_string(), "".to_string()),
+            ])
+            .unwrap();
+        assert!(matches!(
+            ret.as_slice(),
+            [
+                KeyValueStoreUpdateReturn::Insert_with_check(false),
+                KeyValueStoreUpdateReturn::Insert_with_check(true)
+            ]
+        ));
+        let batch: Vec<_> = (0..3)
+            .map(|i| KeyValueStoreUpdateParams::Insert(format!("key {i}"), "value".to_string()))
+            .collect();
+        db.update_batch(&batch).unwrap();
+
+        // each group is a single line
+        let log = std::fs::read_to_string(path.join("logfile.0")).unwrap();
+        assert_eq!(log.lines().count(), 3);
+        let data = db.clone_data();
+        drop(db);
+        let db = KeyValueStoreDb::open(&path).unwrap();
+        assert_eq!(data, db.clone_data());
+        drop(db);
+
+        // a partially written group is skipped completely
+        std::fs::write(path.join("logfile.0"), &log[..log.len() - 10]).unwrap();
+        let db = KeyValueStoreDb::open(&path).unwrap();
+        assert_eq!(db.get("new"), Some("".to_string()));
+        assert_eq!(db.get("key 0"), None);
+    }
+
+    #[test]
+    fn test_replay_report() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("kv-store");
+        let open = || Database::open_with_report(&path, JsonFormat::<KeyValueStore>::new());
+        let (mut db, report) = open().unwrap();
+        assert_eq!(report, ReplayReport::default());
+        for i in 0..3 {
+            let params = KeyValueStoreUpdateParams::Insert(format!("key {i}"), "value".to_string());
+            db.update(&params).unwrap();
+        }
+        drop(db);
+        let (db, report) = open().unwrap();
+        assert_eq!(report.entries_applied, 3);
+        assert!(!report.truncated);
+        drop(db);
+
+        let log_path = path.join("logfile.0");
+        let log = std::fs::read(&log_path).unwrap();
+        std::fs::write(&log_path, &log[..log.len() - 5]).unwrap();
+        let (_, report) = open().unwrap();
+        assert_eq!(report.entries_applied, 2);
+        assert!(report.truncated);
+    }
+
+    #[test]
+    fn test_missing_log() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("kv-store");
+        let mut db = KeyValueStoreDb::open(&path).unwrap();
+        db.insert("key".to_string(), "value".to_string()).unwrap();
+        db.create_checkpoint().unwrap();
+        db.insert("more".to_string(), "value".to_string()).unwrap();
+        drop(db);
+
+        std::fs::remove_file(path.join("logfile.1")).unwrap();
+        let mut db = KeyValueStoreDb::open(&path).unwrap();
+        assert_eq!(db.get("key"), Some("value".to_string()));
+        assert_eq!(db.get("more"), None);
+        db.insert("another".to_string(), "pair".to_string())
+            .unwrap();
+        drop(db);
+        let db = KeyValueStoreDb::open(&path).unwrap();
+        assert_eq!(db.get("another"), Some("pair".to_string()));
+    }
+
+    #[test]
+    fn test_errors() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("kv-store");
+        drop(KeyValueStoreDb::open(&path).unwrap());
+
+        // without an intact checkpoint, the version cannot be recovered
+        std::fs::write(path.join("checkpoint.0.sha256"), "wrong").unwrap();
+        std::fs::write(path.join("version"), "one").unwrap();
+        let err = KeyValueStoreDb::open(&path).err().unwrap();
+        assert!(matches!(&err, BjwError::VersionParse(v) if v == "one"));
+        let err = std::io::Error::from(err);
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        std::fs::write(path.join("version"), "0").unwrap();
+        std::fs::write(path.join("checkpoint.0"), "{").unwrap();
+        std::fs::remove_file(path.join("checkpoint.0.sha256")).unwrap();
+        let err = KeyValueStoreDb::open(&path).err().unwrap();
+        assert!(matches!(err, BjwError::Deserialize(_)));
+    }
+
+    #[test]
+    fn test_stats() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("kv-store");
+        let mut db = KeyValueStoreDb::open(&path).unwrap();
+        db.insert("key".to_string(), "value".to_string()).unwrap();
+        db.update_batch(&[
+            KeyValueStoreUpdateParams::Insert("a".to_string(), "b".to_string()),
+            KeyValueStoreUpdateParams::Insert("c".to_string(), "d".to_string()),
+        ])
+        .unwrap();
+        let stats = db.stats().unwrap();
+        assert_eq!(stats.version, 0);
+        assert_eq!(stats.updates_since_checkpoint, 3);
+        let log_len = std::fs::metadata(path.join("logfile.0")).unwrap().len();
+        assert_eq!(stats.log_file_bytes, log_len);
+        drop(db);
+
+        let mut db = KeyValueStoreDb::open(&path).unwrap();
+        assert_eq!(db.stats().unwrap().updates_since_checkpoint, 3);
+        db.create_checkpoint().unwrap();
+        let stats = db.stats().unwrap();
+        assert_eq!(stats.version, 1);
+        assert_eq!(stats.updates_since_checkpoint, 0);
+        assert_eq!(stats.log_file_bytes, 0);
+        let checkpoint_len = std::fs::metadata(path.join("checkpoint.1")).unwrap().len();
+        assert_eq!(stats.checkpoint_file_bytes, checkpoint_len);
+    }
+
+    #[test]
+    fn test_version() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("kv-store");
+        let mut db = KeyValueStoreDb::open(&path).unwrap();
+        assert_eq!(db.version(), 0);
+        db.insert("key".to_string(), "value".to_string()).unwrap();
+        db.create_checkpoint().unwrap();
+        db.create_checkpoint().unwrap();
+        assert_eq!(db.version(), 2);
+        drop(db);
+
+        let db = Database::open(&path, JsonFormat::<KeyValueStore>::new()).unwrap();
+        assert_eq!(db.version(), 2);
+        assert!(path.join(format!("checkpoint.{}", db.version())).exists());
+    }
+
+    #[test]
+    fn test_iter_updates() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("kv-store");
+        let mut db = Database::open(&path, JsonFormat::<KeyValueStore>::new()).unwrap();
+        assert_eq!(db.iter_updates().unwrap().count(), 0);
+        db.update(&KeyValueStoreUpdateParams::Insert(
+            "a".to_string(),
+            "1".to_string(),
+        ))
+        .unwrap();
+        db.update(&KeyValueStoreUpdateParams::Insert_with_check(
+            "a".to_string(),
+            "2".to_string(),
+        ))
+        .unwrap();
+        db.update(&KeyValueStoreUpdateParams::Insert(
+            "b".to_string(),
+            "3".to_string(),
+        ))
+        .unwrap();
+
+        let updates: Vec<_> = db.iter_updates().unwrap().map(Result::unwrap).collect();
+        assert_eq!(updates.len(), 3);
+        assert!(
+            matches!(&updates[0], KeyValueStoreUpdateParams::Insert(k, v) if k == "a" && v == "1")
+        );
+        assert!(
+            matches!(&updates[1], KeyValueStoreUpdateParams::Insert_with_check(k, v) if k == "a" && v == "2")
+        );
+        assert!(
+            matches!(&updates[2], KeyValueStoreUpdateParams::Insert(k, v) if k == "b" && v == "3")
+        );
+        db.create_checkpoint().unwrap();
+        assert_eq!(db.iter_updates().unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_log_timestamps() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("kv-store");
+        let insert = |k: &str, v: &str| KeyValueStoreUpdateParams::Insert(k.into(), v.into());
+        let before = std::time::SystemTime::now();
+        let mut db = Database::builder(JsonFormat::<KeyValueStore>::new())
+            .log_timestamps()
+            .log_segment_bytes(64)
+            .open(&path)
+            .unwrap();
+        for i in 0..5 {
+            db.update(&insert(&i.to_string(), "x")).unwrap();
+        }
+        db.update_batch(&[insert("a", "1"), insert("b", "2")])
+            .unwrap();
+        drop(db);
+        let after = std::time::SystemTime::now();
+
+        // reopened without the option, the segments written with it stay timestamped
+        let mut db = Database::open(&path, JsonFormat::<KeyValueStore>::new()).unwrap();
+        assert_eq!(db.read_all().store.len(), 7);
+        db.update(&insert("c", "3")).unwrap();
+        let updates: Vec<_> = db
+            .iter_updates_with_timestamps()
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(updates.len(), 8);
+        let timestamps: Vec<_> = updates.iter().map(|(timestamp, _)| *timestamp).collect();
+        assert!(timestamps.iter().all(Option::is_some));
+        assert!(timestamps.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!(timestamps[0].unwrap() >= before && timestamps[6].unwrap() <= after);
+        assert_eq!(timestamps[5], timestamps[6]);
+        assert!(
+            matches!(&updates[6].1, KeyValueStoreUpdateParams::Insert(k, v) if k == "b" && v == "2")
+        );
+        assert_eq!(db.iter_updates().unwrap().count(), 8);
+        assert!(db.verify().unwrap().is_ok());
+        let mut replica =
+            Database::open_read_only(&path, JsonFormat::<KeyValueStore>::new()).unwrap();
+        db.update(&insert("e", "5")).unwrap();
+        db.flush().unwrap();
+        assert_eq!(replica.refresh().unwrap(), 1);
+        assert_eq!(replica.read_all().store.len(), 9);
+
+        // a fresh segment follows the option again
+        db.create_checkpoint().unwrap();
+        db.update(&insert("d", "4")).unwrap();
+        let updates: Vec<_> = db
+            .iter_updates_with_timestamps()
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert!(matches!(&updates[..], [(None, _)]));
+    }
+
+    #[test]
+    fn test_fold_updates() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("kv-store");
+        let mut db = Database::open(&path, JsonFormat::<KeyValueStore>::new()).unwrap();
+        for (key, value) in [("a", "1"), ("b", "2"), ("a", "3")] {
+            db.update(&KeyValueStoreUpdateParams::Insert(
+                key.to_string(),
+                value.to_string(),
+            ))
+            .unwrap();
+        }
+
+        let inserts_per_key = db
+            .fold_updates(BTreeMap::new(), |mut counts, args| {
+                if let KeyValueStoreUpdateParams::Insert(key, _) = args {
+                    *counts.entry(key.clone()).or_insert(0) += 1;
+                }
+                counts
+            })
+            .unwrap();
+        assert_eq!(
+            inserts_per_key,
+            BTreeMap::from([("a".to_string(), 2), ("b".to_string(), 1)])
+        );
+        assert_eq!(db.clone_data().store["a"], "3");
+    }
+
+    #[test]
+    fn test_sync_policy() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        for (i, policy) in [SyncPolicy::EveryN(3), SyncPolicy::Never]
+            .into_iter()
+            .enumerate()
+        {
+            let path = tempdir.path().join(format!("kv-store-{i}"));
+            let fmt = JsonFormat::<KeyValueStore>::new();
+            let mut db = Database::builder(fmt)
+                .sync_policy(policy)
+                .open(&path)
+                .unwrap();
+            for i in 0..10 {
+                let params =
+                    KeyValueStoreUpdateParams::Insert(format!("key {i}"), "value".to_string());
+                db.update(&params).unwrap();
+            }
+
+            let data = db.clone_data();
+            drop(db);
+            let mut db = KeyValueStoreDb::open(&path).unwrap();
+            assert_eq!(data, db.clone_data());
+            db.create_checkpoint().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_drop_syncs_log() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("kv-store");
+        let backend = FaultInjectingBackend::new(FsBackend);
+        let open = || {
+            Database::builder(JsonFormat::<KeyValueStore>::new())
+                .sync_policy(SyncPolicy::Never)
+                .backend(backend.clone())
+                .open(&path)
+                .unwrap()
+        };
+        let mut db = open();
+        for i in 0..10 {
+            let params = KeyValueStoreUpdateParams::Insert(format!("key {i}"), "value".to_string());
+            db.update(&params).unwrap();
+        }
+        let data = db.clone_data();
+        let writes = backend.writes();
+        drop(db);
+        // the log is synced once more, which counts as a write
+        assert_eq!(backend.writes(), writes + 1);
+        assert_eq!(KeyValueStoreDb::open(&path).unwrap().clone_data(), data);
+
+        // close reports what dropping can only log
+        let mut db = open();
+        let params = KeyValueStoreUpdateParams::Insert("key".to_string(), "value".to_string());
+        db.update(&params).unwrap();
+        backend.fail_nth_write(1);
+        assert!(db.close().is_err());
+    }
+
+    #[test]
+    fn test_current_paths() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("kv-store");
+        let mut db = Database::open(&path, JsonFormat::<KeyValueStore>::new()).unwrap();
+        assert_eq!(db.current_checkpoint_path(), path.join("checkpoint.0"));
+        db.create_checkpoint().unwrap();
+        let params = KeyValueStoreUpdateParams::Insert("key".to_string(), "value".to_string());
+        db.update(&params).unwrap();
+        assert_eq!(db.current_checkpoint_path(), path.join("checkpoint.1"));
+        assert_eq!(db.current_log_path(), path.join("logfile.1"));
+        assert!(db.current_checkpoint_path().exists());
+        assert!(std::fs::read_to_string(db.current_log_path())
+            .unwrap()
+            .contains("key"));
+    }
+
+    #[test]
+    fn test_refresh() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("kv-store");
+        let mut writer = KeyValueStoreDb::open(&path).unwrap();
+        writer.insert("a".to_string(), "value".to_string()).unwrap();
+        let mut replica = KeyValueStoreDb::open_read_only(&path).unwrap();
+        assert_eq!(replica.refresh().unwrap(), 0);
+        assert!(writer.refresh().is_err());
+
+        writer.insert("b".to_string(), "value".to_string()).unwrap();
+        writer.insert("c".to_string(), "value".to_string()).unwrap();
+        assert_eq!(replica.refresh().unwrap(), 2);
+        assert_eq!(replica.get("c"), Some("value".to_string()));
+
+        // a new checkpoint is loaded together with its log
+        writer.create_checkpoint().unwrap();
+        writer.insert("d".to_string(), "value".to_string()).unwrap();
+        assert_eq!(replica.refresh().unwrap(), 1);
+        assert_eq!(replica.version(), 1);
+        assert_eq!(replica.clone_data(), writer.clone_data());
+        drop(writer);
+
+        // an update that is only partially written is picked up once it is complete
+        let log_path = path.join("logfile.1");
+        let append = |content: &str| {
+            let mut log = std::fs::OpenOptions::new()
+                .append(true)
+                .open(&log_path)
+                .unwrap();
+            log.write_all(content.as_bytes()).unwrap();
+        };
+        append(r#"{"Insert":["e","#);
+        assert_eq!(replica.refresh().unwrap(), 0);
+        append("\"value\"]}\n");
+        assert_eq!(replica.refresh().unwrap(), 1);
+        assert_eq!(replica.get("e"), Some("value".to_string()));
+        assert_eq!(replica.refresh().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_cleanup_leaves_foreign_files() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("kv-store");
+        let mut db = KeyValueStoreDb::open(&path).unwrap();
+        db.insert("key".to_string(), "value".to_string()).unwrap();
+        let foreign = [
+            "checkpoint.00",
+            "checkpoint.+0",
+            "checkpoint.0x",
+            "checkpoint.0.bak",
+            "checkpoint.0.sha256.orig",
+            "logfile.00",
+            "logfile.0.01",
+            "logfile.0.-1",
+            "my.backup.logfile.0",
+            "notes.tmp",
+            "checkpoint",
+            ".checkpoint.0",
+        ];
+        for filename in foreign {
+            std::fs::write(path.join(filename), "not ours").unwrap();
+        }
+        for _ in 0..3 {
+            db.create_checkpoint().unwrap();
+        }
+        assert!(!path.join("checkpoint.0").exists());
+        for filename in foreign {
+            assert!(path.join(filename).exists(), "{filename} was removed");
+        }
+    }
+
+    #[test]
+    fn test_list_versions() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("kv-store");
+        let mut db = Database::builder(JsonFormat::<KeyValueStore>::new())
+            .keep_versions(2)
+            .open(&path)
+            .unwrap();
+        assert_eq!(db.list_versions().unwrap(), [0]);
+        for i in 0..5 {
+            let params = KeyValueStoreUpdateParams::Insert(i.to_string(), "value".to_string());
+            db.update(&params).unwrap();
+            db.create_checkpoint().unwrap();
+        }
+        let versions = db.list_versions().unwrap();
+        assert_eq!(versions, [3, 4, 5]);
+        for version in versions {
+            assert!(path.join(format!("checkpoint.{version}")).exists());
+            // the checkpoint holds `version` entries, its log adds the next one
+            let len = (version as usize + 1).min(5);
+            assert_eq!(db.view_at(version).unwrap().store.len(), len);
+        }
+        assert!(!path.join("checkpoint.2").exists());
+    }
+
+    #[test]
+    fn test_history() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let mut db = Database::builder(JsonFormat::<KeyValueStore>::new())
+            .keep_versions(3)
+            .open(tempdir.path().join("kv-store"))
+            .unwrap();
+        for (version, updates) in [2, 0, 3].into_iter().enumerate() {
+            for i in 0..updates {
+                let params = KeyValueStoreUpdateParams::Insert(
+                    format!("key {version} {i}"),
+                    "value".to_string(),
+                );
+                db.update(&params).unwrap();
+            }
+            db.create_checkpoint().unwrap();
+        }
+        let params = KeyValueStoreUpdateParams::Insert("another".to_string(), "pair".to_string());
+        db.update(&params).unwrap();
+        db.flush().unwrap();
+        assert_eq!(db.history().unwrap(), [(0, 2), (1, 0), (2, 3), (3, 1)]);
+    }
+
+    #[test]
+    fn test_append_only() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("kv-store");
+        let mut db = KeyValueStoreDb::open(&path).unwrap();
+        db.insert("a".to_string(), "value".to_string()).unwrap();
+        drop(db);
+
+        let insert =
+            |key: &str| KeyValueStoreUpdateParams::Insert(key.to_string(), "value".to_string());
+        let open =
+            || Database::open_append_only(&path, JsonFormat::<KeyValueStore>::new()).unwrap();
+        let mut db = open();
+        db.append(&insert("b")).unwrap();
+        db.append(&insert("c")).unwrap();
+        assert!(db.has_pending_updates().unwrap());
+        // the checkpoint folds the log into the state on disk
+        db.create_checkpoint().unwrap();
+        assert_eq!(db.version(), 1);
+        db.append(&insert("d")).unwrap();
+        drop(db);
+        let mut db = open();
+        db.append(&insert("e")).unwrap();
+        drop(db);
+
+        let db = KeyValueStoreDb::open(&path).unwrap();
+        assert_eq!(db.version(), 1);
+        assert_eq!(
+            db.clone_data().store.into_keys().collect::<Vec<_>>(),
+            ["a", "b", "c", "d", "e"]
+        );
+    }
+
+    #[test]
+    fn test_has_pending_updates() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("kv-store");
+        let mut db = KeyValueStoreDb::open(&path).unwrap();
+        assert!(!db.has_pending_updates().unwrap());
+        db.insert("key".to_string(), "value".to_string()).unwrap();
+        assert!(db.has_pending_updates().unwrap());
+        db.create_checkpoint().unwrap();
+        assert!(!db.has_pending_updates().unwrap());
+        db.insert("key".to_string(), "value".to_string()).unwrap();
+        drop(db);
+        assert!(KeyValueStoreDb::open(&path)
+            .unwrap()
+            .has_pending_updates()
+            .unwrap());
+    }
+
+    #[cfg(feature = "json-relaxed")]
+    #[test]
+    fn test_lenient_checkpoint() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("kv-store");
+        let mut db = Database::open(&path, JsonFormat::<KeyValueStore>::lenient()).unwrap();
+        let params = KeyValueStoreUpdateParams::Insert("key".to_string(), "value".to_string());
+        db.update(&params).unwrap();
+        db.create_checkpoint().unwrap();
+        drop(db);
+
+        let checkpoint = std::fs::read_to_string(path.join("checkpoint.1")).unwrap();
+        let edited = checkpoint.replace("\"value\"", "\"edited\", // by hand");
+        assert_ne!(checkpoint, edited);
+        std::fs::write(path.join("checkpoint.1"), edited).unwrap();
+        std::fs::remove_file(path.join("checkpoint.1.sha256")).unwrap();
+        assert!(KeyValueStoreDb::open(&path).is_err());
+        let db = Database::open(&path, JsonFormat::<KeyValueStore>::lenient()).unwrap();
+        assert_eq!(db.read_all().store["key"], "edited");
+    }
+
+    #[test]
+    fn test_rebuild_from_logs() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("kv-store");
+        let builder = || Database::builder(JsonFormat::<KeyValueStore>::new()).keep_versions(10);
+        let mut db = builder().open(&path).unwrap();
+        for i in 0..9 {
+            let params = KeyValueStoreUpdateParams::Insert(format!("key {}", i % 4), i.to_string());
+            db.update(&params).unwrap();
+            if i % 3 == 2 {
+                db.create_checkpoint().unwrap();
+            }
+        }
+        let data = db.clone_data();
+        drop(db);
+
+        std::fs::remove_file(path.join("checkpoint.3")).unwrap();
+        assert!(builder().open(&path).is_err());
+        let db = builder().rebuild_from_logs().open(&path).unwrap();
+        assert_eq!(db.clone_data(), data);
+        assert_eq!(db.stats().unwrap().version, 4);
+        drop(db);
+        assert_eq!(builder().open(&path).unwrap().clone_data(), data);
+
+        // a missing log in between cannot be made up for
+        std::fs::remove_file(path.join("logfile.1")).unwrap();
+        let res = builder().rebuild_from_logs().open(&path);
+        assert!(matches!(res, Err(BjwError::Corrupt(e)) if e.contains("version 1 is missing")));
+    }
+
+    #[test]
+    fn test_open_file_instead_of_directory() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("kv-store");
+        std::fs::write(&path, "not a database").unwrap();
+        for res in [
+            KeyValueStoreDb::open(&path).map(|_| ()),
+            Database::open_read_only(&path, JsonFormat::<KeyValueStore>::new()).map(|_| ()),
+        ] {
+            let Err(BjwError::Io(e)) = res else {
+                panic!("opened a file as a database");
+            };
+            assert_eq!(e.kind(), ErrorKind::NotADirectory);
+            assert!(e.to_string().contains("is not a directory"));
+        }
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "not a database");
+    }
+
+    #[test]
+    fn test_max_replay_updates() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("kv-store");
+        let open = || {
+            Database::builder(JsonFormat::<KeyValueStore>::new())
+                .sync_policy(SyncPolicy::Never)
+                .max_replay_updates(1000)
+                .auto_cleanup(false)
+                .open_with_report(&path)
+                .unwrap()
+        };
+        let (mut db, _) = open();
+        for i in 0..1000 {
+            let params = KeyValueStoreUpdateParams::Insert(i.to_string(), "value".to_string());
+            db.update(&params).unwrap();
+        }
+        drop(db);
+        // the limit is not exceeded yet
+        let (mut db, report) = open();
+        assert_eq!((report.entries_applied, db.version()), (1000, 0));
+        for i in 1000..10_000 {
+            let params = KeyValueStoreUpdateParams::Insert(i.to_string(), "value".to_string());
+            db.update(&params).unwrap();
+        }
+        drop(db);
+
+        // replay checkpoints every 1000 updates, and the last ones are carried into the new log
+        let (db, report) = open();
+        assert_eq!((report.entries_applied, db.version()), (10_000, 9));
+        assert_eq!(db.list_versions().unwrap(), (0..=9).collect::<Vec<_>>());
+        for version in 1..=9 {
+            let checkpoint = db.read_checkpoint_file(version).unwrap();
+            let expected: Vec<_> = (0..version as usize * 1000)
+                .map(|i| i.to_string())
+                .collect();
+            let mut keys: Vec<_> = checkpoint.store.into_keys().collect();
+            keys.sort_by_key(|key| key.parse::<usize>().unwrap());
+            assert_eq!(keys, expected);
+        }
+        assert!(db.has_pending_updates().unwrap());
+        assert_eq!(db.read_all().store.len(), 10_000);
+        drop(db);
+        let (mut db, report) = open();
+        assert_eq!((report.entries_applied, db.version()), (1000, 9));
+        assert_eq!(db.read_all().store.len(), 10_000);
+        // appending continues in the carried log
+        let params = KeyValueStoreUpdateParams::Insert("10000".to_string(), "value".to_string());
+        db.update(&params).unwrap();
+        drop(db);
+        // which exceeds the limit again
+        let (db, report) = open();
+        assert_eq!((report.entries_applied, db.version()), (1001, 10));
+        assert_eq!(db.read_all().store.len(), 10_001);
+        drop(db);
+        let (_, report) = open();
+        assert_eq!(report.entries_applied, 1);
+    }
+
+    #[test]
+    fn test_read_only() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("kv-store");
+        assert!(KeyValueStoreDb::open_read_only(&path).is_err());
+        assert!(!path.exists());
+
+        let mut db = KeyValueStoreDb::open(&path).unwrap();
+        db.insert("key".to_string(), "value".to_string()).unwrap();
+
+        let mut reader = KeyValueStoreDb::open_read_only(&path).unwrap();
+        assert_eq!(reader.get("key"), Some("value".to_string()));
+        let err = reader
+            .insert("other".to_string(), "value".to_string())
+            .unwrap_err();
+        assert!(matches!(err, BjwError::Io(e) if e.kind() == ErrorKind::PermissionDenied));
+        let err = reader.create_checkpoint().unwrap_err();
+        assert!(matches!(err, BjwError::Io(e) if e.kind() == ErrorKind::PermissionDenied));
+        assert!(!path.join("logfile.1").exists());
+        assert_eq!(db.clone_data(), reader.clone_data());
+    }
+
+    #[test]
+    fn test_in_memory() {
+        let mut db = Database::open_in_memory(JsonFormat::<KeyValueStore>::new()).unwrap();
+        let params = KeyValueStoreUpdateParams::Insert("key".to_string(), "value".to_string());
+        db.update(&params).unwrap();
+        db.create_checkpoint().unwrap();
+        assert_eq!(db.path(), &PathBuf::new());
+
+        // re-open from a shared backend to replay checkpoint and log
+        let backend = MemoryBackend::new();
+        let fmt = || JsonFormat::<KeyValueStore>::new();
+        drop(db);
+        let mut db = Database::builder(fmt())
+            .backend(backend.clone())
+            .open("kv-store")
+            .unwrap();
+        db.update(&params).unwrap();
+        db.create_checkpoint().unwrap();
+        let params = KeyValueStoreUpdateParams::Insert("another".to_string(), "pair".to_string());
+        db.update(&params).unwrap();
+        let data = db.clone_data();
+        drop(db);
+        let db = Database::builder(fmt())
+            .backend(backend.clone())
+            .open("kv-store")
+            .unwrap();
+        assert_eq!(data, db.clone_data());
+        assert!(!Path::new("kv-store").exists());
+
+        let mut db = KeyValueStoreDb::open_in_memory().unwrap();
+        db.insert("key".to_string(), "value".to_string()).unwrap();
+        assert_eq!(db.get("key"), Some("value".to_string()));
+    }
+
+    #[test]
+    fn test_create_new_keeps_existing_files() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("logfile.0");
+        let backends: [(&dyn Backend, &Path); 2] = [
+            (&FsBackend, &path),
+            (&MemoryBackend::new(), Path::new("logfile.0")),
+        ];
+        for (backend, path) in backends {
+            // of all handles racing to create the log, exactly one does
+            let created = std::thread::scope(|scope| {
+                let racers: Vec<_> = (0..8)
+                    .map(|_| scope.spawn(|| backend.create_new(path).unwrap()))
+                    .collect();
+                racers
+                    .into_iter()
+                    .map(|racer| racer.join().unwrap())
+                    .filter(|&created| created)
+                    .count()
+            });
+            assert_eq!(created, 1);
+            backend.append(path, b"update", true).unwrap();
+            assert!(!backend.create_new(path).unwrap());
+            assert_eq!(backend.read(path).unwrap(), b"update");
+        }
+
+        // a log that exists already is appended to, not truncated
+        let path = tempdir.path().join("kv-store");
+        let mut db = KeyValueStoreDb::open(&path).unwrap();
+        db.insert("key".to_string(), "value".to_string()).unwrap();
+        drop(db);
+        let mut db = KeyValueStoreDb::open(&path).unwrap();
+        db.insert("another".to_string(), "pair".to_string())
+            .unwrap();
+        drop(db);
+        let db = KeyValueStoreDb::open(&path).unwrap();
+        assert_eq!(db.get("key"), Some("value".to_string()));
+        assert_eq!(db.get("another"), Some("pair".to_string()));
+    }
+
+    #[cfg(feature = "object_store")]
+    #[test]
+    fn test_object_store_backend() {
+        // the same steps leave the same files behind as on the local file system
+        fn run<B: Backend + 'static>(
+            path: &Path,
+            backend: impl Fn() -> B,
+        ) -> (KeyValueStore, Vec<(String, Vec<u8>)>) {
+            let open = || {
+                Database::builder(JsonFormat::<KeyValueStore>::new())
+                    .backend(backend())
+                    .keep_versions(1)
+                    .open(path)
+                    .unwrap()
+            };
+            let mut db = open();
+            for key in ["a", "b", "c"] {
+                let params =
+                    KeyValueStoreUpdateParams::Insert(key.to_string(), "value".to_string());
+                db.update(&params).unwrap();
+                if key != "c" {
+                    db.create_checkpoint().unwrap();
+                }
+            }
+            drop(db);
+            let db = open();
+            let backend = backend();
+            let mut files: Vec<_> = backend
+                .list_files(path)
+                .unwrap()
+                .into_iter()
+                .filter(|filename| filename != ".lock")
+                .map(|filename| {
+                    let data = backend.read(&path.join(&filename)).unwrap();
+                    (filename, data)
+                })
+                .collect();
+            files.sort();
+            (db.clone_data(), files)
+        }
+
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let expected = run(&tempdir.path().join("kv-store"), || FsBackend);
+        let store: std::sync::Arc<dyn object_store::ObjectStore> =
+            std::sync::Arc::new(object_store::memory::InMemory::new());
+        let backend = || ObjectStoreBackend::new(store.clone()).unwrap();
+        assert_eq!(run(Path::new("bucket/kv-store"), backend), expected);
+        assert_eq!(expected.1.len(), 7);
+
+        let backend = backend();
+        assert!(backend.exists(Path::new("bucket")).unwrap());
+        backend.remove_dir_all(Path::new("bucket")).unwrap();
+        assert!(!backend.exists(Path::new("bucket")).unwrap());
+    }
+
+    #[cfg(feature = "object_store")]
+    #[test]
+    fn test_object_store_appends() {
+        use object_store::{path::Path as Location, ObjectStore};
+
+        let store = std::sync::Arc::new(object_store::memory::InMemory::new());
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let objects = |prefix: &str| {
+            let list = runtime.block_on(store.list_with_delimiter(Some(&Location::from(prefix))));
+            list.unwrap().objects.len()
+        };
+        let backend = ObjectStoreBackend::new(store.clone()).unwrap();
+        let log = Path::new("bucket/logfile.0");
+        assert!(backend.append(log, b"a", true).is_err());
+        backend.write(log, b"a").unwrap();
+        backend.append(log, b"b", true).unwrap();
+        backend.append(log, b"c", true).unwrap();
+
+        // every append is an object of its own, the file itself is never uploaded again
+        let head = runtime.block_on(store.head(&Location::from("bucket/logfile.0")));
+        assert_eq!(head.unwrap().size, 1);
+        assert_eq!(objects("bucket/logfile.0"), 2);
+        assert_eq!(backend.read(log).unwrap(), b"abc");
+        assert_eq!(backend.file_size(log).unwrap(), 3);
+        assert_eq!(
+            backend.list_files(Path::new("bucket")).unwrap(),
+            ["logfile.0"]
+        );
+
+        // another handle continues the numbering
+        let other = ObjectStoreBackend::new(store.clone()).unwrap();
+        other.append(log, b"d", true).unwrap();
+        backend.append(log, b"e", true).unwrap();
+        assert_eq!(backend.read(log).unwrap(), b"abcde");
+
+        let compact = Path::new("bucket/logfile.0.compact");
+        backend.rename(log, compact).unwrap();
+        assert!(!backend.exists(log).unwrap());
+        assert_eq!(objects("bucket/logfile.0"), 0);
+        assert_eq!(backend.read(compact).unwrap(), b"abcde");
+        backend.write(compact, b"x").unwrap();
+        assert_eq!(backend.read(compact).unwrap(), b"x");
+        backend.append(compact, b"y", true).unwrap();
+        backend.remove_file(compact).unwrap();
+        assert!(!backend.exists(Path::new("bucket")).unwrap());
+    }
+
+    #[test]
+    fn test_injected_faults() {
+        let insert = |db: &mut Database<KeyValueStore, JsonFormat<KeyValueStore>>, key: &str| {
+            let params = KeyValueStoreUpdateParams::Insert(key.to_string(), "value".to_string());
+            db.update(&params)
+        };
+        // fail every write of a checkpoint in turn, which must leave either version intact
+        for n in 1.. {
+            let memory = MemoryBackend::new();
+            let backend = FaultInjectingBackend::new(memory.clone());
+            let mut db = Database::builder(JsonFormat::<KeyValueStore>::new())
+                .backend(backend.clone())
+                .open("kv-store")
+                .unwrap();
+            insert(&mut db, "a").unwrap();
+            let writes = backend.writes();
+            backend.fail_nth_write(n);
+            let failed = db.create_checkpoint().is_err();
+            let hit = backend.writes() - writes >= n;
+            drop(db);
+
+            let mut db = Database::builder(JsonFormat::<KeyValueStore>::new())
+                .backend(memory)
+                .open("kv-store")
+                .unwrap();
+            // once the new version file is written, opening continues with it
+            assert!(db.version() <= 1, "write {n}");
+            assert!(db.verify().unwrap().is_ok(), "write {n}");
+            insert(&mut db, "b").unwrap();
+            assert_eq!(db.read_all().store.len(), 2, "write {n}");
+            if !hit {
+                assert!(!failed);
+                break;
+            }
+        }
+
+        // a failed append leaves the update out
+        let memory = MemoryBackend::new();
+        let backend = FaultInjectingBackend::new(memory.clone());
+        let mut db = Database::builder(JsonFormat::<KeyValueStore>::new())
+            .backend(backend.clone())
+            .open("kv-store")
+            .unwrap();
+        backend.fail_nth_write(1);
+        assert!(insert(&mut db, "a").is_err());
+        insert(&mut db, "b").unwrap();
+        drop(db);
+        let db = Database::builder(JsonFormat::<KeyValueStore>::new())
+            .backend(memory)
+            .open("kv-store")
+            .unwrap();
+        assert_eq!(db.read_all().store.keys().collect::<Vec<_>>(), ["b"]);
+    }
+
+    #[test]
+    fn test_failed_sync() {
+        let memory = MemoryBackend::new();
+        let backend = FaultInjectingBackend::new(memory.clone());
+        let open = |backend| {
+            Database::builder(JsonFormat::<KeyValueStore>::new())
+                .backend(backend)
+                .open("kv-store")
+                .unwrap()
+        };
+        let mut db = open(backend.clone());
+        let insert =
+            |key: &str| KeyValueStoreUpdateParams::Insert(key.to_string(), "value".to_string());
+        db.update(&insert("a")).unwrap();
+        backend.fail_nth_sync(1);
+        assert!(db.update(&insert("b")).is_err());
+        // the update is not applied, and nothing can build on the state until it is persisted
+        assert_eq!(db.read_all().store.keys().collect::<Vec<_>>(), ["a"]);
+        assert!(db.update(&insert("c")).is_err());
+        db.create_checkpoint().unwrap();
+        db.update(&insert("c")).unwrap();
+        drop(db);
+        let db = open(FaultInjectingBackend::new(memory));
+        assert_eq!(db.read_all().store.keys().collect::<Vec<_>>(), ["a", "c"]);
+    }
+
+    #[test]
+    fn test_lock() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("kv-store");
+        let db = KeyValueStoreDb::open(&path).unwrap();
+        let err = KeyValueStoreDb::open(&path).err().unwrap();
+        assert!(matches!(err, BjwError::Io(e) if e.kind() == ErrorKind::WouldBlock));
+        assert!(KeyValueStoreDb::open_read_only(&path).is_ok());
+        drop(db);
+
+        // a blocking open waits until the lock is released
+        let db = KeyValueStoreDb::open(&path).unwrap();
+        let waiting = {
+            let path = path.clone();
+            std::thread::spawn(move || {
+                Database::builder(JsonFormat::<KeyValueStore>::new())
+                    .wait_for_lock()
+                    .open(path)
+                    .map(|_| ())
+            })
+        };
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        assert!(!waiting.is_finished());
+        drop(db);
+        waiting.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_racing_first_opens() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("kv-store");
+        std::fs::create_dir(&path).unwrap();
+        // both handles find the directory empty while it is locked
+        let lock = FsBackend.lock(&path.join(".lock"), false).unwrap();
+        let openers: Vec<_> = ["a", "b"]
+            .into_iter()
+            .map(|key| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    let mut db = Database::builder(JsonFormat::<KeyValueStore>::new())
+                        .wait_for_lock()
+                        .open(path)
+                        .unwrap();
+                    let params =
+                        KeyValueStoreUpdateParams::Insert(key.to_string(), "value".to_string());
+                    db.update(&params).unwrap();
+                    db.create_checkpoint().unwrap();
+                })
+            })
+            .collect();
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        drop(lock);
+        for opener in openers {
+            opener.join().unwrap();
+        }
+
+        // the second one opened what the first one created instead of starting over
+        let db = KeyValueStoreDb::open(&path).unwrap();
+        assert_eq!(db.version(), 2);
+        assert_eq!(
+            db.clone_data().store.into_keys().collect::<Vec<_>>(),
+            ["a", "b"]
+        );
+    }
+
+    #[test]
+    fn test_interrupted_checkpoint_write() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("kv-store");
+        let mut db = KeyValueStoreDb::open(&path).unwrap();
+        db.insert("key".to_string(), "value".to_string()).unwrap();
+        db.create_checkpoint().unwrap();
+        db.insert("more".to_string(), "value".to_string()).unwrap();
+        let data = db.clone_data();
+
+        // a crash while writing the next checkpoint leaves only a truncated temporary file
+        std::fs::write(path.join("checkpoint.2.tmp"), b"{\"store\":{\"ke").unwrap();
+        drop(db);
+        let mut db = KeyValueStoreDb::open(&path).unwrap();
+        assert_eq!(data, db.clone_data());
+
+        // the next checkpoint replaces it and cleans up
+        db.create_checkpoint().unwrap();
+        assert!(!path.join("checkpoint.2.tmp").exists());
+        drop(db);
+        let db = KeyValueStoreDb::open(&path).unwrap();
+        assert_eq!(data, db.clone_data());
+    }
+
+    #[test]
+    fn test_checkpoint_fallback() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("kv-store");
+        let open = |fallback: bool| {
+            let builder = Database::builder(JsonFormat::<KeyValueStore>::new());
+            let builder = if fallback {
+                builder.fallback_to_previous_checkpoint()
+            } else {
+                builder
+            };
+            builder.open(&path)
+        };
+        let mut db = open(true).unwrap();
+        for i in 0..3 {
+            let params = KeyValueStoreUpdateParams::Insert(format!("key {i}"), "value".to_string());
+            db.update(&params).unwrap();
+            db.create_checkpoint().unwrap();
+        }
+        let params = KeyValueStoreUpdateParams::Insert("another".to_string(), "pair".to_string());
+        db.update(&params).unwrap();
+        let data = db.clone_data();
+        drop(db);
+        assert!(path.join("checkpoint.2.sha256").exists());
+        assert!(!path.join("checkpoint.1").exists());
+
+        // a bit flip in the newest checkpoint that is still valid json
+        let checkpoint_path = path.join("checkpoint.3");
+        let mut checkpoint = std::fs::read(&checkpoint_path).unwrap();
+        let pos = checkpoint.windows(5).position(|w| w == b"value").unwrap();
+        checkpoint[pos] ^= 1;
+        std::fs::write(&checkpoint_path, checkpoint).unwrap();
+
+        let err = open(false).err().unwrap();
+        assert!(matches!(err, BjwError::Corrupt(_)));
+        let db = open(true).unwrap();
+        assert_eq!(data, db.clone_data());
+    }
+
+    #[test]
+    fn test_create_checkpoint_with_version() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("kv-store");
+        let open = || {
+            Database::builder(JsonFormat::<KeyValueStore>::new())
+                .fallback_to_previous_checkpoint()
+                .open(&path)
+                .unwrap()
+        };
+        let mut db = open();
+        let insert = |db: &mut Database<_, _>, key: &str| {
+            let params = KeyValueStoreUpdateParams::Insert(key.to_string(), "value".to_string());
+            db.update(&params).unwrap();
+        };
+        insert(&mut db, "a");
+        db.create_checkpoint_with_version(10).unwrap();
+        insert(&mut db, "b");
+        db.create_checkpoint_with_version(20).unwrap();
+        assert_eq!(db.version(), 20);
+        for version in [20, 5] {
+            let err = db.create_checkpoint_with_version(version).err().unwrap();
+            assert!(matches!(&err, BjwError::Io(e) if e.kind() == ErrorKind::InvalidInput));
+        }
+        insert(&mut db, "c");
+        let data = db.clone_data();
+        drop(db);
+        // the previous checkpoint is kept to fall back to, even with versions skipped
+        assert!(path.join("checkpoint.10").exists());
+        assert!(!path.join("checkpoint.0").exists());
+
+        assert_eq!(open().clone_data(), data);
+        std::fs::write(path.join("checkpoint.20"), b"garbage").unwrap();
+        let db = open();
+        assert_eq!(db.version(), 20);
+        assert_eq!(db.clone_data(), data);
+    }
+
+    #[test]
+    fn test_view_at() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("kv-store");
+        let mut db = Database::builder(JsonFormat::<KeyValueStore>::new())
+            .fallback_to_previous_checkpoint()
+            .open(&path)
+            .unwrap();
+        let mut history = Vec::new();
+        for i in 0..3 {
+            let params = KeyValueStoreUpdateParams::Insert(format!("key {i}"), "value".to_string());
+            db.update(&params).unwrap();
+            history.push(db.clone_data());
+            db.create_checkpoint().unwrap();
+        }
+        let params = KeyValueStoreUpdateParams::Insert("another".to_string(), "pair".to_string());
+        db.update(&params).unwrap();
+
+        assert_eq!(db.view_at(3).unwrap(), db.clone_data());
+        assert_eq!(db.view_at(2).unwrap(), history[2]);
+        assert_eq!(db.view_at(2).unwrap().store.len(), 3);
+        let err = db.view_at(1).err().unwrap();
+        assert!(matches!(err, BjwError::Io(e) if e.kind() == ErrorKind::NotFound));
+        assert!(db.view_at(4).is_err());
+    }
+
+    #[test]
+    fn test_builder_options() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("kv-store");
+        let open = || {
+            Database::builder(JsonFormat::<KeyValueStore>::new())
+                .auto_checkpoint_bytes(100)
+                .keep_versions(1)
+                .sync_policy(SyncPolicy::EveryN(3))
+                .open(&path)
+                .unwrap()
+        };
+        let mut db = open();
+        for i in 0..20 {
+            let params = KeyValueStoreUpdateParams::Insert(format!("key {i}"), "value".to_string());
+            db.update(&params).unwrap();
+        }
+        let version = db.version();
+        assert!(version >= 5);
+        drop(db);
+
+        let checkpoints = std::fs::read_dir(&path)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.starts_with("checkpoint.") && !name.ends_with(".sha256"))
+            .count();
+        assert_eq!(checkpoints, 2);
+        let db = open();
+        assert_eq!(db.version(), version);
+        assert_eq!(db.read_all().store.len(), 20);
+    }
+
+    #[test]
+    fn test_open_or_init() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("kv-store");
+        let seeded = |value: &str| KeyValueStore {
+            store: BTreeMap::from([("seed".to_string(), value.to_string())]),
+        };
+        let mut db =
+            Database::open_or_init(&path, JsonFormat::new(), || seeded("initial")).unwrap();
+        assert_eq!(db.read_all().store["seed"], "initial");
+        db.update(&KeyValueStoreUpdateParams::Insert(
+            "key".to_string(),
+            "value".to_string(),
+        ))
+        .unwrap();
+        drop(db);
+
+        let db = Database::open_or_init(&path, JsonFormat::new(), || seeded("other")).unwrap();
+        assert_eq!(db.read_all().store["seed"], "initial");
+        assert_eq!(db.read_all().store["key"], "value");
+    }
+
+    #[test]
+    fn test_recover_version() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("kv-store");
+        let mut db = Database::open(&path, JsonFormat::<KeyValueStore>::new()).unwrap();
+        for i in 0..2 {
+            let params = KeyValueStoreUpdateParams::Insert(format!("key {i}"), "value".to_string());
+            db.update(&params).unwrap();
+            db.create_checkpoint().unwrap();
+        }
+        let params =
+            KeyValueStoreUpdateParams::Insert("in the log".to_string(), "value".to_string());
+        db.update(&params).unwrap();
+        drop(db);
+        // a damaged newer checkpoint is skipped
+        std::fs::write(path.join("checkpoint.3"), "{}").unwrap();
+        std::fs::write(path.join("checkpoint.3.sha256"), "wrong").unwrap();
+
+        std::fs::write(path.join("version"), "").unwrap();
+        let db = Database::open(&path, JsonFormat::<KeyValueStore>::new()).unwrap();
+        assert_eq!(db.version(), 2);
+        assert_eq!(db.read_all().store.len(), 3);
+        drop(db);
+        assert_eq!(std::fs::read_to_string(path.join("version")).unwrap(), "2");
+
+        std::fs::write(path.join("version"), "2x").unwrap();
+        std::fs::write(path.join("checkpoint.2.sha256"), "wrong").unwrap();
+        std::fs::write(path.join("checkpoint.1.sha256"), "wrong").unwrap();
+        std::fs::remove_file(path.join("checkpoint.0.sha256")).ok();
+        let err = Database::open(&path, JsonFormat::<KeyValueStore>::new())
+            .err()
+            .unwrap();
+        assert!(matches!(err, BjwError::VersionParse(version) if version == "2x"));
+    }
+
+    #[test]
+    fn test_interrupted_checkpoint() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let orphan = br#"{"store":{"orphan":"value"}}"#.to_vec();
+        let checkpoint = ("checkpoint.1", orphan.clone());
+        let hash = ("checkpoint.1.sha256", sha256_hex(&orphan));
+        let log = ("logfile.1", Vec::new());
+        // the files that exist at each point create_checkpoint can be interrupted at
+        let steps = [
+            vec![("checkpoint.1.tmp", orphan.clone())],
+            vec![checkpoint.clone()],
+            vec![checkpoint.clone(), hash.clone()],
+            vec![checkpoint.clone(), hash.clone(), log.clone()],
+            vec![checkpoint, hash, log, ("new_version", b"1".to_vec())],
+        ];
+        for (step, files) in steps.iter().enumerate() {
+            let path = tempdir.path().join(format!("step-{step}"));
+            let mut db = Database::open(&path, JsonFormat::<KeyValueStore>::new()).unwrap();
+            let params = KeyValueStoreUpdateParams::Insert("key".to_string(), "value".to_string());
+            db.update(&params).unwrap();
+            drop(db);
+            for (filename, data) in files {
+                std::fs::write(path.join(filename), data).unwrap();
+            }
+
+            let mut db = Database::open(&path, JsonFormat::<KeyValueStore>::new()).unwrap();
+            let store = &db.read_all().store;
+            if step == steps.len() - 1 {
+                // the version file was as good as switched
+                assert_eq!(db.version(), 1);
+                assert_eq!(store.keys().collect::<Vec<_>>(), ["orphan"]);
+                continue;
+            }
+            assert_eq!(db.version(), 0);
+            assert_eq!(store.keys().collect::<Vec<_>>(), ["key"]);
+            for (filename, _) in files {
+                assert!(!path.join(filename).exists(), "{filename} at step {step}");
+            }
+            db.create_diff_checkpoint().unwrap();
+            db.create_diff_checkpoint().unwrap();
+            drop(db);
+            let db = Database::builder(JsonFormat::<KeyValueStore>::new())
+                .diff_checkpoints()
+                .open(&path)
+                .unwrap();
+            assert_eq!(db.version(), 2);
+            assert_eq!(db.read_all().store.keys().collect::<Vec<_>>(), ["key"]);
+        }
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_read_mapped() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("file");
+        std::fs::write(&path, "content").unwrap();
+        assert_eq!(&**FsBackend.read_mapped(&path).unwrap(), b"content");
+        std::fs::write(&path, "").unwrap();
+        assert!(FsBackend.read_mapped(&path).unwrap().is_empty());
+
+        // checkpoints are still checked against their hash
+        let path = tempdir.path().join("kv-store");
+        let mut db = Database::open(&path, JsonFormat::<KeyValueStore>::new()).unwrap();
+        db.update(&KeyValueStoreUpdateParams::Insert(
+            "key".to_string(),
+            "value".to_string(),
+        ))
+        .unwrap();
+        db.create_checkpoint().unwrap();
+        drop(db);
+        let db = Database::open(&path, JsonFormat::<KeyValueStore>::new()).unwrap();
+        assert_eq!(db.read_all().store["key"], "value");
+        drop(db);
+        std::fs::write(path.join("checkpoint.1"), r#"{"store":{}}"#).unwrap();
+        let err = Database::open(&path, JsonFormat::<KeyValueStore>::new())
+            .err()
+            .unwrap();
+        assert!(matches!(err, BjwError::Corrupt(_)));
+    }
+
+    #[test]
+    fn test_streamed_checkpoint() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("kv-store");
+        let mut db = Database::open(&path, JsonFormat::<KeyValueStore>::new()).unwrap();
+        let params: Vec<_> = (0..100_000)
+            .map(|i| KeyValueStoreUpdateParams::Insert(format!("key {i}"), format!("value {i}")))
+            .collect();
+        db.update_batch(&params).unwrap();
+        db.create_checkpoint().unwrap();
+
+        let checkpoint = std::fs::read(path.join("checkpoint.1")).unwrap();
+        let fmt = JsonFormat::<KeyValueStore>::new();
+        let content = checkpoint.strip_prefix(b"bjw:json:1\n".as_slice()).unwrap();
+        assert_eq!(content, fmt.serialize_data(db.read_all()).unwrap());
+        assert_eq!(
+            std::fs::read(path.join("checkpoint.1.sha256")).unwrap(),
+            sha256_hex(&checkpoint)
+        );
+        let data = db.clone_data();
+        drop(db);
+        let db = Database::open(&path, JsonFormat::<KeyValueStore>::new()).unwrap();
+        assert_eq!(db.read_all(), &data);
+        assert_eq!(data.store.len(), 100_000);
+    }
+
+    #[test]
+    fn test_verify() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("kv-store");
+        let mut db = Database::open(&path, JsonFormat::<KeyValueStore>::new()).unwrap();
+        assert!(db.verify().unwrap().is_ok());
+        for i in 0..3 {
+            let params = KeyValueStoreUpdateParams::Insert(format!("key {i}"), "value".to_string());
+            db.update(&params).unwrap();
+        }
+        let report = db.verify().unwrap();
+        assert!(report.is_ok());
+        assert_eq!(report.log_entries, 3);
+
+        let mut log = std::fs::OpenOptions::new()
+            .append(true)
+            .open(path.join("logfile.0"))
+            .unwrap();
+        log.write_all(b"{\"Insert\":[\"torn").unwrap();
+        let report = db.verify().unwrap();
+        assert!(!report.is_ok());
+        assert!(report.log_truncated);
+        assert_eq!(report.log_entries, 3);
+        assert!(report.checkpoint.is_none());
+
+        std::fs::write(path.join("checkpoint.0"), "{").unwrap();
+        std::fs::write(path.join("version"), "1").unwrap();
+        let report = db.verify().unwrap();
+        assert!(matches!(report.checkpoint, Some(BjwError::Corrupt(_))));
+        assert!(matches!(report.version_file, Some(BjwError::VersionParse(v)) if v == "1"));
+        assert!(report.log_truncated);
+    }
+
+    #[test]
+    fn test_log_segments() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("kv-store");
+        let open = || {
+            Database::builder(JsonFormat::<KeyValueStore>::new())
+                .log_segment_bytes(100)
+                .open_with_report(&path)
+                .unwrap()
+        };
+        let segments = || {
+            std::fs::read_dir(&path)
+                .unwrap()
+                .filter(|entry| {
+                    let filename = entry.as_ref().unwrap().file_name();
+                    filename.to_string_lossy().starts_with("logfile.0")
+                })
+                .count()
+        };
+        let insert = |db: &mut Database<KeyValueStore, JsonFormat<KeyValueStore>>, i: u32| {
+            db.update(&KeyValueStoreUpdateParams::Insert(
+                i.to_string(),
+                "value".to_string(),
+            ))
+            .unwrap();
+        };
+
+        let (mut db, _) = open();
+        for i in 0..10 {
+            insert(&mut db, i);
+        }
+        assert!(segments() > 2);
+        assert!(path.join("logfile.0.1").exists());
+        assert!(std::fs::metadata(path.join("logfile.0")).unwrap().len() < 150);
+        let data = db.clone_data();
+        drop(db);
+
+        // the segments are replayed in order, also without the option
+        let db = KeyValueStoreDb::open(&path).unwrap();
+        assert_eq!(db.clone_data(), data);
+        assert_eq!(db.stats().unwrap().updates_since_checkpoint, 10);
+        drop(db);
+
+        // a torn entry only affects its own segment, and appending continues in a new one
+        let last = segments() - 1;
+        let mut log = std::fs::OpenOptions::new()
+            .append(true)
+            .open(path.join(format!("logfile.0.{last}")))
+            .unwrap();
+        log.write_all(b"{\"Insert\":[\"torn").unwrap();
+        drop(log);
+        let (mut db, report) = open();
+        assert!(report.truncated);
+        assert_eq!(db.read_all(), &data);
+        insert(&mut db, 10);
+        assert_eq!(segments(), last + 2);
+        let data = db.clone_data();
+        drop(db);
+        let (mut db, report) = open();
+        assert!(report.truncated);
+        assert_eq!(db.read_all(), &data);
+
+        // all segments are removed together with the old version
+        db.create_checkpoint().unwrap();
+        assert_eq!(segments(), 0);
+        assert_eq!(db.read_all(), &data);
+    }
+
+    #[cfg(feature = "bincode")]
+    #[test]
+    fn test_format_mismatch() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("kv-store");
+        let mut db = KeyValueStoreDb::open(&path).unwrap();
+        db.insert("key".to_string(), "value".to_string()).unwrap();
+        db.create_checkpoint().unwrap();
+        drop(db);
+
+        let err = Database::open(&path, BincodeFormat::<KeyValueStore>::new())
+            .err()
+            .unwrap();
+        let msg = err.to_string();
+        assert!(
+            msg.contains("\"json\"") && msg.contains("\"bincode\""),
+            "{msg}"
+        );
+
+        // checkpoints written before headers were introduced are still read
+        let checkpoint = std::fs::read(path.join("checkpoint.1")).unwrap();
+        let content = checkpoint.strip_prefix(b"bjw:json:1\n".as_slice()).unwrap();
+        std::fs::write(path.join("checkpoint.1"), content).unwrap();
+        std::fs::write(path.join("checkpoint.1.sha256"), sha256_hex(content)).unwrap();
+        let db = KeyValueStoreDb::open(&path).unwrap();
+        assert_eq!(db.get("key"), Some("value".to_string()));
+    }
+
+    #[test]
+    fn test_open_autodetect() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("kv-store");
+        let mut db = KeyValueStoreDb::open(&path).unwrap();
+        db.insert("key".to_string(), "value".to_string()).unwrap();
+        drop(db);
+
+        let mut db = Database::<KeyValueStore, AnyFormat<_>>::open_autodetect(&path).unwrap();
+        assert!(matches!(db.format(), AnyFormat::Json(_)));
+        assert_eq!(db.read_all().store.get("key").unwrap(), "value");
+        db.create_checkpoint().unwrap();
+        drop(db);
+        assert!(KeyValueStoreDb::open(&path).is_ok());
+
+        assert!(Database::<KeyValueStore, AnyFormat<_>>::open_autodetect(
+            tempdir.path().join("missing")
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_version_ahead_of_checkpoints() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("kv-store");
+        let mut db = KeyValueStoreDb::open(&path).unwrap();
+        db.create_checkpoint().unwrap();
+        drop(db);
+
+        std::fs::write(path.join("version"), "5").unwrap();
+        let Err(BjwError::Corrupt(msg)) = KeyValueStoreDb::open(&path) else {
+            panic!("a missing checkpoint must be reported as corrupt");
+        };
+        assert!(msg.contains("checkpoint.5"), "{msg}");
+        assert!(msg.contains("checkpoint.1"), "{msg}");
+    }
+
+    #[test]
+    fn test_repair() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("kv-store");
+        let insert = |db: &mut Database<KeyValueStore, JsonFormat<KeyValueStore>>, key: &str| {
+            let params = KeyValueStoreUpdateParams::Insert(key.to_string(), "value".to_string());
+            db.update(&params).unwrap();
+        };
+        let open = || {
+            Database::builder(JsonFormat::<KeyValueStore>::new())
+                .fallback_to_previous_checkpoint()
+                .open(&path)
+                .unwrap()
+        };
+
+        // a torn log, with an update appended after its corrupt end
+        let mut db = open();
+        insert(&mut db, "a");
+        assert!(db.repair().unwrap().is_ok());
+        assert_eq!(db.version(), 0);
+        drop(db);
+        let mut log = std::fs::OpenOptions::new()
+            .append(true)
+            .open(path.join("logfile.0"))
+            .unwrap();
+        log.write_all(b"{\"Insert\":[\"torn").unwrap();
+        let mut db = open();
+        insert(&mut db, "b");
+        let report = db.repair().unwrap();
+        assert!(report.log_truncated);
+        assert_eq!(db.version(), 1);
+        drop(db);
+        let (db, report) =
+            Database::open_with_report(&path, JsonFormat::<KeyValueStore>::new()).unwrap();
+        assert!(!report.truncated);
+        assert_eq!(db.read_all().store.len(), 2);
+        drop(db);
+
+        // a corrupt checkpoint
+        let mut db = open();
+        insert(&mut db, "c");
+        db.create_checkpoint().unwrap();
+        insert(&mut db, "d");
+        drop(db);
+        std::fs::write(path.join("checkpoint.2"), "{").unwrap();
+        let mut db = open();
+        let report = db.repair().unwrap();
+        assert!(matches!(report.checkpoint, Some(BjwError::Corrupt(_))));
+        assert_eq!(db.version(), 3);
+        drop(db);
+        let db = Database::open(&path, JsonFormat::<KeyValueStore>::new()).unwrap();
+        assert!(db.verify().unwrap().is_ok());
+        assert_eq!(db.read_all().store.len(), 4);
+    }
+
+    #[test]
+    fn test_manual_cleanup() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("kv-store");
+        let open = || {
+            Database::builder(JsonFormat::<KeyValueStore>::new())
+                .auto_cleanup(false)
+                .open(&path)
+                .unwrap()
+        };
+        let mut db = open();
+        for key in ["a", "b", "c"] {
+            let params = KeyValueStoreUpdateParams::Insert(key.to_string(), "value".to_string());
+            db.update(&params).unwrap();
+            db.create_checkpoint().unwrap();
+        }
+        drop(db);
+        // neither checkpoints nor opening removes anything
+        let mut db = open();
+        for version in 0..=3 {
+            assert!(path.join(format!("checkpoint.{version}")).exists());
+            assert!(path.join(format!("logfile.{version}")).exists());
+        }
+        assert_eq!(db.view_at(1).unwrap().store.len(), 2);
+
+        db.prune(1).unwrap();
+        assert!(!path.join("checkpoint.1").exists());
+        assert!(!path.join("logfile.1").exists());
+        assert!(!path.join("checkpoint.1.sha256").exists());
+        assert!(path.join("checkpoint.2").exists());
+        assert_eq!(db.read_all().store.len(), 3);
+    }
+
+    #[test]
+    fn test_serialize_params_into() {
+        let fmt = JsonFormat::<KeyValueStore>::new();
+        let params = KeyValueStoreUpdateParams::Insert("key".to_string(), "value".to_string());
+        let mut buf = b"prefix".to_vec();
+        fmt.serialize_params_into(&params, &mut buf).unwrap();
+        let ser = fmt.serialize_params(&params).unwrap();
+        assert_eq!(buf, [b"prefix".as_slice(), &ser].concat());
+
+        // updates through the reused buffer are replayed like before
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("kv-store");
+        let mut db = KeyValueStoreDb::open(&path).unwrap();
+        db.insert("key".to_string(), "a longer value".to_string())
+            .unwrap();
+        db.insert("more".to_string(), "v".to_string()).unwrap();
+        let data = db.clone_data();
+        drop(db);
+        assert_eq!(KeyValueStoreDb::open(&path).unwrap().clone_data(), data);
+    }
+
+    #[cfg(feature = "parallel")]
+    impl MapData for KeyValueStore {
+        type Key = String;
+        type Value = String;
+
+        fn entries(&self) -> &BTreeMap<String, String> {
+            &self.store
+        }
+
+        fn from_entries(store: BTreeMap<String, String>) -> Self {
+            KeyValueStore { store }
+        }
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_parallel_checkpoints() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("kv-store");
+        let open = || {
+            Database::builder(JsonFormat::<KeyValueStore>::new())
+                .parallel_checkpoints(4)
+                .open(&path)
+        };
+        let mut db = open().unwrap();
+        for i in 0..10 {
+            db.update(&KeyValueStoreUpdateParams::Insert(
+                i.to_string(),
+                "value".to_string(),
+            ))
+            .unwrap();
+        }
+        db.create_checkpoint().unwrap();
+        let checkpoint = std::fs::read(path.join("checkpoint.1")).unwrap();
+        assert!(checkpoint.starts_with(b"bjw:json:1\nbjw-shards\n"));
+        let data = db.clone_data();
+        drop(db);
+
+        assert_eq!(open().unwrap().read_all(), &data);
+        let err = Database::open(&path, JsonFormat::<KeyValueStore>::new())
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("parallel_checkpoints"));
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_compress_old_logs() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("kv-store");
+        let mut db = Database::builder(JsonFormat::<KeyValueStore>::new())
+            .keep_versions(2)
+            .compress_old_logs()
+            .open(&path)
+            .unwrap();
+        for key in ["a", "b", "c"] {
+            let params = KeyValueStoreUpdateParams::Insert(key.to_string(), "value".to_string());
+            db.update(&params).unwrap();
+            db.update(&params).unwrap();
+            db.create_checkpoint().unwrap();
+        }
+        assert!(!path.join("logfile.0.zst").exists());
+        for version in 1..=2 {
+            assert!(path.join(format!("logfile.{version}.zst")).exists());
+            assert!(!path.join(format!("logfile.{version}")).exists());
+        }
+        assert!(path.join("logfile.3").exists());
+        assert_eq!(db.view_at(1).unwrap().store.len(), 2);
+        assert_eq!(db.view_at(2).unwrap().store.len(), 3);
+    }
+
+    #[test]
+    fn test_keep_versions() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("kv-store");
+        let mut db = Database::builder(JsonFormat::<KeyValueStore>::new())
+            .keep_versions(2)
+            .open(&path)
+            .unwrap();
+        for i in 0..5 {
+            let params = KeyValueStoreUpdateParams::Insert(format!("key {i}"), "value".to_string());
+            db.update(&params).unwrap();
+            db.create_checkpoint().unwrap();
+        }
+
+        let mut files: Vec<_> = std::fs::read_dir(&path)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name != ".lock" && name != "version")
+            .collect();
+        files.sort();
+        let mut expected = Vec::new();
+        for version in 3..=5 {
+            expected.push(format!("checkpoint.{version}"));
+            expected.push(format!("checkpoint.{version}.sha256"));
+            expected.push(format!("logfile.{version}"));
+        }
+        expected.sort();
+        assert_eq!(files, expected);
+        assert_eq!(db.view_at(3).unwrap().store.len(), 4);
+    }
+
+    #[test]
+    fn test_compact() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("kv-store");
+        let mut db = Database::open(&path, JsonFormat::<KeyValueStore>::new()).unwrap();
+        for i in 0..3 {
+            let params = KeyValueStoreUpdateParams::Insert(format!("key {i}"), "value".to_string());
+            db.update(&params).unwrap();
+        }
+        db.compact().unwrap();
+        let stats = db.stats().unwrap();
+        assert_eq!(stats.version, 0);
+        assert_eq!(stats.log_file_bytes, 0);
+        let data = db.clone_data();
+        drop(db);
+
+        let (mut db, report) =
+            Database::open_with_report(&path, JsonFormat::<KeyValueStore>::new()).unwrap();
+        assert_eq!(report.entries_applied, 0);
+        assert_eq!(db.read_all(), &data);
+        assert_eq!(db.stats().unwrap().version, 0);
+
+        // interrupt a compaction right after the old log was moved aside
+        let params = KeyValueStoreUpdateParams::Insert("key 3".to_string(), "value".to_string());
+        db.update(&params).unwrap();
+        let data = db.clone_data();
+        let ser = db.fmt.serialize_data(&data).unwrap();
+        std::fs::write(path.join("checkpoint.0.tmp"), ser).unwrap();
+        std::fs::rename(path.join("logfile.0"), path.join("logfile.0.compact")).unwrap();
+        drop(db);
+        assert!(Database::open_read_only(&path, JsonFormat::<KeyValueStore>::new()).is_err());
+
+        let (db, report) =
+            Database::open_with_report(&path, JsonFormat::<KeyValueStore>::new()).unwrap();
+        assert_eq!(report.entries_applied, 0);
+        assert_eq!(db.read_all(), &data);
+        assert!(!path.join("logfile.0.compact").exists());
+        assert_eq!(std::fs::read(path.join("logfile.0")).unwrap(), b"");
+    }
+
+    #[test]
+    #[cfg(feature = "backup")]
+    fn test_backup() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("kv-store");
+        let mut db = Database::open(&path, JsonFormat::<KeyValueStore>::new()).unwrap();
+        for i in 0..3 {
+            let params = KeyValueStoreUpdateParams::Insert(format!("key {i}"), "value".to_string());
+            db.update(&params).unwrap();
+            db.create_checkpoint().unwrap();
+        }
+        let params =
+            KeyValueStoreUpdateParams::Insert("in the log".to_string(), "value".to_string());
+        db.update(&params).unwrap();
+        let mut archive = Vec::new();
+        db.backup_to(&mut archive).unwrap();
+        let data = db.clone_data();
+        drop(db);
+
+        std::fs::remove_dir_all(&path).unwrap();
+        tar::Archive::new(archive.as_slice()).unpack(&path).unwrap();
+        let db = Database::open(&path, JsonFormat::<KeyValueStore>::new()).unwrap();
+        assert_eq!(db.read_all(), &data);
+        assert_eq!(db.stats().unwrap().version, 3);
+    }
+
+    #[test]
+    #[cfg(feature = "backup")]
+    fn test_backup_of_buffered_updates() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let mut db = Database::builder(JsonFormat::<KeyValueStore>::new())
+            .sync_policy(SyncPolicy::Never)
+            .open(tempdir.path().join("kv-store"))
+            .unwrap();
+        for i in 0..3 {
+            let params = KeyValueStoreUpdateParams::Insert(format!("key {i}"), "value".to_string());
+            db.update(&params).unwrap();
+        }
+        let mut archive = Vec::new();
+        db.backup_to(&mut archive).unwrap();
+        let data = db.clone_data();
+
+        let restored = tempdir.path().join("restored");
+        let db = Database::restore_from(
+            archive.as_slice(),
+            &restored,
+            JsonFormat::<KeyValueStore>::new(),
+            false,
+        )
+        .unwrap();
+        assert_eq!(db.read_all(), &data);
+    }
+
+    #[test]
+    fn test_export() {
+        let mut db = Database::open_in_memory(JsonFormat::<KeyValueStore>::new()).unwrap();
+        for i in 0..3 {
+            let params = KeyValueStoreUpdateParams::Insert(format!("key {i}"), "value".to_string());
+            db.update(&params).unwrap();
+        }
+        let mut exported = Vec::new();
+        db.export(&mut exported).unwrap();
+        let data = JsonFormat::<KeyValueStore>::new()
+            .deserialize_data(&exported)
+            .unwrap();
+        assert_eq!(data, db.clone_data());
+        assert_eq!(db.stats().unwrap().version, 0);
+    }
+
+    #[test]
+    fn test_import() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("kv-store");
+        let mut db = Database::open(&path, JsonFormat::<KeyValueStore>::new()).unwrap();
+        let params = KeyValueStoreUpdateParams::Insert("old".to_string(), "value".to_string());
+        db.update(&params).unwrap();
+
+        let mut source = Database::open_in_memory(JsonFormat::<KeyValueStore>::new()).unwrap();
+        let params = KeyValueStoreUpdateParams::Insert("new".to_string(), "value".to_string());
+        source.update(&params).unwrap();
+        let mut exported = Vec::new();
+        source.export(&mut exported).unwrap();
+
+        let err = db.import(&b"garbage"[..]).err().unwrap();
+        assert!(matches!(err, BjwError::Deserialize(_)));
+        assert!(db.read_all().store.contains_key("old"));
+        db.import(exported.as_slice()).unwrap();
+        assert_eq!(db.stats().unwrap().version, 1);
+        drop(db);
+
+        let db = Database::open(&path, JsonFormat::<KeyValueStore>::new()).unwrap();
+        assert_eq!(db.read_all(), source.read_all());
+    }
+
+    #[test]
+    fn test_naming() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("shared");
+        let naming = |name: &str| Naming {
+            checkpoint_prefix: format!("{name}-checkpoint"),
+            log_prefix: format!("{name}-log"),
+            version_file: format!("{name}-version"),
+            lock_file: format!(".{name}-lock"),
+            delim: '_',
+        };
+        let open = |name: &str| {
+            Database::builder(JsonFormat::<KeyValueStore>::new())
+                .naming(naming(name))
+                .open(&path)
+                .unwrap()
+        };
+        let mut first = open("first");
+        let mut second = open("second");
+        for i in 0..3 {
+            let params = KeyValueStoreUpdateParams::Insert(format!("key {i}"), "first".to_string());
+            first.update(&params).unwrap();
+            first.create_checkpoint().unwrap();
+        }
+        let params = KeyValueStoreUpdateParams::Insert("key".to_string(), "second".to_string());
+        second.update(&params).unwrap();
+        second.create_checkpoint().unwrap();
+        drop(first);
+        drop(second);
+
+        let first = open("first");
+        let second = open("second");
+        assert_eq!(first.read_all().store.len(), 3);
+        assert_eq!(second.read_all().store.len(), 1);
+        assert_eq!(second.stats().unwrap().version, 1);
+        let mut files: Vec<_> = std::fs::read_dir(&path)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        assert_eq!(
+            files,
+            [
+                ".first-lock",
+                ".second-lock",
+                "first-checkpoint_3",
+                "first-checkpoint_3_sha256",
+                "first-log_3",
+                "first-version",
+                "second-checkpoint_1",
+                "second-checkpoint_1_sha256",
+                "second-log_1",
+                "second-version",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_large_log() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("kv-store");
+        let db = Database::open(&path, JsonFormat::<KeyValueStore>::new()).unwrap();
+        drop(db);
+
+        // write the log directly, which is much faster than going through `update`
+        let fmt = JsonFormat::<KeyValueStore>::new();
+        let mut log =
+            std::io::BufWriter::new(std::fs::File::create(path.join("logfile.0")).unwrap());
+        let n = 200_000;
+        for i in 0..n {
+            let params =
+                KeyValueStoreUpdateParams::Insert(format!("key {}", i % 1000), i.to_string());
+            log.write_all(&fmt.serialize_params(&params).unwrap())
+                .unwrap();
+        }
+        let batch = [KeyValueStoreUpdateParams::Insert(
+            "batch".to_string(),
+            "value".to_string(),
+        )];
+        log.write_all(&fmt.serialize_batch(&batch).unwrap())
+            .unwrap();
+        // a torn write at the end
+        log.write_all(b"{\"Insert\":[\"torn").unwrap();
+        drop(log);
+
+        let (db, report) = Database::open_with_report(&path, fmt).unwrap();
+        assert_eq!(report.entries_applied, n + 1);
+        assert!(report.truncated);
+        assert_eq!(db.read_all().store.len(), 1001);
+        assert_eq!(db.read_all().store["key 999"], (n - 1).to_string());
+    }
+
+    #[test]
+    fn test_flush() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("kv-store");
+        let open = || {
+            Database::builder(JsonFormat::<KeyValueStore>::new())
+                .sync_policy(SyncPolicy::Never)
+                .open(&path)
+                .unwrap()
+        };
+        let log_len = || std::fs::metadata(path.join("logfile.0")).unwrap().len();
+        let mut db = open();
+        for i in 0..3 {
+            let params = KeyValueStoreUpdateParams::Insert(format!("key {i}"), "value".to_string());
+            db.update(&params).unwrap();
+        }
+        // the updates are still buffered
+        assert_eq!(log_len(), 0);
+        db.flush().unwrap();
+        let flushed = log_len();
+        assert!(flushed > 0);
+
+        let params = KeyValueStoreUpdateParams::Insert("on drop".to_string(), "value".to_string());
+        db.update(&params).unwrap();
+        assert_eq!(log_len(), flushed);
+        drop(db);
+        let db = open();
+        assert_eq!(db.read_all().store.len(), 4);
+    }
+
+    impl Diffable for KeyValueStore {
+        type Diff = (BTreeMap<String, String>, Vec<String>);
+
+        fn diff(&self, base: &Self) -> Self::Diff {
+            let changed = self
+                .store
+                .iter()
+                .filter(|(key, value)| base.store.get(*key) != Some(*value))
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect();
+            let removed = base
+                .store
+                .keys()
+                .filter(|key| !self.store.contains_key(*key))
+                .cloned()
+                .collect();
+            (changed, removed)
+        }
+
+        fn apply_diff(&mut self, (changed, removed): Self::Diff) {
+            self.store.extend(changed);
+            for key in removed {
+                self.store.remove(&key);
+            }
+        }
+    }
+
+    #[test]
+    fn test_diff_checkpoints() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("kv-store");
+        let open = || {
+            Database::builder(JsonFormat::<KeyValueStore>::new())
+                .diff_checkpoints()
+                .open(&path)
+                .unwrap()
+        };
+        let mut db = open();
+        for i in 0..100 {
+            let params = KeyValueStoreUpdateParams::Insert(format!("key {i}"), "value".to_string());
+            db.update(&params).unwrap();
+        }
+        // there is no base yet, so this one is full
+        db.create_diff_checkpoint().unwrap();
+        for i in 0..3 {
+            let params =
+                KeyValueStoreUpdateParams::Insert(format!("key {i}"), "changed".to_string());
+            db.update(&params).unwrap();
+            db.create_diff_checkpoint().unwrap();
+        }
+        let diff_len = std::fs::metadata(path.join("checkpoint.4.diff"))
+            .unwrap()
+            .len();
+        assert!(diff_len < std::fs::metadata(path.join("checkpoint.1")).unwrap().len() / 10);
+        assert!(!path.join("checkpoint.3.diff").exists());
+        let data = db.clone_data();
+        drop(db);
+
+        let err = Database::open(&path, JsonFormat::<KeyValueStore>::new())
+            .err()
+            .unwrap();
+        assert!(matches!(err, BjwError::Io(e) if e.kind() == ErrorKind::Unsupported));
+        let mut db = open();
+        assert_eq!(db.read_all(), &data);
+        // the base is still known after reopening
+        let params = KeyValueStoreUpdateParams::Insert("new".to_string(), "value".to_string());
+        db.update(&params).unwrap();
+        db.create_diff_checkpoint().unwrap();
+        assert!(path.join("checkpoint.5.diff").exists());
+        let data = db.clone_data();
+        drop(db);
+        assert_eq!(open().read_all(), &data);
+    }
+
+    #[test]
+    fn test_pretty_json() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("kv-store");
+        let mut db = Database::open(&path, JsonFormat::<KeyValueStore>::pretty()).unwrap();
+        for i in 0..3 {
+            let params = KeyValueStoreUpdateParams::Insert(format!("key {i}"), "value".to_string());
+            db.update(&params).unwrap();
+        }
+        db.create_checkpoint().unwrap();
+        let params = KeyValueStoreUpdateParams::Insert("another".to_string(), "pair".to_string());
+        db.update(&params).unwrap();
+
+        let checkpoint = std::fs::read_to_string(path.join("checkpoint.1")).unwrap();
+        assert!(checkpoint.lines().count() > 3);
+        let log = std::fs::read_to_string(path.join("logfile.1")).unwrap();
+        assert_eq!(log.lines().count(), 1);
+
+        let data = db.clone_data();
+        drop(db);
+        let db = Database::open(&path, JsonFormat::<KeyValueStore>::pretty()).unwrap();
+        assert_eq!(data, db.clone_data());
+    }
+
+    #[test]
+    #[cfg(feature = "zstd")]
+    fn test_compressed() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("kv-store");
+        let fmt = Compressed::with_level(JsonFormat::<KeyValueStore>::new(), 9);
+        let mut db = Database::open(&path, fmt).unwrap();
+        for i in 0..100 {
+            let params = KeyValueStoreUpdateParams::Insert(format!("key {i}"), "value".to_string());
+            db.update(&params).unwrap();
+        }
+        db.create_checkpoint().unwrap();
+        let params = KeyValueStoreUpdateParams::Insert("another".to_string(), "pair".to_string());
+        db.update(&params).unwrap();
+
+        // the checkpoint is noticeably smaller than the plain json
+        let plain = JsonFormat::<KeyValueStore>::new()
+            .serialize_data(db.read_all())
+            .unwrap();
+        let compressed = std::fs::metadata(path.join("checkpoint.1")).unwrap().len();
+        assert!(compressed < plain.len() as u64 / 2);
+
+        let data = db.clone_data();
+        drop(db);
+        let db = Database::open(&path, Compressed::<JsonFormat<KeyValueStore>>::new()).unwrap();
+        assert_eq!(data, db.clone_data());
+
+        // a truncated frame in the log is skipped
+        let mut log = std::fs::read(path.join("logfile.1")).unwrap();
+        log.extend_from_slice(&[100, 0, 0, 0, 1, 2, 3]);
+        std::fs::write(path.join("logfile.1"), log).unwrap();
+        drop(db);
+        let db = Database::open(&path, Compressed::<JsonFormat<KeyValueStore>>::new()).unwrap();
+        assert_eq!(data, db.clone_data());
+    }
+
+    #[test]
+    #[cfg(feature = "encryption")]
+    fn test_encrypted() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("kv-store");
+        let key = [7; 32];
+        let fmt = || Encrypted::new(JsonFormat::<KeyValueStore>::new(), &key);
+        let mut db = Database::open(&path, fmt()).unwrap();
+        let params = KeyValueStoreUpdateParams::Insert("secret".to_string(), "value".to_string());
+        db.update(&params).unwrap();
+        db.create_checkpoint().unwrap();
+        for i in 0..3 {
+            let params = KeyValueStoreUpdateParams::Insert(format!("key {i}"), "value".to_string());
+            db.update(&params).unwrap();
+        }
+        let data = db.clone_data();
+
+        // nothing is stored in plain text
+        let checkpoint = std::fs::read(path.join("checkpoint.1")).unwrap();
+        assert!(!checkpoint.windows(6).any(|w| w == b"secret"));
+        drop(db);
+        let db = Database::open(&path, fmt()).unwrap();
+        assert_eq!(data, db.clone_data());
+        drop(db);
+
+        // a wrong key is rejected
+        let wrong_key = Encrypted::new(JsonFormat::<KeyValueStore>::new(), &[8; 32]);
+        let err = Database::open(&path, wrong_key).err().unwrap();
+        assert!(matches!(err, BjwError::Corrupt(_)));
+        // as is a missing one
+        let keyless = <Encrypted<JsonFormat<KeyValueStore>> as DataFormat>::new();
+        let err = Database::open(&path, keyless).err().unwrap();
+        assert!(matches!(err, BjwError::Io(e) if e.kind() == ErrorKind::InvalidInput));
+
+        // a tampered log entry stops the replay right there
+        let log_path = path.join("logfile.1");
+        let mut log = std::fs::read(&log_path).unwrap();
+        let last = log.len() - 1;
+        log[last] ^= 1;
+        std::fs::write(&log_path, log).unwrap();
+        let db = Database::open(&path, fmt()).unwrap();
+        assert_eq!(db.read_all().store.len(), 3);
+        assert_eq!(db.read_all().store.get("key 2"), None);
+        drop(db);
+
+        // a tampered checkpoint is rejected
+        let mut tampered = checkpoint.clone();
+        tampered[NONCE_SIZE] ^= 1;
+        std::fs::write(path.join("checkpoint.1"), tampered).unwrap();
+        let err = Database::open(&path, fmt()).err().unwrap();
+        assert!(matches!(err, BjwError::Corrupt(_)));
+    }
+
+    #[test]
+    #[cfg(feature = "checksum")]
+    fn test_checksummed() {
+        let tempdir = TempDir::with_prefix("bjw-").unwrap();
+        let path = tempdir.path().join("kv-store");
+        let fmt = || Checksummed::new(JsonFormat::<KeyValueStore>::new());
+        let mut db = Database::open(&path, fmt()).unwrap();
+        for i in 0..5 {
+            let params = KeyValueStoreUpdateParams::Insert(format!("key {i}"), "value".to_string());
+            db.update(&params).unwrap();
+        }
+        let data = db.clone_data();
+        drop(db);
+        let db = Database::open(&path, fmt()).unwrap();
+        assert_eq!(data, db.clone_data());
+        drop(db);
+
+        // flipping a bit keeps the json valid ("key 2" becomes "key 3"), but the checksum catches it
+        let log_path = path.join("logfile.0");
+        let mut log = std::fs::read(&log_path).unwrap();
+        let pos = log.windows(5).position(|w| w == b"key 2").unwrap();
+        log[pos + 4] ^= 1;
+        std::fs::write(&log_path, log).unwrap();
+        let db = Database::open(&path, fmt()).unwrap();
+        assert_eq!(db.read_all().store.len(), 2);
+        assert_eq!(db.read_all().store.get("key 1"), Some(&"value".to_string()));
+    }
+
+    mod auto_checkpoint {
+        use crate as bjw_db;
+
+        use serde::{Deserialize, Serialize};
+        use std::collections::BTreeMap;
+        use tempfile::TempDir;
+
+        #[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+        struct Counters {
+            counts: BTreeMap<String, u64>,
+        }
+
+        #[bjw_db_derive::derive_bjw_db(auto_checkpoint_bytes = 64, db_name = "CounterStore")]
+        impl Counters {
+            pub fn add(&mut self, name: String, n: u64) {
+                *self.counts.entry(name).or_default() += n;
+            }
+
+            pub fn get(&self, name: &str) -> u64 {
+                self.counts.get(name).copied().unwrap_or_default()
+            }
+        }
+
+        #[test]
+        fn test_derive_auto_checkpoint() {
+            let tempdir = TempDir::with_prefix("bjw-").unwrap();
+            let path = tempdir.path().join("counters");
+            let mut db = CounterStore::open(&path).unwrap();
+            for _ in 0..20 {
+                db.add("counter".to_string(), 1).unwrap();
+            }
+            assert_ne!(std::fs::read_to_string(path.join("version")).unwrap(), "0");
+            drop(db);
+            let db = CounterStore::open(&path).unwrap();
+            assert_eq!(db.get("counter"), 20);
+        }
+    }
+
+    mod json_options {
+        use crate as bjw_db;
+
+        use serde::{Deserialize, Serialize};
+        use std::collections::BTreeMap;
+        use tempfile::TempDir;
+
+        use crate::JsonOptions;
+
+        #[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+        struct Balances {
+            balances: BTreeMap<String, u64>,
+            raw: serde_json::Value,
+        }
+
+        #[bjw_db_derive::derive_bjw_db]
+        impl Balances {
+            pub fn set(&mut self, name: String, balance: u64) {
+                self.raw = balance.into();
+                self.balances.insert(name, balance);
+            }
+
+            pub fn get(&self, name: &str) -> u64 {
+                self.balances.get(name).copied().unwrap_or_default()
+            }
+        }
+
+        #[test]
+        fn test_large_integers_round_trip() {
+            let tempdir = TempDir::with_prefix("bjw-").unwrap();
+            let path = tempdir.path().join("balances");
+            let fmt = || {
+                JsonFormat::<Balances>::with_options(JsonOptions {
+                    pretty: true,
+                    ..Default::default()
+                })
+            };
+            let balance = (1 << 63) + 1;
+            let mut db = Database::open(&path, fmt()).unwrap();
+            assert!(db.format().options().pretty);
+            db.update(&BalancesUpdateParams::Set("max".to_string(), u64::MAX))
+                .unwrap();
+            db.update(&BalancesUpdateParams::Set("large".to_string(), balance))
+                .unwrap();
+            db.create_checkpoint().unwrap();
+            drop(db);
+
+            let checkpoint = std::fs::read_to_string(path.join("checkpoint.1")).unwrap();
+            assert!(checkpoint.contains(&balance.to_string()));
+            for fmt in [fmt(), JsonFormat::new()] {
+                let db = Database::open(&path, fmt).unwrap();
+                assert_eq!(db.read_all().balances["large"], balance);
+                assert_eq!(db.read_all().balances["max"], u64::MAX);
+                assert_eq!(db.read_all().raw.as_u64(), Some(balance));
+            }
+        }
+    }
+
+    mod public_enums {
+        mod store {
+            use crate as bjw_db;
+
+            use serde::{Deserialize, Serialize};
+
+            #[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+            pub struct KeyValueStore {
+                pub store: std::collections::BTreeMap<String, String>,
+            }
+
+            #[bjw_db_derive::derive_bjw_db]
+            impl KeyValueStore {
+                pub fn insert(&mut self, key: String, value: String) {
+                    self.store.insert(key, value);
+                }
+
+                pub fn get(&self, key: &str) -> Option<String> {
+                    self.store.get(key).cloned()
+                }
+            }
+        }
+
+        use store::{KeyValueStoreDb, KeyValueStoreReadParams, KeyValueStoreUpdateParams};
+
+        #[test]
+        fn test_enums_from_another_module() {
+            let mut db = KeyValueStoreDb::open_in_memory().unwrap();
+            let batch: Vec<_> = (0..3)
+                .map(|i| KeyValueStoreUpdateParams::Insert(format!("key {i}"), i.to_string()))
+                .collect();
+            db.update_batch(&batch).unwrap();
+            assert_eq!(db.get("key 2"), Some("2".to_string()));
+            let read = KeyValueStoreReadParams::Get("key 1");
+            assert!(matches!(
+                db.with_data(|data| crate::Readable::read(data, &read)),
+                store::KeyValueStoreReadReturn::Get(Some(value)) if value == "1"
+            ));
+        }
+    }
+
+    #[cfg(feature = "backup")]
+    mod backup_api {
+        use crate as bjw_db;
+
+        use bjw_db::BjwError;
+        use serde::{Deserialize, Serialize};
+        use std::{collections::BTreeMap, io::ErrorKind};
+        use tempfile::TempDir;
+
+        #[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+        struct KeyValueStore {
+            store: BTreeMap<String, String>,
+        }
+
+        #[bjw_db_derive::derive_bjw_db(backup, thread_safe)]
+        impl KeyValueStore {
+            pub fn insert(&mut self, key: String, value: String) {
+                self.store.insert(key, value);
+            }
+
+            pub fn get(&self, key: &str) -> Option<String> {
+                self.store.get(key).cloned()
+            }
+        }
+
+        #[test]
+        fn test_restore() {
+            let tempdir = TempDir::with_prefix("bjw-").unwrap();
+            let path = tempdir.path().join("kv-store");
+            let db = KeyValueStoreDb::open(&path).unwrap();
+            db.insert("key".to_string(), "value".to_string()).unwrap();
+            db.create_checkpoint().unwrap();
+            db.insert("in the log".to_string(), "value".to_string())
+                .unwrap();
+            let mut archive = Vec::new();
+            db.backup_to(&mut archive).unwrap();
+            let data = db.clone_data();
+            drop(db);
+
+            let err = KeyValueStoreDb::restore_from(archive.as_slice(), &path, false)
+                .err()
+                .unwrap();
+            assert!(matches!(err, BjwError::Io(e) if e.kind() == ErrorKind::AlreadyExists));
+            assert!(KeyValueStoreDb::restore_from(&b"garbage"[..], &path, true).is_err());
+            let mut incomplete = tar::Builder::new(Vec::new());
+            let mut header = tar::Header::new_gnu();
+            header.set_size(1);
+            incomplete
+                .append_data(&mut header, "version", &b"1"[..])
+                .unwrap();
+            let incomplete = incomplete.into_inner().unwrap();
+            let err = KeyValueStoreDb::restore_from(incomplete.as_slice(), &path, true)
+                .err()
+                .unwrap();
+            assert!(matches!(err, BjwError::Corrupt(_)));
+            // nothing was touched by the failed attempts
+            assert_eq!(KeyValueStoreDb::open(&path).unwrap().clone_data(), data);
+
+            let db = KeyValueStoreDb::restore_from(archive.as_slice(), &path, true).unwrap();
+            assert_eq!(db.clone_data(), data);
+            drop(db);
+            let restored = tempdir.path().join("restored");
+            let db = KeyValueStoreDb::restore_from(archive.as_slice(), &restored, false).unwrap();
+            assert_eq!(db.get("in the log"), Some("value".to_string()));
+            assert_eq!(db.stats().unwrap().version, 1);
+        }
+    }
+
+    #[cfg(feature = "encryption")]
+    mod encrypted_derive {
+        use crate as bjw_db;
+
+        use bjw_db::{Encrypted, JsonFormat, Result};
+        use serde::{Deserialize, Serialize};
+        use tempfile::TempDir;
+
+        /// How the derive gets a key: the format creates `Encrypted` with one it loads itself.
+        struct KeyedFormat<T>(Encrypted<JsonFormat<T>>);
+
+        impl<T> DataFormat for KeyedFormat<T>
+        where
+            T: Serialize + serde::de::DeserializeOwned + Readable + Updateable,
+        {
+            type Data = T;
+            const MAGIC: &'static [u8] = b"keyed";
+
+            fn new() -> Self {
+                // e.g. read from the environment or a secret store
+                KeyedFormat(Encrypted::new(JsonFormat::new(), &[3; 32]))
+            }
+
+            fn serialize_data(&self, data: &T) -> Result<Vec<u8>> {
+                self.0.serialize_data(data)
+            }
+
+            fn deserialize_data(&self, input: &[u8]) -> Result<T> {
+                self.0.deserialize_data(input)
+            }
+
+            fn serialize_params(&self, params: &<T as Updateable>::Args) -> Result<Vec<u8>> {
+                self.0.serialize_params(params)
+            }
+
+            fn deserialize_params(&self, input: &[u8]) -> Result<Vec<<T as Updateable>::Args>> {
+                self.0.deserialize_params(input)
+            }
+
+            fn deserialize_log(
+                &self,
+                input: &[u8],
+            ) -> Result<(Vec<<T as Updateable>::Args>, bool)> {
+                self.0.deserialize_log(input)
+            }
+
+            fn serialize_batch(&self, params: &[<T as Updateable>::Args]) -> Result<Vec<u8>> {
+                self.0.serialize_batch(params)
+            }
+        }
+
+        #[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+        struct Secrets {
+            secrets: Vec<String>,
+        }
+
+        #[bjw_db_derive::derive_bjw_db(fmt = "KeyedFormat")]
+        impl Secrets {
+            pub fn add(&mut self, secret: String) {
+                self.secrets.push(secret);
+            }
+
+            pub fn count(&self) -> usize {
+                self.secrets.len()
+            }
+        }
+
+        #[test]
+        fn test_derive_with_key() {
+            let tempdir = TempDir::with_prefix("bjw-").unwrap();
+            let path = tempdir.path().join("secrets");
+            let mut db = SecretsDb::open(&path).unwrap();
+            db.add("hunter2".to_string()).unwrap();
+            db.create_checkpoint().unwrap();
+            db.add("swordfish".to_string()).unwrap();
+            drop(db);
+
+            let log = std::fs::read(path.join("logfile.1")).unwrap();
+            assert!(!log.windows(9).any(|w| w == b"swordfish"));
+            assert_eq!(SecretsDb::open(&path).unwrap().count(), 2);
+        }
+    }
+
+    mod thread_safe {
+        use crate as bjw_db;
+
+        use serde::{Deserialize, Serialize};
+        use std::{
+            sync::Arc,
+            time::{Duration, Instant},
+        };
+        use tempfile::TempDir;
+
+        #[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+        struct History {
+            values: Vec<u64>,
+        }
+
+        #[bjw_db_derive::derive_bjw_db(thread_safe)]
+        impl History {
+            pub fn push(&mut self, value: u64) {
+                self.values.push(value);
+            }
+
+            pub fn contains(&self, value: &u64) -> bool {
+                self.values.contains(value)
+            }
+        }
+
+        #[test]
+        fn test_spawn_autocheckpoint() {
+            let tempdir = TempDir::with_prefix("bjw-").unwrap();
+            let db = Arc::new(HistoryDb::open(tempdir.path().join("history")).unwrap());
+            let handle = db.spawn_autocheckpoint(Duration::from_millis(10)).unwrap();
+            let checkpointed = |version| {
+                let deadline = Instant::now() + Duration::from_secs(10);
+                while db.version() < version {
+                    assert!(
+                        Instant::now() < deadline,
+                        "no checkpoint of version {version}"
+                    );
+                    std::thread::sleep(Duration::from_millis(5));
+                }
+            };
+            for value in 0..3 {
+                db.push(value).unwrap();
+                checkpointed(value + 1);
+            }
+            // without updates, there is nothing to checkpoint
+            std::thread::sleep(Duration::from_millis(50));
+            assert_eq!(db.version(), 3);
+
+            drop(handle);
+            db.push(3).unwrap();
+            std::thread::sleep(Duration::from_millis(50));
+            assert_eq!(db.version(), 3);
+            assert!(db.has_pending_updates().unwrap());
+            assert_eq!(Arc::strong_count(&db), 1);
+        }
+
+        #[test]
+        fn test_concurrent_updates() {
+            let tempdir = TempDir::with_prefix("bjw-").unwrap();
+            let path = tempdir.path().join("history");
+            let db = Arc::new(HistoryDb::open(&path).unwrap());
+            let threads: Vec<_> = (0..4)
+                .map(|thread| {
+                    let db = db.clone();
+                    std::thread::spawn(move || {
+                        for i in 0..50 {
+                            db.push(thread * 100 + i).unwrap();
+                            assert!(db.contains(&(thread * 100 + i)));
+                        }
+                    })
+                })
+                .collect();
+            for thread in threads {
+                thread.join().unwrap();
+            }
+            let history = db.clone_data();
+            assert_eq!(history.values.len(), 200);
+            drop(db);
+
+            // the log has the same order as the updates were applied in
+            let db = HistoryDb::open(&path).unwrap();
+            assert_eq!(db.clone_data(), history);
+        }
+
+        #[test]
+        fn test_snapshot() {
+            let tempdir = TempDir::with_prefix("bjw-").unwrap();
+            let db = Arc::new(HistoryDb::open(tempdir.path().join("history")).unwrap());
+            let writer = {
+                let db = db.clone();
+                std::thread::spawn(move || {
+                    for i in 0..50 {
+                        db.push(i).unwrap();
+                        db.create_checkpoint().unwrap();
+                    }
+                })
+            };
+            // every version holds one more value, and the log of a version at most one
+            while !writer.is_finished() {
+                let (version, history) = db.snapshot();
+                let len = history.values.len() as u64;
+                assert!(len == version || len == version + 1, "{version}: {len}");
+            }
+            writer.join().unwrap();
+            assert_eq!(db.snapshot().0, 50);
+        }
+
+        #[test]
+        fn test_snapshot_arc() {
+            let tempdir = TempDir::with_prefix("bjw-").unwrap();
+            let db = HistoryDb::open(tempdir.path().join("history")).unwrap();
+            db.push(1).unwrap();
+            let snapshot = db.snapshot_arc();
+            assert!(Arc::ptr_eq(&snapshot, &db.snapshot_arc()));
+
+            // holding the snapshot does not block writers, which replace it
+            db.push(2).unwrap();
+            assert_eq!(snapshot.values, [1]);
+            let updated = db.snapshot_arc();
+            assert_eq!(updated.values, [1, 2]);
+            db.create_checkpoint().unwrap();
+            assert!(!Arc::ptr_eq(&updated, &db.snapshot_arc()));
+            assert_eq!(*db.snapshot_arc(), db.clone_data());
+        }
+    }
+
+    mod generic {
+        use crate as bjw_db;
+
+        use serde::{Deserialize, Serialize};
+        use std::collections::BTreeMap;
+        use tempfile::TempDir;
+
+        #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+        struct Cache<K: Ord, V> {
+            entries: BTreeMap<K, V>,
+        }
+
+        impl<K: Ord, V> Default for Cache<K, V> {
+            fn default() -> Self {
+                Cache {
+                    entries: BTreeMap::new(),
+                }
+            }
+        }
+
+        #[bjw_db_derive::derive_bjw_db(thread_safe)]
+        impl<K: Ord + Clone, V: Clone> Cache<K, V> {
+            pub fn put(&mut self, key: K, value: V) -> Option<V> {
+                self.entries.insert(key, value)
+            }
+
+            pub fn get(&self, key: &K) -> Option<V> {
+                self.entries.get(key).cloned()
+            }
+
+            pub fn len(&self, _unit: &()) -> usize {
+                self.entries.len()
+            }
+
+            pub fn cleared(self) -> Self {
+                Cache::default()
+            }
+        }
+
+        #[test]
+        fn test_generic() {
+            let tempdir = TempDir::with_prefix("bjw-").unwrap();
+            let path = tempdir.path().join("cache");
+            let db = CacheDb::<u64, String>::open(&path).unwrap();
+            assert_eq!(db.put(1, "one".to_string()).unwrap(), None);
+            assert_eq!(
+                db.put(1, "uno".to_string()).unwrap(),
+                Some("one".to_string())
+            );
+            db.put(2, "two".to_string()).unwrap();
+            drop(db);
+
+            let db: CacheDb<u64, String> = CacheDb::open(&path).unwrap();
+            assert_eq!(db.get(&1), Some("uno".to_string()));
+            assert_eq!(db.len(&()), 2);
+            db.cleared().unwrap();
+            assert_eq!(db.len(&()), 0);
+
+            // another instantiation, with its own log format
+            let mut db =
+                Database::<Cache<String, u64>, _>::open_in_memory(JsonFormat::new()).unwrap();
+            db.update(&CacheUpdateParams::Put("one".to_string(), 1))
+                .unwrap();
+            assert_eq!(db.clone_data().entries["one"], 1);
+        }
+    }
+
+    mod borrowed {
+        use crate as bjw_db;
+
+        use serde::{Deserialize, Serialize};
+        use std::collections::BTreeMap;
+
+        #[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+        struct Names {
+            names: BTreeMap<u64, String>,
+        }
+
+        #[bjw_db_derive::derive_bjw_db]
+        impl Names {
+            pub fn set(&mut self, id: u64, name: String) {
+                self.names.insert(id, name);
+            }
+
+            pub fn contains(&self, id: &u64) -> bool {
+                self.names.contains_key(id)
+            }
+
+            pub fn name(&self, id: &u64) -> &str {
+                self.names.get(id).map_or("", String::as_str)
+            }
+        }
+
+        mod shared {
+            use crate as bjw_db;
+
+            use serde::{Deserialize, Serialize};
+
+            #[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+            pub(super) struct SharedNames {
+                names: Vec<String>,
+            }
+
+            #[bjw_db_derive::derive_bjw_db(thread_safe)]
+            impl SharedNames {
+                pub fn push(&mut self, name: String) {
+                    self.names.push(name);
+                }
+
+                pub fn len(&self, _unit: &()) -> usize {
+                    self.names.len()
+                }
+
+                pub fn all(&self) -> &[String] {
+                    &self.names
+                }
+            }
+        }
+        use shared::SharedNamesDb;
+
+        #[test]
+        fn test_borrowed_reads() {
+            let mut db = NamesDb::open_in_memory().unwrap();
+            db.set(1, "one".to_string()).unwrap();
+            assert!(db.contains(&1));
+            assert_eq!(db.name(&1, |name| name.len()), 3);
+            assert_eq!(db.name(&2, str::to_uppercase), "");
+
+            let db = SharedNamesDb::open_in_memory().unwrap();
+            db.push("a".to_string()).unwrap();
+            db.push("b".to_string()).unwrap();
+            assert_eq!(db.all(|names| names.join(",")), "a,b");
+            // the read lock is released once the closure returns
+            db.all(|names| assert_eq!(names.len(), 2));
+            db.push("c".to_string()).unwrap();
+            assert_eq!(db.len(&()), 3);
+        }
+    }
+
+    mod where_clause {
+        use crate as bjw_db;
+
+        use serde::{Deserialize, Serialize};
+
+        trait Label {
+            fn label(&self) -> String;
+        }
+
+        impl Label for u64 {
+            fn label(&self) -> String {
+                format!("#{self}")
+            }
+        }
+
+        #[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+        struct Tickets {
+            next: u64,
+        }
+
+        #[bjw_db_derive::derive_bjw_db]
+        impl Tickets
+        where
+            u64: Label,
+        {
+            pub fn issue(&mut self) -> String {
+                self.next += 1;
+                self.next.label()
+            }
+
+            pub fn peek(&self, _unit: &()) -> String {
+                (self.next + 1).label()
+            }
+        }
+
+        #[test]
+        fn test_where_clause() {
+            let mut db = TicketsDb::open_in_memory().unwrap();
+            assert_eq!(db.issue().unwrap(), "#1");
+            assert_eq!(db.peek(&()), "#2");
+        }
+    }
+
+    mod read_only {
+        use crate as bjw_db;
+
+        use serde::{Deserialize, Serialize};
+
+        #[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+        struct Settings {
+            retries: u32,
+        }
+
+        #[bjw_db_derive::derive_bjw_db]
+        impl Settings {
+            pub fn retries(&self, fallback: u32) -> u32 {
+                if self.retries == 0 {
+                    fallback
+                } else {
+                    self.retries
+                }
+            }
+        }
+
+        #[test]
+        fn test_read_only_store() {
+            let mut db = SettingsDb::open_in_memory().unwrap();
+            assert_eq!(db.retries(3), 3);
+            assert!(db.update_batch(&[]).unwrap().is_empty());
+            db.create_checkpoint().unwrap();
+            assert_eq!(db.retries(5), 5);
+        }
+
+        mod empty {
+            use crate as bjw_db;
+
+            use serde::{Deserialize, Serialize};
+
+            #[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+            struct Marker;
+
+            #[bjw_db_derive::derive_bjw_db]
+            impl Marker {}
+
+            #[test]
+            fn test_empty_impl() {
+                let mut db = MarkerDb::open_in_memory().unwrap();
+                assert!(db.update_batch(&[]).unwrap().is_empty());
+                assert_eq!(db.clone_data(), Marker);
+            }
+        }
+    }
+
+    mod write_only {
+        use crate as bjw_db;
+
+        use serde::{Deserialize, Serialize};
+        use tempfile::TempDir;
+
+        #[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+        struct AuditLog {
+            entries: Vec<String>,
+        }
+
+        #[bjw_db_derive::derive_bjw_db]
+        impl AuditLog {
+            pub fn record(&mut self, entry: String) {
+                self.entries.push(entry);
+            }
+        }
+
+        #[test]
+        fn test_write_only_store() {
+            let tmpdir = TempDir::new().unwrap();
+            let path = tmpdir.path().join("audit");
+            {
+                let mut db = AuditLogDb::open(&path).unwrap();
+                db.record("login".to_string()).unwrap();
+                db.record("logout".to_string()).unwrap();
+            }
+            let db = AuditLogDb::open(&path).unwrap();
+            assert_eq!(db.clone_data().entries, ["login", "logout"]);
+        }
+    }
+
+    mod dry_run {
+        use crate as bjw_db;
+
+        use serde::{Deserialize, Serialize};
+        use std::collections::HashMap;
+        use tempfile::TempDir;
+
+        #[derive(Debug, Default, Serialize, Deserialize, Clone)]
+        struct Registry {
+            names: HashMap<String, String>,
+        }
+
+        #[bjw_db_derive::derive_bjw_db(dry_run)]
+        impl Registry {
+            pub fn insert(&mut self, key: String, value: String) {
+                self.names.insert(key, value);
+            }
+
+            pub fn insert_with_check(&mut self, key: String, value: String) -> bool {
+                if self.names.contains_key(&key) {
+                    return false;
+                }
+                self.names.insert(key, value);
+                true
+            }
+
+            pub fn get(&self, key: &str) -> Option<String> {
+                self.names.get(key).cloned()
+            }
+        }
+
+        #[test]
+        fn test_dry_run() {
+            let tempdir = TempDir::with_prefix("bjw-").unwrap();
+            let path = tempdir.path().join("registry");
+            let mut db = RegistryDb::open(&path).unwrap();
+            db.insert("key".to_string(), "value".to_string()).unwrap();
+            let log = std::fs::read(path.join("logfile.0")).unwrap();
+
+            assert!(!db.insert_with_check_dry_run("key".to_string(), "other".to_string()));
+            assert!(db.insert_with_check_dry_run("new".to_string(), "value".to_string()));
+            db.insert_dry_run("key".to_string(), "other".to_string());
+            assert_eq!(db.get("key"), Some("value".to_string()));
+            assert_eq!(db.get("new"), None);
+            assert_eq!(std::fs::read(path.join("logfile.0")).unwrap(), log);
+        }
+
+        // Without `dry_run` no wrappers are generated, so update methods are free to use the
+        // `_dry_run` suffix themselves.
+        mod opt_in {
+            use crate as bjw_db;
+
+            use serde::{Deserialize, Serialize};
+            use tempfile::TempDir;
+
+            #[derive(Debug, Default, Serialize, Deserialize, Clone)]
+            struct Switch {
+                on: bool,
+                dry_run: bool,
+            }
+
+            #[bjw_db_derive::derive_bjw_db]
+            impl Switch {
+                pub fn set(&mut self, on: bool) {
+                    self.on = on;
+                }
+
+                pub fn set_dry_run(&mut self, dry_run: bool) {
+                    self.dry_run = dry_run;
+                }
+            }
+
+            #[test]
+            fn test_dry_run_is_opt_in() {
+                let tempdir = TempDir::with_prefix("bjw-").unwrap();
+                let mut db = SwitchDb::open(tempdir.path().join("switch")).unwrap();
+                db.set(true).unwrap();
+                db.set_dry_run(true).unwrap();
+                let data = db.clone_data();
+                assert!(data.on && data.dry_run);
+            }
+        }
+    }
+
+    mod no_args {
+        use crate as bjw_db;
+
+        use serde::{Deserialize, Serialize};
+        use tempfile::TempDir;
+
+        #[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+        struct Counter {
+            count: u64,
+        }
+
+        #[bjw_db_derive::derive_bjw_db]
+        impl Counter {
+            pub fn increment(&mut self) -> u64 {
+                self.count += 1;
+                self.count
+            }
+
+            pub fn reset(&mut self) {
+                self.count = 0;
+            }
+
+            pub fn count(&self) -> u64 {
+                self.count
+            }
+        }
+
+        #[test]
+        fn test_no_args() {
+            let fmt = JsonFormat::<Counter>::new();
+            let ser = fmt.serialize_params(&CounterUpdateParams::Reset).unwrap();
+            assert_eq!(String::from_utf8_lossy(&ser).trim(), "\"Reset\"");
+            assert!(matches!(
+                fmt.deserialize_params(&ser).unwrap()[..],
+                [CounterUpdateParams::Reset]
+            ));
+
+            let tmpdir = TempDir::new().unwrap();
+            let path = tmpdir.path().join("counter");
+            {
+                let mut db = CounterDb::open(&path).unwrap();
+                assert_eq!(db.increment().unwrap(), 1);
+                assert_eq!(db.increment().unwrap(), 2);
+                db.reset().unwrap();
+                assert_eq!(db.increment().unwrap(), 1);
+                assert_eq!(db.count(), 1);
+            }
+            let mut db = CounterDb::open(&path).unwrap();
+            assert_eq!(db.count(), 1);
+            assert_eq!(db.increment().unwrap(), 2);
+        }
+    }
+
+    mod projection {
+        use crate as bjw_db;
 
-        // create new db
-        let path = tempdir.path().join("kv-store");
-        let mut db = KeyValueStoreDb::open(&path).unwrap();
-        db.insert("key".to_string(), "value".to_string()).unwrap();
-        db.insert("more".to_string(), "value".to_string()).unwrap();
-        assert!(!db
-            .insert_with_check("key".to_string(), "".to_string())
-            .unwrap());
-        assert_eq!(db.get("key"), Some("value".to_string()));
+        use serde::{Deserialize, Serialize};
+        use std::{
+            collections::BTreeMap,
+            sync::atomic::{AtomicUsize, Ordering},
+        };
 
-        // create a checkpoint
-        db.create_checkpoint().unwrap();
-        db.insert("another".to_string(), "pair".to_string())
+        static CLONES: AtomicUsize = AtomicUsize::new(0);
+
+        #[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+        struct Scores {
+            scores: BTreeMap<String, u64>,
+        }
+
+        impl Clone for Scores {
+            fn clone(&self) -> Self {
+                CLONES.fetch_add(1, Ordering::Relaxed);
+                Scores {
+                    scores: self.scores.clone(),
+                }
+            }
+        }
+
+        #[bjw_db_derive::derive_bjw_db(thread_safe)]
+        impl Scores {
+            pub fn set(&mut self, name: String, score: u64) {
+                self.scores.insert(name, score);
+            }
+        }
+
+        #[test]
+        fn test_with_data() {
+            let db = ScoresDb::open_in_memory().unwrap();
+            db.set("a".to_string(), 3).unwrap();
+            db.set("b".to_string(), 5).unwrap();
+            let total: u64 = db.with_data(|data| data.scores.values().sum());
+            assert_eq!(total, 8);
+            assert_eq!(CLONES.load(Ordering::Relaxed), 0);
+            db.clone_data();
+            assert_eq!(CLONES.load(Ordering::Relaxed), 1);
+        }
+    }
+
+    mod serde_rename {
+        use crate as bjw_db;
+
+        use serde::{Deserialize, Serialize};
+        use std::collections::BTreeMap;
+        use tempfile::TempDir;
+
+        #[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+        struct KeyValueStore {
+            store: BTreeMap<String, String>,
+        }
+
+        #[bjw_db_derive::derive_bjw_db]
+        impl KeyValueStore {
+            /// Was called `insert` before.
+            #[bjw(serde_rename = "Insert")]
+            pub fn put(&mut self, key: String, value: String) {
+                self.store.insert(key, value);
+            }
+
+            pub fn get(&self, key: &str) -> Option<String> {
+                self.store.get(key).cloned()
+            }
+        }
+
+        #[test]
+        fn test_serde_rename() {
+            let tempdir = TempDir::with_prefix("bjw-").unwrap();
+            let path = tempdir.path().join("kv-store");
+            let mut db = super::KeyValueStoreDb::open(&path).unwrap();
+            db.insert("old".to_string(), "value".to_string()).unwrap();
+            drop(db);
+
+            let mut db = KeyValueStoreDb::open(&path).unwrap();
+            assert_eq!(db.get("old"), Some("value".to_string()));
+            db.put("new".to_string(), "value".to_string()).unwrap();
+            drop(db);
+            let log = std::fs::read_to_string(path.join("logfile.0")).unwrap();
+            assert_eq!(log.matches("Insert").count(), 2);
+            assert!(!log.contains("Put"));
+        }
+    }
+
+    mod subscriptions {
+        use crate as bjw_db;
+
+        use bjw_db::SyncPolicy;
+        use serde::{Deserialize, Serialize};
+        use std::collections::BTreeMap;
+        use tempfile::TempDir;
+
+        #[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+        struct KeyValueStore {
+            store: BTreeMap<String, String>,
+        }
+
+        #[bjw_db_derive::derive_bjw_db(subscribe, thread_safe, debug)]
+        impl KeyValueStore {
+            pub fn insert(&mut self, key: String, value: String) {
+                self.store.insert(key, value);
+            }
+
+            pub fn get(&self, key: &str) -> Option<String> {
+                self.store.get(key).cloned()
+            }
+        }
+
+        fn keys(updates: impl Iterator<Item = KeyValueStoreUpdateParams>) -> Vec<String> {
+            updates
+                .map(|KeyValueStoreUpdateParams::Insert(key, _)| key)
+                .collect()
+        }
+
+        #[test]
+        fn test_subscribe() {
+            let tempdir = TempDir::with_prefix("bjw-").unwrap();
+            let db = KeyValueStoreDb::open(tempdir.path().join("kv-store")).unwrap();
+            db.insert("before".to_string(), "value".to_string())
+                .unwrap();
+            let first = db.subscribe();
+            db.insert("a".to_string(), "1".to_string()).unwrap();
+            let second = db.subscribe();
+            db.update_batch(&[
+                KeyValueStoreUpdateParams::Insert("b".to_string(), "2".to_string()),
+                KeyValueStoreUpdateParams::Insert("c".to_string(), "3".to_string()),
+            ])
             .unwrap();
+            drop(second);
+            db.insert("d".to_string(), "4".to_string()).unwrap();
+            assert_eq!(keys(first.try_iter()), ["a", "b", "c", "d"]);
 
-        // re-open db
-        let data = db.clone_data();
-        let mut db = KeyValueStoreDb::open(&path).unwrap();
-        assert_eq!(data, db.clone_data());
+            // updates that are not synced yet are held back
+            let mut db = Database::builder(JsonFormat::<KeyValueStore>::new())
+                .sync_policy(SyncPolicy::Never)
+                .open(tempdir.path().join("unsynced"))
+                .unwrap();
+            let receiver = db.subscribe();
+            db.update(&KeyValueStoreUpdateParams::Insert(
+                "key".to_string(),
+                "value".to_string(),
+            ))
+            .unwrap();
+            assert!(receiver.try_recv().is_err());
+            db.flush().unwrap();
+            assert_eq!(keys(receiver.try_iter()), ["key"]);
+        }
+    }
 
-        // create a checkpoint and don't update, but re-open right away (-> tests empty log)
-        db.create_checkpoint().unwrap();
-        let db = KeyValueStoreDb::open(&path).unwrap();
+    mod migration {
+        use crate as bjw_db;
 
-        // delete
-        db.delete().unwrap();
+        use bjw_db::{BjwError, DataFormat, Database, JsonFormat};
+        use tempfile::TempDir;
+
+        mod v1 {
+            use crate as bjw_db;
+
+            use serde::{Deserialize, Serialize};
+
+            #[derive(Debug, Default, Serialize, Deserialize, Clone)]
+            pub struct Counter {
+                pub count: u64,
+            }
+
+            #[bjw_db_derive::derive_bjw_db]
+            impl Counter {
+                pub fn add(&mut self, n: u64) {
+                    self.count += n;
+                }
+
+                pub fn get(&self, _unused: &()) -> u64 {
+                    self.count
+                }
+            }
+        }
+
+        mod v2 {
+            use crate as bjw_db;
+
+            use serde::{Deserialize, Serialize};
+
+            #[derive(Debug, Default, Serialize, Deserialize, Clone)]
+            pub struct Counter {
+                pub count: u64,
+                pub updates: u64,
+            }
+
+            #[bjw_db_derive::derive_bjw_db]
+            impl Counter {
+                pub fn add(&mut self, n: u64) {
+                    self.count += n;
+                    self.updates += 1;
+                }
+
+                pub fn get(&self, _unused: &()) -> u64 {
+                    self.count
+                }
+            }
+        }
+
+        #[test]
+        fn test_migration() {
+            let tempdir = TempDir::with_prefix("bjw-").unwrap();
+            let path = tempdir.path().join("counter");
+            let mut db = v1::CounterDb::open(&path).unwrap();
+            db.add(1).unwrap();
+            db.create_checkpoint().unwrap();
+            db.add(2).unwrap();
+            drop(db);
+
+            let fmt = JsonFormat::<v2::Counter>::new;
+            assert!(Database::open(&path, fmt()).is_err());
+            let db = Database::open_with_migration(&path, fmt(), |ser, version| {
+                assert_eq!(version, 1);
+                let old: v1::Counter =
+                    serde_json::from_slice(ser).map_err(BjwError::deserialize)?;
+                Ok(v2::Counter {
+                    count: old.count,
+                    updates: 0,
+                })
+            })
+            .unwrap();
+            assert_eq!(db.read_all().count, 3);
+            assert_eq!(db.read_all().updates, 1);
+            assert_eq!(db.stats().unwrap().version, 2);
+            drop(db);
+
+            // the migrated state was persisted in the new format
+            let db = Database::open(&path, fmt()).unwrap();
+            assert_eq!(db.read_all().count, 3);
+        }
+    }
+
+    #[cfg(feature = "tokio")]
+    mod async_api {
+        use crate as bjw_db;
+
+        use serde::{Deserialize, Serialize};
+        use std::collections::BTreeMap;
+        use tempfile::TempDir;
+
+        #[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+        struct KeyValueStore {
+            store: BTreeMap<String, String>,
+        }
+
+        #[bjw_db_derive::derive_bjw_db(async)]
+        impl KeyValueStore {
+            pub fn insert(&mut self, key: String, value: String) -> Option<String> {
+                self.store.insert(key, value)
+            }
+
+            pub fn get(&self, key: &str) -> Option<String> {
+                self.store.get(key).cloned()
+            }
+
+            pub fn get_ref(&self, key: &str) -> &str {
+                self.store.get(key).map_or("", String::as_str)
+            }
+        }
+
+        #[tokio::test]
+        async fn test_async_database() {
+            let tempdir = TempDir::with_prefix("bjw-").unwrap();
+            let path = tempdir.path().join("kv-store");
+            let db = KeyValueStoreAsyncDb::open(&path).await.unwrap();
+            db.insert("key".to_string(), "value".to_string())
+                .await
+                .unwrap();
+            db.create_checkpoint().await.unwrap();
+            let old = db
+                .insert("key".to_string(), "other".to_string())
+                .await
+                .unwrap();
+            assert_eq!(old, Some("value".to_string()));
+            db.update_batch(vec![KeyValueStoreUpdateParams::Insert(
+                "more".to_string(),
+                "values".to_string(),
+            )])
+            .await
+            .unwrap();
+            assert_eq!(db.get("key").await, Some("other".to_string()));
+            assert_eq!(db.get_ref("more", str::len).await, 6);
+
+            // the files are shared with the synchronous api
+            let data = db.clone_data().await;
+            drop(db);
+            let sync_db = KeyValueStoreDb::open(&path).unwrap();
+            assert_eq!(data, sync_db.clone_data());
+            drop(sync_db);
+            let db = KeyValueStoreAsyncDb::open(&path).await.unwrap();
+            assert_eq!(data, db.clone_data().await);
+            db.delete().await.unwrap();
+            assert!(!path.exists());
+        }
+    }
+
+    mod flatten_result {
+        use crate as bjw_db;
+
+        use serde::{Deserialize, Serialize};
+        use tempfile::TempDir;
+
+        #[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+        struct Account {
+            balance: u64,
+        }
+
+        #[bjw_db_derive::derive_bjw_db]
+        impl Account {
+            pub fn deposit(&mut self, amount: u64) -> Result<u64, String> {
+                self.balance += amount;
+                Ok(self.balance)
+            }
+
+            #[bjw(flatten_result)]
+            pub fn withdraw(&mut self, amount: u64) -> Result<u64, String> {
+                if amount > self.balance {
+                    return Err(format!("cannot withdraw {amount}"));
+                }
+                self.balance -= amount;
+                Ok(self.balance)
+            }
+
+            pub fn balance(&self, _unit: &str) -> u64 {
+                self.balance
+            }
+
+            pub fn reset(self, balance: u64) -> Self {
+                Account { balance }
+            }
+        }
+
+        #[test]
+        fn test_flatten_result() {
+            let tempdir = TempDir::with_prefix("bjw-").unwrap();
+            let path = tempdir.path().join("account");
+            let mut db = AccountDb::open(&path).unwrap();
+
+            // without the attribute, the method's result is nested in the database's
+            let nested: Result<Result<u64, String>, bjw_db::BjwError> = db.deposit(10);
+            assert_eq!(nested.unwrap(), Ok(10));
+
+            let flat: Result<u64, bjw_db::UpdateError<String>> = db.withdraw(3);
+            assert_eq!(flat.unwrap(), 7);
+            match db.withdraw(100) {
+                Err(bjw_db::UpdateError::Update(e)) => assert_eq!(e, "cannot withdraw 100"),
+                other => panic!("unexpected result {other:?}"),
+            }
+
+            // failed updates are logged as well and fail again on replay
+            drop(db);
+            let mut db = AccountDb::open(&path).unwrap();
+            assert_eq!(db.balance("cents"), 7);
+
+            // consuming methods replace the state and are replayed as well
+            db.reset(42).unwrap();
+            assert_eq!(db.balance("cents"), 42);
+            drop(db);
+            let db = AccountDb::open(&path).unwrap();
+            assert_eq!(db.balance("cents"), 42);
+        }
+    }
+
+    mod multiple_impls {
+        use crate as bjw_db;
+
+        use serde::{Deserialize, Serialize};
+        use std::collections::BTreeMap;
+        use tempfile::TempDir;
+
+        #[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+        struct Inventory {
+            items: BTreeMap<String, u64>,
+        }
+
+        bjw_db_derive::bjw_db_impls! {
+            #[derive_bjw_db(thread_safe, debug)]
+            impl Inventory {
+                pub fn add(&mut self, item: String, n: u64) {
+                    *self.items.entry(item).or_default() += n;
+                }
+
+                pub fn count(&self, item: &str) -> u64 {
+                    self.items.get(item).copied().unwrap_or_default()
+                }
+            }
+
+            impl Inventory {
+                pub fn remove(&mut self, item: String) -> Option<u64> {
+                    self.items.remove(&item)
+                }
+
+                pub fn contains(&self, item: &str) -> bool {
+                    self.items.contains_key(item)
+                }
+            }
+        }
+
+        #[test]
+        fn test_multiple_impls() {
+            let tempdir = TempDir::with_prefix("bjw-").unwrap();
+            let path = tempdir.path().join("inventory");
+            let db = InventoryDb::open(&path).unwrap();
+            db.add("apple".to_string(), 3).unwrap();
+            db.add("pear".to_string(), 1).unwrap();
+            assert_eq!(db.remove("pear".to_string()).unwrap(), Some(1));
+            assert_eq!(db.count("apple"), 3);
+            assert!(!db.contains("pear"));
+            let params = InventoryUpdateParams::Add("apple".to_string(), 3);
+            assert_eq!(format!("{params:?}"), r#"Add("apple", 3)"#);
+            let ret = InventoryReadReturn::Count(3);
+            assert_eq!(format!("{ret:?}"), "Count(3)");
+
+            drop(db);
+            let db = InventoryDb::open(&path).unwrap();
+            assert_eq!(db.count("apple"), 3);
+            assert!(!db.contains("pear"));
+        }
+    }
+
+    #[allow(non_snake_case)]
+    mod renamed_variants {
+        use crate as bjw_db;
+
+        use serde::{Deserialize, Serialize};
+        use std::collections::BTreeMap;
+        use tempfile::TempDir;
+
+        #[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+        struct KeyValueStore {
+            store: BTreeMap<String, String>,
+        }
+
+        #[bjw_db_derive::derive_bjw_db]
+        impl KeyValueStore {
+            pub fn insert(&mut self, key: String, value: String) {
+                self.store.insert(key, value);
+            }
+
+            pub fn get(&self, key: &str) -> Option<String> {
+                self.store.get(key).cloned()
+            }
+
+            #[bjw(rename = "GetUppercase")]
+            pub fn Get(&self, key: &str) -> Option<String> {
+                self.store.get(key).map(|value| value.to_uppercase())
+            }
+        }
+
+        #[test]
+        fn test_renamed_variants() {
+            let tempdir = TempDir::with_prefix("bjw-").unwrap();
+            let path = tempdir.path().join("kv-store");
+            let mut db = KeyValueStoreDb::open(&path).unwrap();
+            db.insert("key".to_string(), "value".to_string()).unwrap();
+            assert_eq!(db.get("key"), Some("value".to_string()));
+            assert_eq!(db.Get("key"), Some("VALUE".to_string()));
+            let params = KeyValueStoreReadParams::GetUppercase("key");
+            assert!(matches!(
+                db.clone_data().read(&params),
+                KeyValueStoreReadReturn::GetUppercase(Some(_))
+            ));
+        }
+    }
+
+    #[cfg(feature = "rkyv")]
+    mod rkyv_format {
+        use crate as bjw_db;
+
+        use serde::{Deserialize, Serialize};
+        use std::{collections::BTreeMap, io::Write};
+        use tempfile::TempDir;
+
+        #[derive(
+            Debug,
+            Default,
+            Serialize,
+            Deserialize,
+            Clone,
+            PartialEq,
+            rkyv::Archive,
+            rkyv::Serialize,
+            rkyv::Deserialize,
+        )]
+        struct NumberStore {
+            store: BTreeMap<u64, String>,
+        }
+
+        #[bjw_db_derive::derive_bjw_db(fmt = "RkyvFormat")]
+        impl NumberStore {
+            pub fn insert(&mut self, key: u64, value: String) {
+                self.store.insert(key, value);
+            }
+
+            pub fn get(&self, key: &u64) -> Option<String> {
+                self.store.get(key).cloned()
+            }
+        }
+
+        #[test]
+        fn test_rkyv_round_trip() {
+            let tempdir = TempDir::with_prefix("bjw-").unwrap();
+            let path = tempdir.path().join("number-store");
+            let mut db = NumberStoreDb::open(&path).unwrap();
+            for i in 0..100 {
+                db.insert(i, format!("value {i}")).unwrap();
+            }
+            db.create_checkpoint().unwrap();
+            db.insert(100, "single".to_string()).unwrap();
+            db.update_batch(&[
+                NumberStoreUpdateParams::Insert(101, "first".to_string()),
+                NumberStoreUpdateParams::Insert(102, "second".to_string()),
+            ])
+            .unwrap();
+
+            let data = db.clone_data();
+            drop(db);
+            let db = NumberStoreDb::open(&path).unwrap();
+            assert_eq!(data, db.clone_data());
+            assert_eq!(db.get(&102), Some("second".to_string()));
+            assert!(std::fs::read(path.join("checkpoint.1"))
+                .unwrap()
+                .starts_with(b"bjw:rkyv:"));
+            drop(db);
+
+            // a truncated trailing frame is skipped
+            let mut log = std::fs::OpenOptions::new()
+                .append(true)
+                .open(path.join("logfile.1"))
+                .unwrap();
+            log.write_all(&[42, 0, 0, 0, 1, 2]).unwrap();
+            drop(log);
+            let db = NumberStoreDb::open(&path).unwrap();
+            assert_eq!(data, db.clone_data());
+        }
+    }
+
+    #[cfg(feature = "bincode")]
+    mod bincode_format {
+        use crate as bjw_db;
+
+        use serde::{Deserialize, Serialize};
+        use std::{collections::BTreeMap, io::Write};
+        use tempfile::TempDir;
+
+        #[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+        struct NumberStore {
+            store: BTreeMap<u64, String>,
+        }
+
+        #[bjw_db_derive::derive_bjw_db(fmt = "BincodeFormat")]
+        impl NumberStore {
+            pub fn insert(&mut self, key: u64, value: String) {
+                self.store.insert(key, value);
+            }
+
+            pub fn get(&self, key: &u64) -> Option<String> {
+                self.store.get(key).cloned()
+            }
+        }
+
+        #[test]
+        fn test_round_trip() {
+            let tempdir = TempDir::with_prefix("bjw-").unwrap();
+            let path = tempdir.path().join("number-store");
+
+            // insert half before and half after a checkpoint, so both paths are covered
+            let mut db = NumberStoreDb::open(&path).unwrap();
+            for i in 0..500 {
+                db.insert(i, format!("value {i}")).unwrap();
+            }
+            db.create_checkpoint().unwrap();
+            for i in 500..1000 {
+                db.insert(i, format!("value {i}")).unwrap();
+            }
+
+            let data = db.clone_data();
+            drop(db);
+            let db = NumberStoreDb::open(&path).unwrap();
+            assert_eq!(data, db.clone_data());
+            assert_eq!(db.get(&999), Some("value 999".to_string()));
+
+            // a truncated trailing frame is skipped
+            let mut log = std::fs::OpenOptions::new()
+                .append(true)
+                .open(path.join("logfile.1"))
+                .unwrap();
+            log.write_all(&[42, 0, 0, 0, 1, 2]).unwrap();
+            drop(db);
+            let db = NumberStoreDb::open(&path).unwrap();
+            assert_eq!(data, db.clone_data());
+        }
+
+        #[test]
+        fn test_refresh_after_torn_write() {
+            let tempdir = TempDir::with_prefix("bjw-").unwrap();
+            let path = tempdir.path().join("number-store");
+            let mut db = NumberStoreDb::open(&path).unwrap();
+            db.insert(1, "one".to_string()).unwrap();
+            drop(db);
+            let append = |bytes: &[u8]| {
+                let mut log = std::fs::OpenOptions::new()
+                    .append(true)
+                    .open(path.join("logfile.0"))
+                    .unwrap();
+                log.write_all(bytes).unwrap();
+            };
+            let fmt = BincodeFormat::<NumberStore>::new();
+            let params = |key: u64| NumberStoreUpdateParams::Insert(key, format!("value {key}"));
+
+            // torn while the replica refreshes, right before a value that is a frame itself, which
+            // must not be read on its own
+            let mut replica = NumberStoreDb::open_read_only(&path).unwrap();
+            let inner = fmt.serialize_params(&params(42)).unwrap();
+            let value = String::from_utf8(inner).unwrap();
+            let ser = fmt
+                .serialize_params(&NumberStoreUpdateParams::Insert(2, value.clone()))
+                .unwrap();
+            let (head, tail) = ser.split_at(ser.len() - value.len());
+            append(head);
+            assert_eq!(replica.refresh().unwrap(), 0);
+            append(tail);
+            assert_eq!(replica.refresh().unwrap(), 1);
+            assert_eq!(replica.get(&2), Some(value));
+            assert_eq!(replica.get(&42), None);
+
+            // torn while the replica opens
+            let ser = fmt.serialize_params(&params(3)).unwrap();
+            let (head, tail) = ser.split_at(6);
+            append(head);
+            let mut replica = NumberStoreDb::open_read_only(&path).unwrap();
+            assert_eq!(replica.get(&3), None);
+            append(tail);
+            append(&fmt.serialize_params(&params(4)).unwrap());
+            assert_eq!(replica.refresh().unwrap(), 2);
+            assert_eq!(replica.get(&3), Some("value 3".to_string()));
+            assert_eq!(replica.get(&4), Some("value 4".to_string()));
+            assert_eq!(replica.refresh().unwrap(), 0);
+        }
+    }
+
+    #[cfg(feature = "msgpack")]
+    mod msgpack_format {
+        use crate as bjw_db;
+
+        use serde::{Deserialize, Serialize};
+        use std::collections::BTreeMap;
+        use tempfile::TempDir;
+
+        #[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+        struct NumberStore {
+            store: BTreeMap<u64, String>,
+        }
+
+        #[bjw_db_derive::derive_bjw_db(fmt = "MsgPackFormat")]
+        impl NumberStore {
+            pub fn insert(&mut self, key: u64, value: String) {
+                self.store.insert(key, value);
+            }
+
+            pub fn remove(&mut self, key: u64) -> Option<String> {
+                self.store.remove(&key)
+            }
+
+            pub fn get(&self, key: &u64) -> Option<String> {
+                self.store.get(key).cloned()
+            }
+        }
+
+        #[test]
+        fn test_interleaved_checkpoints() {
+            let tempdir = TempDir::with_prefix("bjw-").unwrap();
+            let path = tempdir.path().join("number-store");
+
+            let mut db = NumberStoreDb::open(&path).unwrap();
+            for round in 0..5 {
+                for i in 0..20 {
+                    db.insert(round * 100 + i, format!("value {i}")).unwrap();
+                }
+                assert!(db.remove(round * 100).unwrap().is_some());
+                if round % 2 == 1 {
+                    db.create_checkpoint().unwrap();
+                }
+
+                let data = db.clone_data();
+                drop(db);
+                db = NumberStoreDb::open(&path).unwrap();
+                assert_eq!(data, db.clone_data());
+            }
+            assert_eq!(db.get(&0), None);
+            assert_eq!(db.get(&401), Some("value 1".to_string()));
+
+            // a partially written trailing update (the last removal) is skipped
+            let log_path = path.join("logfile.2");
+            let mut log = std::fs::read(&log_path).unwrap();
+            log.truncate(log.len() - 3);
+            std::fs::write(&log_path, log).unwrap();
+            drop(db);
+            let db = NumberStoreDb::open(&path).unwrap();
+            assert_eq!(db.get(&400), Some("value 0".to_string()));
+        }
+    }
+
+    #[cfg(feature = "cbor")]
+    mod cbor_format {
+        use crate as bjw_db;
+
+        use serde::{Deserialize, Serialize};
+        use std::collections::BTreeMap;
+        use tempfile::TempDir;
+
+        #[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+        struct KeyValueStore {
+            store: BTreeMap<String, String>,
+        }
+
+        #[bjw_db_derive::derive_bjw_db(fmt = "CborFormat")]
+        impl KeyValueStore {
+            pub fn insert(&mut self, key: String, value: String) {
+                self.store.insert(key, value);
+            }
+
+            pub fn get(&self, key: &str) -> Option<String> {
+                self.store.get(key).cloned()
+            }
+        }
+
+        #[test]
+        fn test_external_decoding() {
+            let tempdir = TempDir::with_prefix("bjw-").unwrap();
+            let path = tempdir.path().join("kv-store");
+
+            let mut db = KeyValueStoreDb::open(&path).unwrap();
+            db.insert("key".to_string(), "value".to_string()).unwrap();
+            db.create_checkpoint().unwrap();
+            db.insert("more".to_string(), "values".to_string()).unwrap();
+            db.insert("even more".to_string(), "values".to_string())
+                .unwrap();
+
+            // after its header, the checkpoint is a plain CBOR document that can be decoded without
+            // knowing the type
+            let checkpoint = std::fs::read(path.join("checkpoint.1")).unwrap();
+            let content = checkpoint.strip_prefix(b"bjw:cbor:1\n".as_slice()).unwrap();
+            let value: ciborium::Value = ciborium::from_reader(content).unwrap();
+            let expected = ciborium::Value::Map(vec![(
+                "store".into(),
+                ciborium::Value::Map(vec![("key".into(), "value".into())]),
+            )]);
+            assert_eq!(value, expected);
+
+            // the log holds concatenated data items without any separator
+            let log = std::fs::read(path.join("logfile.1")).unwrap();
+            assert_ne!(log.last(), Some(&b'\n'));
+
+            // a partially written trailing item is skipped
+            std::fs::write(path.join("logfile.1"), &log[..log.len() - 2]).unwrap();
+            drop(db);
+            let db = KeyValueStoreDb::open(&path).unwrap();
+            assert_eq!(db.get("more"), Some("values".to_string()));
+            assert_eq!(db.get("even more"), None);
+        }
+    }
+
+    #[cfg(feature = "toml")]
+    mod toml_format {
+        use crate as bjw_db;
+
+        use serde::{Deserialize, Serialize};
+        use std::collections::BTreeMap;
+        use tempfile::TempDir;
+
+        #[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+        struct Config {
+            retries: u32,
+            hosts: BTreeMap<String, String>,
+        }
+
+        #[bjw_db_derive::derive_bjw_db(fmt = "TomlFormat")]
+        impl Config {
+            pub fn set_retries(&mut self, retries: u32) {
+                self.retries = retries;
+            }
+
+            pub fn add_host(&mut self, name: String, address: String) {
+                self.hosts.insert(name, address);
+            }
+
+            pub fn address(&self, name: &str) -> Option<String> {
+                self.hosts.get(name).cloned()
+            }
+        }
+
+        #[test]
+        fn test_edit_checkpoint() {
+            let tempdir = TempDir::with_prefix("bjw-").unwrap();
+            let path = tempdir.path().join("config");
+
+            let mut db = ConfigDb::open(&path).unwrap();
+            db.set_retries(3).unwrap();
+            db.add_host("db".to_string(), "10.0.0.1".to_string())
+                .unwrap();
+            db.create_checkpoint().unwrap();
+            // the log is not TOML, but one JSON encoded update per line
+            db.add_host("cache".to_string(), "10.0.0.2".to_string())
+                .unwrap();
+            drop(db);
+            let log = std::fs::read_to_string(path.join("logfile.1")).unwrap();
+            assert_eq!(log.lines().count(), 1);
+
+            // edit the checkpoint like a user would
+            let checkpoint = std::fs::read_to_string(path.join("checkpoint.1")).unwrap();
+            assert!(checkpoint.contains("retries = 3"), "{checkpoint}");
+            let edited = checkpoint
+                .replace("retries = 3", "retries = 5")
+                .replace("10.0.0.1", "10.0.0.9");
+            std::fs::write(path.join("checkpoint.1"), edited).unwrap();
+            std::fs::remove_file(path.join("checkpoint.1.sha256")).unwrap();
+
+            let db = ConfigDb::open(&path).unwrap();
+            assert_eq!(db.clone_data().retries, 5);
+            assert_eq!(db.address("db"), Some("10.0.0.9".to_string()));
+            assert_eq!(db.address("cache"), Some("10.0.0.2".to_string()));
+        }
     }
 }
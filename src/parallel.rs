@@ -0,0 +1,138 @@
+use std::collections::BTreeMap;
+
+use rayon::prelude::*;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{BjwError, DataFormat, DatabaseBuilder, Readable, Result, Updateable};
+
+/// Marks a checkpoint that consists of shards, see [`DatabaseBuilder::parallel_checkpoints`].
+const SHARDS_MAGIC: &[u8] = b"bjw-shards\n";
+
+/// Data that mostly consists of one big map, so its checkpoints can be split into shards of
+/// entries by [`ParallelFormat`].
+pub trait MapData {
+    type Key: Ord + Serialize + DeserializeOwned + Send + Sync;
+    type Value: Serialize + DeserializeOwned + Send + Sync;
+
+    fn entries(&self) -> &BTreeMap<Self::Key, Self::Value>;
+    /// Rebuilds the data from the entries of a checkpoint.
+    fn from_entries(entries: BTreeMap<Self::Key, Self::Value>) -> Self;
+}
+
+/// A [`DataFormat`] that can serialize its data in independent shards, so that checkpoints of
+/// large data are (de)serialized on several threads.
+pub trait ParallelFormat: DataFormat + Sync {
+    /// Serializes `data` into at most `shards` parts. Runs on the thread pool of the database,
+    /// so the parts can be serialized with rayon's parallel iterators.
+    fn serialize_shards(&self, data: &Self::Data, shards: usize) -> Result<Vec<Vec<u8>>>;
+    /// Reassembles the data from the parts returned by [`ParallelFormat::serialize_shards`].
+    fn deserialize_shards(&self, shards: &[&[u8]]) -> Result<Self::Data>;
+}
+
+#[cfg(feature = "json")]
+impl<T> ParallelFormat for crate::JsonFormat<T>
+where
+    T: Serialize + DeserializeOwned + Readable + Updateable + MapData + Sync,
+{
+    fn serialize_shards(&self, data: &T, shards: usize) -> Result<Vec<Vec<u8>>> {
+        let entries: Vec<_> = data.entries().iter().collect();
+        let shard_len = entries.len().div_ceil(shards.max(1)).max(1);
+        entries
+            .par_chunks(shard_len)
+            .map(|shard| serde_json::to_vec(shard).map_err(BjwError::serialize))
+            .collect()
+    }
+
+    fn deserialize_shards(&self, shards: &[&[u8]]) -> Result<T> {
+        let shards = shards
+            .par_iter()
+            .map(|shard| {
+                serde_json::from_slice::<Vec<(T::Key, T::Value)>>(shard)
+                    .map_err(BjwError::deserialize)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(T::from_entries(shards.into_iter().flatten().collect()))
+    }
+}
+
+/// How checkpoints are sharded, set by [`DatabaseBuilder::parallel_checkpoints`].
+pub(crate) struct Sharding<T, F> {
+    pub(crate) threads: usize,
+    /// See [`serialize_sharded`].
+    pub(crate) serialize: fn(&F, &T, usize) -> Result<Vec<u8>>,
+    /// See [`deserialize_sharded`].
+    pub(crate) deserialize: fn(&F, &[&[u8]], usize) -> Result<T>,
+}
+
+/// Serializes `data` on `threads` threads into the content of a checkpoint file, which frames
+/// each shard with its length.
+fn serialize_sharded<F>(fmt: &F, data: &F::Data, threads: usize) -> Result<Vec<u8>>
+where
+    F: ParallelFormat,
+    F::Data: Sync,
+{
+    let shards = thread_pool(threads)?.install(|| fmt.serialize_shards(data, threads))?;
+    let mut ser = SHARDS_MAGIC.to_vec();
+    for shard in shards {
+        ser.extend((shard.len() as u64).to_be_bytes());
+        ser.extend(shard);
+    }
+    Ok(ser)
+}
+
+/// Reassembles the data from the `shards` of a checkpoint file on `threads` threads.
+fn deserialize_sharded<F>(fmt: &F, shards: &[&[u8]], threads: usize) -> Result<F::Data>
+where
+    F: ParallelFormat,
+    F::Data: Send,
+{
+    thread_pool(threads)?.install(|| fmt.deserialize_shards(shards))
+}
+
+fn thread_pool(threads: usize) -> Result<rayon::ThreadPool> {
+    Ok(rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .map_err(std::io::Error::other)?)
+}
+
+/// The shards of a checkpoint written with sharding, or `None` if it is a regular one.
+pub(crate) fn split_shards(mut input: &[u8]) -> Option<Result<Vec<&[u8]>>> {
+    input = input.strip_prefix(SHARDS_MAGIC)?;
+    let mut shards = Vec::new();
+    while !input.is_empty() {
+        let Some((len, rest)) = input.split_first_chunk::<8>() else {
+            return Some(Err(BjwError::Corrupt(
+                "Sharded checkpoint is truncated".to_string(),
+            )));
+        };
+        let len = u64::from_be_bytes(*len);
+        if len > rest.len() as u64 {
+            return Some(Err(BjwError::Corrupt(
+                "Sharded checkpoint is truncated".to_string(),
+            )));
+        }
+        let (shard, rest) = rest.split_at(len as usize);
+        shards.push(shard);
+        input = rest;
+    }
+    Some(Ok(shards))
+}
+
+impl<T, F> DatabaseBuilder<T, F>
+where
+    T: Default + Serialize + DeserializeOwned + Readable + Updateable + Send + Sync,
+    F: ParallelFormat<Data = T>,
+{
+    /// Splits checkpoints into one shard per thread, which are serialized (and deserialized
+    /// when opening) on a pool of `threads` threads. The checkpoint files are a sequence of these
+    /// shards then, so opening a database at such a checkpoint requires this option as well.
+    pub fn parallel_checkpoints(mut self, threads: usize) -> Self {
+        self.sharding = Some(Sharding {
+            threads: threads.max(1),
+            serialize: serialize_sharded::<F>,
+            deserialize: deserialize_sharded::<F>,
+        });
+        self
+    }
+}
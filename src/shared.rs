@@ -0,0 +1,241 @@
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    ops::{Deref, DerefMut},
+    sync::{
+        mpsc::{self, RecvTimeoutError},
+        Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard, Weak,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use crate::{DataFormat, Database, Readable, Result, Updateable};
+
+/// Shares a [`Database`] between threads, like an `RwLock<Database>` that keeps readers going
+/// during disk I/O. Updates append to the log while only holding the read lock, and just take
+/// the write lock to apply the update in memory. A separate mutex orders the updates, so they
+/// are applied in the same order as they are logged. This is what `#[derive_bjw_db(thread_safe)]`
+/// uses.
+pub struct SharedDatabase<T, F> {
+    db: RwLock<Database<T, F>>,
+    /// Orders the updates, and holds the buffer they are serialized into.
+    writer: Mutex<Vec<u8>>,
+    /// The data handed out by [`SharedDatabase::snapshot_arc`], until it changes. Only locked
+    /// while holding `db`, so that it cannot get out of date.
+    snapshot: Mutex<Option<Arc<T>>>,
+}
+
+/// Creates checkpoints in a background thread, see [`SharedDatabase::spawn_autocheckpoint`].
+/// Dropping it stops the thread, waiting for a checkpoint that is in progress.
+pub struct AutoCheckpoint {
+    /// Dropped to wake the thread up and stop it.
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+/// Exclusive access to a [`SharedDatabase`], see [`SharedDatabase::write`].
+pub struct WriteGuard<'a, T, F> {
+    db: RwLockWriteGuard<'a, Database<T, F>>,
+    _writer: MutexGuard<'a, Vec<u8>>,
+    snapshot: &'a Mutex<Option<Arc<T>>>,
+}
+
+impl<T, F> SharedDatabase<T, F> {
+    pub fn new(db: Database<T, F>) -> Self {
+        SharedDatabase {
+            db: RwLock::new(db),
+            writer: Mutex::new(Vec::new()),
+            snapshot: Mutex::new(None),
+        }
+    }
+
+    /// Reads the current state, which blocks only while an update is applied in memory or
+    /// [`SharedDatabase::write`] is held.
+    pub fn read(&self) -> RwLockReadGuard<'_, Database<T, F>> {
+        self.db.read().unwrap()
+    }
+
+    /// Exclusive access for everything but updates, e.g. to create a checkpoint.
+    pub fn write(&self) -> WriteGuard<'_, T, F> {
+        let writer = self.writer.lock().unwrap();
+        WriteGuard {
+            db: self.db.write().unwrap(),
+            _writer: writer,
+            snapshot: &self.snapshot,
+        }
+    }
+
+    pub fn into_inner(self) -> Database<T, F> {
+        self.db.into_inner().unwrap()
+    }
+}
+
+impl<T, F> SharedDatabase<T, F>
+where
+    T: Serialize + DeserializeOwned + Readable + Updateable,
+    F: DataFormat<Data = T>,
+{
+    /// See [`Database::update`].
+    pub fn update(
+        &self,
+        parameters: &<T as Updateable>::Args,
+    ) -> Result<<T as Updateable>::ReturnType> {
+        self.logged(
+            |db, buf| db.fmt.serialize_params_into(parameters, buf),
+            |db| {
+                let ret = db.data.update(parameters);
+                db.finish_update(std::slice::from_ref(parameters));
+                ret
+            },
+        )
+    }
+
+    /// See [`Database::update_batch`].
+    pub fn update_batch(
+        &self,
+        parameters: &[<T as Updateable>::Args],
+    ) -> Result<Vec<<T as Updateable>::ReturnType>> {
+        if parameters.is_empty() {
+            return self.read().check_writable().map(|_| Vec::new());
+        }
+        self.logged(
+            |db, buf| {
+                *buf = db.fmt.serialize_batch(parameters)?;
+                Ok(())
+            },
+            |db| {
+                let ret = parameters
+                    .iter()
+                    .map(|params| db.data.update(params))
+                    .collect();
+                db.finish_update(parameters);
+                ret
+            },
+        )
+    }
+
+    /// Creates a checkpoint like [`Database::create_checkpoint`], unless there are no updates
+    /// since the last one (see [`Database::has_pending_updates`]). That is checked with just the
+    /// read lock, so an idle database is never locked. Returns whether it created one.
+    pub fn checkpoint_if_pending(&self) -> Result<bool> {
+        if !self.read().has_pending_updates()? {
+            return Ok(false);
+        }
+        self.write().create_checkpoint()?;
+        Ok(true)
+    }
+
+    /// Logs what `serialize` writes into the (cleared) buffer, and then runs `apply` with the
+    /// write lock held.
+    fn logged<R>(
+        &self,
+        serialize: impl FnOnce(&Database<T, F>, &mut Vec<u8>) -> Result<()>,
+        apply: impl FnOnce(&mut Database<T, F>) -> R,
+    ) -> Result<R> {
+        let mut ser = self.writer.lock().unwrap();
+        ser.clear();
+        {
+            let db = self.read();
+            db.check_writable()?;
+            serialize(&db, &mut ser)?;
+        }
+        let mut append = self.db.write().unwrap().begin_append()?;
+        let written = self.read().write_append(&mut append, &ser);
+        let mut db = self.db.write().unwrap();
+        db.finish_append(append, &ser, written)?;
+        let ret = apply(&mut db);
+        *self.snapshot.lock().unwrap() = None;
+        Ok(ret)
+    }
+}
+
+impl<T, F> SharedDatabase<T, F>
+where
+    T: Serialize + DeserializeOwned + Readable + Updateable + Send + Sync + 'static,
+    F: DataFormat<Data = T> + Send + Sync + 'static,
+{
+    /// Calls [`SharedDatabase::checkpoint_if_pending`] every `interval` in a background thread
+    /// until the returned handle is dropped, which keeps checkpoints out of the request path.
+    /// The checkpoint holds the write lock throughout, so the interval is counted from the end
+    /// of the previous one to leave updates room in between. Failed checkpoints are logged and
+    /// retried on the next tick. The thread only holds a weak reference to the database, and
+    /// stops once it is gone.
+    pub fn spawn_autocheckpoint(self: &Arc<Self>, interval: Duration) -> Result<AutoCheckpoint> {
+        AutoCheckpoint::spawn(self, interval, Self::checkpoint_if_pending)
+    }
+}
+
+impl AutoCheckpoint {
+    /// Calls `checkpoint` on `db` every `interval` in a background thread, like
+    /// [`SharedDatabase::spawn_autocheckpoint`] does for a [`SharedDatabase`]. This is the form
+    /// for anything that owns one, such as the wrappers of `#[derive_bjw_db(thread_safe)]`.
+    pub fn spawn<D: Send + Sync + 'static>(
+        db: &Arc<D>,
+        interval: Duration,
+        checkpoint: impl Fn(&D) -> Result<bool> + Send + 'static,
+    ) -> Result<Self> {
+        let db = Arc::downgrade(db);
+        let (stop, stopped) = mpsc::channel();
+        let thread = std::thread::Builder::new()
+            .name("bjw-autocheckpoint".into())
+            .spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    let Some(db) = Weak::upgrade(&db) else {
+                        return;
+                    };
+                    if let Err(e) = checkpoint(&db) {
+                        log::warn!("Failed to create a periodic checkpoint: {:?}", e);
+                    }
+                }
+            })?;
+        Ok(AutoCheckpoint {
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for AutoCheckpoint {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            // a panic in the thread was reported by it already
+            let _ = thread.join();
+        }
+    }
+}
+
+impl<T: Clone, F> SharedDatabase<T, F> {
+    /// A clone of the data that can be held without blocking writers, e.g. by many readers of a
+    /// read-heavy workload. It is cloned only once and shared by all calls until the data
+    /// changes, so it costs memory for a second copy of the data, and more clones if it is
+    /// updated often.
+    pub fn snapshot_arc(&self) -> Arc<T> {
+        let db = self.read();
+        let mut snapshot = self.snapshot.lock().unwrap();
+        snapshot
+            .get_or_insert_with(|| Arc::new(db.clone_data()))
+            .clone()
+    }
+}
+
+impl<T, F> Deref for WriteGuard<'_, T, F> {
+    type Target = Database<T, F>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.db
+    }
+}
+
+impl<T, F> DerefMut for WriteGuard<'_, T, F> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.db
+    }
+}
+
+impl<T, F> Drop for WriteGuard<'_, T, F> {
+    fn drop(&mut self) {
+        // the data may have been changed in any way
+        *self.snapshot.lock().unwrap() = None;
+    }
+}
@@ -0,0 +1,78 @@
+use std::sync::mpsc::{self, Receiver, Sender};
+
+use crate::{Database, Updateable};
+
+/// The subscribers of a database. The trait is only generic over the data, as the struct
+/// [`Database`] cannot name its update arguments.
+pub(crate) trait Notifier<T>: Send + Sync {
+    fn subscribe(&mut self) -> Receiver<<T as Updateable>::Args>
+    where
+        T: Updateable;
+
+    /// Queues an applied update, to be sent once the log was synced.
+    fn queue(&mut self, args: &<T as Updateable>::Args)
+    where
+        T: Updateable;
+
+    /// Sends all queued updates, as they are durable now.
+    fn release(&mut self);
+}
+
+struct Subscribers<T: Updateable> {
+    senders: Vec<Sender<T::Args>>,
+    /// Updates that are not synced yet, with the number of senders subscribed at the time.
+    pending: Vec<(T::Args, usize)>,
+}
+
+impl<T> Notifier<T> for Subscribers<T>
+where
+    T: Updateable,
+    T::Args: Clone + Send + Sync,
+{
+    fn subscribe(&mut self) -> Receiver<T::Args> {
+        let (sender, receiver) = mpsc::channel();
+        self.senders.push(sender);
+        receiver
+    }
+
+    fn queue(&mut self, args: &T::Args) {
+        if !self.senders.is_empty() {
+            self.pending.push((args.clone(), self.senders.len()));
+        }
+    }
+
+    fn release(&mut self) {
+        let mut alive = vec![true; self.senders.len()];
+        for (args, subscribed) in self.pending.drain(..) {
+            for (sender, alive) in self.senders[..subscribed].iter().zip(&mut alive) {
+                *alive = *alive && sender.send(args.clone()).is_ok();
+            }
+        }
+        // a dropped receiver unsubscribes
+        let mut alive = alive.into_iter();
+        self.senders.retain(|_| alive.next().unwrap_or(true));
+    }
+}
+
+impl<T, F> Database<T, F>
+where
+    T: Updateable + 'static,
+    T::Args: Clone + Send + Sync,
+{
+    /// Returns a receiver for every update applied from now on, in order. An update is only
+    /// sent once it is durable, i.e. after the log was synced according to the [`SyncPolicy`]
+    /// or by [`Database::flush`], so subscribers never see one that could still be lost.
+    /// Dropping the receiver unsubscribes.
+    ///
+    /// [`SyncPolicy`]: crate::SyncPolicy
+    pub fn subscribe(&mut self) -> Receiver<T::Args> {
+        self.subscribers
+            .get_or_insert_with(|| {
+                Box::new(Subscribers::<T> {
+                    senders: Vec::new(),
+                    pending: Vec::new(),
+                })
+            })
+            .subscribe()
+    }
+}
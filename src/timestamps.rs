@@ -0,0 +1,229 @@
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    collections::VecDeque,
+    io::{BufRead, Read},
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{BjwError, DataFormat, Database, Readable, Result, Updateable};
+
+/// Starts a log segment whose updates carry the time they were appended at, see
+/// [`DatabaseBuilder::log_timestamps`](crate::DatabaseBuilder::log_timestamps). No format starts
+/// a log like this on its own.
+pub(crate) const TIMESTAMP_MAGIC: &[u8] = b"bjw:timestamps\n";
+
+/// Each frame of a timestamped segment starts with the timestamp as a little endian u64 of
+/// nanoseconds since the Unix epoch, and the length of the update as a little endian u32.
+const FRAME_HEADER_SIZE: usize = std::mem::size_of::<u64>() + std::mem::size_of::<u32>();
+
+/// An update decoded from the log, with the time it was appended at if the log records it.
+type Timestamped<T> = (Option<SystemTime>, <T as Updateable>::Args);
+
+/// The updates decoded from a log segment, see [`Database::decode_log`].
+pub(crate) type LogUpdates<'a, T> = Box<dyn Iterator<Item = Result<Timestamped<T>>> + 'a>;
+
+/// Reads the start of a log segment, returning whether its updates are timestamped. If not, what
+/// was read belongs to the first update and is returned as well.
+pub(crate) fn read_log_header(reader: &mut impl Read) -> Result<(bool, Vec<u8>)> {
+    let mut head = Vec::with_capacity(TIMESTAMP_MAGIC.len());
+    reader
+        .take(TIMESTAMP_MAGIC.len() as u64)
+        .read_to_end(&mut head)?;
+    if head == TIMESTAMP_MAGIC {
+        Ok((true, Vec::new()))
+    } else {
+        Ok((false, head))
+    }
+}
+
+/// Frames the serialized update `ser` with its timestamp of `nanos`, behind the magic if it is
+/// the first update of its segment.
+pub(crate) fn timestamped_frame(ser: &[u8], nanos: u64, starts_segment: bool) -> Result<Vec<u8>> {
+    let len = u32::try_from(ser.len())
+        .map_err(|_| BjwError::serialize("Update too large for a single frame"))?;
+    let mut frame = Vec::with_capacity(TIMESTAMP_MAGIC.len() + FRAME_HEADER_SIZE + ser.len());
+    if starts_segment {
+        frame.extend_from_slice(TIMESTAMP_MAGIC);
+    }
+    frame.extend_from_slice(&nanos.to_le_bytes());
+    frame.extend_from_slice(&len.to_le_bytes());
+    frame.extend_from_slice(ser);
+    Ok(frame)
+}
+
+/// The timestamp `time` is framed with, in nanoseconds since the Unix epoch.
+pub(crate) fn nanos_since_epoch(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |since| {
+        u64::try_from(since.as_nanos()).unwrap_or(u64::MAX)
+    })
+}
+
+/// How many bytes framing an update adds to the log, see [`timestamped_frame`].
+pub(crate) fn frame_overhead(starts_segment: bool) -> u64 {
+    let magic = if starts_segment {
+        TIMESTAMP_MAGIC.len()
+    } else {
+        0
+    };
+    (magic + FRAME_HEADER_SIZE) as u64
+}
+
+/// Reads the next frame of a timestamped segment with its timestamp, `None` at the end.
+fn read_frame(reader: &mut impl BufRead) -> Result<Option<(u64, Vec<u8>)>> {
+    let truncated = || BjwError::Corrupt("The log ends in a truncated timestamped update".into());
+    let mut header = Vec::with_capacity(FRAME_HEADER_SIZE);
+    reader
+        .take(FRAME_HEADER_SIZE as u64)
+        .read_to_end(&mut header)?;
+    if header.is_empty() {
+        return Ok(None);
+    }
+    if header.len() < FRAME_HEADER_SIZE {
+        return Err(truncated());
+    }
+    let (nanos, len) = header.split_at(std::mem::size_of::<u64>());
+    let nanos = u64::from_le_bytes(nanos.try_into().unwrap());
+    let len = u32::from_le_bytes(len.try_into().unwrap()) as u64;
+    let mut payload = Vec::new();
+    reader.take(len).read_to_end(&mut payload)?;
+    if (payload.len() as u64) < len {
+        return Err(truncated());
+    }
+    Ok(Some((nanos, payload)))
+}
+
+impl<T, F> Database<T, F>
+where
+    T: Serialize + DeserializeOwned + Readable + Updateable,
+    F: DataFormat<Data = T>,
+{
+    /// Like [`Database::iter_updates`], but also yields when each update was appended. That is
+    /// only recorded with [`DatabaseBuilder::log_timestamps`](crate::DatabaseBuilder::log_timestamps),
+    /// updates appended without it have no timestamp. The updates of a batch share one.
+    pub fn iter_updates_with_timestamps(
+        &self,
+    ) -> Result<impl Iterator<Item = Result<Timestamped<T>>> + '_> {
+        self.iter_version_updates(self.version)
+    }
+
+    /// Decodes the updates recorded in the log of `version`, across all of its segments.
+    pub(crate) fn iter_version_updates(
+        &self,
+        version: u64,
+    ) -> Result<impl Iterator<Item = Result<Timestamped<T>>> + '_> {
+        let mut readers = Vec::new();
+        for (_, filename) in self.log_segments(version)? {
+            if let Some((reader, _)) = self.open_log(&filename)? {
+                readers.push(reader);
+            }
+        }
+        Ok(readers
+            .into_iter()
+            .flat_map(|reader| self.decode_log(reader)))
+    }
+
+    /// Decodes the updates of a whole log segment, which are timestamped if it starts with
+    /// [`TIMESTAMP_MAGIC`]. An error reading it is yielded like one decoding it.
+    pub(crate) fn decode_log<'a>(&'a self, mut reader: Box<dyn BufRead + 'a>) -> LogUpdates<'a, T> {
+        match read_log_header(&mut reader) {
+            Ok((true, _)) => self.decode_timestamped(reader),
+            Ok((false, head)) => Box::new(
+                self.fmt
+                    .deserialize_params_stream(std::io::Cursor::new(head).chain(reader))
+                    .map(|args| args.map(|args| (None, args))),
+            ),
+            Err(e) => Box::new(std::iter::once(Err(e))),
+        }
+    }
+
+    /// Decodes the frames of a timestamped segment after its magic. A frame that cannot be decoded
+    /// ends the updates like a truncated one.
+    pub(crate) fn decode_timestamped<'a>(
+        &'a self,
+        mut reader: impl BufRead + 'a,
+    ) -> LogUpdates<'a, T> {
+        let mut pending = VecDeque::new();
+        let mut done = false;
+        Box::new(std::iter::from_fn(move || loop {
+            if let Some(update) = pending.pop_front() {
+                return Some(Ok(update));
+            }
+            if done {
+                return None;
+            }
+            let (nanos, payload) = match read_frame(&mut reader) {
+                Ok(Some(frame)) => frame,
+                Ok(None) => return None,
+                Err(e) => {
+                    done = true;
+                    return Some(Err(e));
+                }
+            };
+            let timestamp = UNIX_EPOCH + Duration::from_nanos(nanos);
+            match self.fmt.deserialize_log(&payload) {
+                Ok((updates, false)) => {
+                    pending.extend(updates.into_iter().map(|args| (Some(timestamp), args)));
+                }
+                Ok((_, true)) => {
+                    done = true;
+                    return Some(Err(BjwError::Corrupt(
+                        "Found a timestamped update that cannot be decoded".into(),
+                    )));
+                }
+                Err(e) => {
+                    done = true;
+                    return Some(Err(e));
+                }
+            }
+        }))
+    }
+
+    /// Decodes the updates at the start of `input` like [`DataFormat::deserialize_log_prefix`],
+    /// from the frames after the magic if the segment is `timestamped`. Returns them with how
+    /// many bytes they were read from, so the segment can be read on right after them.
+    pub(crate) fn decode_log_prefix(
+        &self,
+        input: &[u8],
+        timestamped: bool,
+    ) -> Result<(Vec<<T as Updateable>::Args>, usize)> {
+        if !timestamped {
+            return self.fmt.deserialize_log_prefix(input);
+        }
+        let mut updates = Vec::new();
+        let mut len = 0;
+        let mut rest = input;
+        // a truncated frame ends the updates like one that cannot be decoded
+        while let Ok(Some((_, payload))) = read_frame(&mut rest) {
+            match self.fmt.deserialize_log(&payload)? {
+                (frame_updates, false) => updates.extend(frame_updates),
+                (_, true) => break,
+            }
+            len = input.len() - rest.len();
+        }
+        Ok((updates, len))
+    }
+
+    /// Whether updates appended to the current segment are timestamped. A new segment is if
+    /// [`DatabaseBuilder::log_timestamps`](crate::DatabaseBuilder::log_timestamps) is set, one
+    /// that was written to already keeps its format.
+    pub(crate) fn segment_timestamped(&mut self, path: &Path) -> Result<bool> {
+        if self.segment_bytes == 0 {
+            self.segment_timestamps = Some(self.options.log_timestamps);
+        }
+        if let Some(timestamped) = self.segment_timestamps {
+            return Ok(timestamped);
+        }
+        let (timestamped, _) = read_log_header(&mut self.backend.open_reader(path)?)?;
+        self.segment_timestamps = Some(timestamped);
+        Ok(timestamped)
+    }
+
+    /// The timestamp of an update appended now. It is never earlier than the previous one of this
+    /// handle, even if the system clock is set back.
+    pub(crate) fn next_timestamp(&mut self) -> u64 {
+        let now = nanos_since_epoch(SystemTime::now());
+        self.last_timestamp = self.last_timestamp.max(now);
+        self.last_timestamp
+    }
+}
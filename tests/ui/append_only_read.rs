@@ -0,0 +1,28 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Counter {
+    count: u64,
+}
+
+#[bjw_db::derive_bjw_db]
+impl Counter {
+    pub fn increment(&mut self) {
+        self.count += 1;
+    }
+
+    pub fn get(&self) -> u64 {
+        self.count
+    }
+}
+
+// an append-only database has no state to read
+fn main() {
+    let mut db = Database::open_append_only("counter", JsonFormat::<Counter>::new()).unwrap();
+    db.append(&CounterUpdateParams::Increment).unwrap();
+    db.read(&CounterReadParams::Get);
+    db.read_all();
+    db.with_data(|counter| counter.count);
+    db.clone_data();
+    db.update(&CounterUpdateParams::Increment).unwrap();
+}
@@ -0,0 +1,27 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+struct Counter {
+    count: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+struct Other {
+    count: u64,
+}
+
+bjw_db::bjw_db_impls! {
+    impl Counter {
+        pub fn increment(&mut self) {
+            self.count += 1;
+        }
+    }
+
+    impl Other {
+        pub fn get(&self, _unit: &str) -> u64 {
+            self.count
+        }
+    }
+}
+
+fn main() {}
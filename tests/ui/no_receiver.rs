@@ -0,0 +1,19 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+struct Counter {
+    count: u64,
+}
+
+#[bjw_db::derive_bjw_db]
+impl Counter {
+    pub fn get(&self, _unit: &str) -> u64 {
+        self.count
+    }
+
+    pub fn starting_at(count: u64) -> Self {
+        Counter { count }
+    }
+}
+
+fn main() {}
@@ -0,0 +1,8 @@
+#[bjw_db::derive_bjw_db]
+impl (u64, u64) {
+    pub fn get(&self, _unit: &str) -> u64 {
+        self.0
+    }
+}
+
+fn main() {}
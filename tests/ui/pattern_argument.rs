@@ -0,0 +1,19 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+struct Counter {
+    count: u64,
+}
+
+#[bjw_db::derive_bjw_db]
+impl Counter {
+    pub fn add(&mut self, (a, b): (u64, u64)) {
+        self.count += a + b;
+    }
+
+    pub fn get(&self, _unit: &str) -> u64 {
+        self.count
+    }
+}
+
+fn main() {}
@@ -0,0 +1,28 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+struct Counter {
+    count: u64,
+}
+
+#[bjw_db::derive_bjw_db]
+impl Counter {
+    pub fn increment(&mut self) {
+        self.count = Self::next_count(self.count);
+    }
+
+    pub fn get(&self, _unit: &str) -> u64 {
+        self.count
+    }
+
+    #[bjw(skip)]
+    fn next_count(count: u64) -> u64 {
+        count + 1
+    }
+}
+
+fn main() {
+    let mut db = CounterDb::open("counter").unwrap();
+    db.increment().unwrap();
+    db.next_count(1);
+}
@@ -0,0 +1,24 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+struct Counter {
+    count: u64,
+}
+
+#[bjw_db::derive_bjw_db]
+#[allow(non_snake_case)]
+impl Counter {
+    pub fn increment(&mut self) {
+        self.count += 1;
+    }
+
+    pub fn get(&self, _unit: &str) -> u64 {
+        self.count
+    }
+
+    pub fn Get(&self, _unit: &str) -> u64 {
+        self.count
+    }
+}
+
+fn main() {}
@@ -0,0 +1,36 @@
+mod outer {
+    pub mod store {
+        use serde::{Deserialize, Serialize};
+
+        #[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+        pub struct Counter {
+            count: u64,
+        }
+
+        #[bjw_db::derive_bjw_db(visibility = "pub(super)")]
+        impl Counter {
+            pub fn increment(&mut self) {
+                self.count += 1;
+            }
+
+            pub fn get(&self, _unit: &str) -> u64 {
+                self.count
+            }
+        }
+    }
+
+    // usable within its visibility, including the enums
+    pub fn count() -> u64 {
+        let mut db = store::CounterDb::open_in_memory().unwrap();
+        db.increment().unwrap();
+        db.update_batch(&[store::CounterUpdateParams::Increment])
+            .unwrap();
+        db.get("")
+    }
+}
+
+fn main() {
+    assert_eq!(outer::count(), 2);
+    outer::store::CounterDb::open_in_memory().unwrap();
+    let _ = outer::store::CounterUpdateParams::Increment;
+}
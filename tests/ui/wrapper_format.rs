@@ -0,0 +1,16 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Counter {
+    count: u64,
+}
+
+// needs a key, which the generated constructors have no way to get
+#[bjw_db::derive_bjw_db(fmt = "Encrypted")]
+impl Counter {
+    pub fn increment(&mut self) {
+        self.count += 1;
+    }
+}
+
+fn main() {}